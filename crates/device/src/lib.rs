@@ -0,0 +1,369 @@
+//! What differs between the readers we target.
+//!
+//! A [`DeviceProfile`] holds everything model-specific: the panel size the
+//! frontend captures pages at, the gray levels the encoder may use, and
+//! where and under what names books go on the card. Supporting another
+//! model means adding a profile to [`Profiles::builtin`] (or loading one
+//! from JSON), not hunting for constants across crates.
+
+use std::path::PathBuf;
+
+use encoder::{EncoderConfig, Levels, Resize};
+use serde::{Deserialize, Serialize};
+
+/// One reader model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    /// Stable identifier used in settings, e.g. `"x4"`.
+    pub id: String,
+    /// Name shown to the user.
+    pub name: String,
+    /// Portrait panel width in pixels; also the capture canvas and XTC page
+    /// width.
+    pub width: u32,
+    /// Portrait panel height in pixels.
+    pub height: u32,
+    /// The most gray levels the firmware renders (High Quality mode).
+    pub levels: Levels,
+    /// Folder on the card books are synced into, `/`-separated; empty for
+    /// the card root.
+    #[serde(default)]
+    pub books_dir: String,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub naming: FileNaming,
+}
+
+/// How books are foldered under [`DeviceProfile::books_dir`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Every book directly in the books folder.
+    #[default]
+    Flat,
+    /// One folder per (first) author.
+    ByAuthor,
+    /// One folder per series, with the series number leading the file name
+    /// so books sort in reading order. Books outside a series stay flat.
+    BySeries,
+}
+
+/// What the layout and naming rules need to know about a book.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BookInfo {
+    pub title: String,
+    pub author: Option<String>,
+    pub series: Option<String>,
+    /// Position within `series`, counting from 1.
+    pub series_index: Option<u32>,
+}
+
+impl BookInfo {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Constraints on book file names on the card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileNaming {
+    /// Longest file name, in characters, extension included.
+    pub max_len: usize,
+    /// Replace characters outside ASCII, for firmware whose file browser
+    /// cannot show them.
+    pub ascii_only: bool,
+}
+
+impl Default for FileNaming {
+    /// FAT32 long-name limits. Neither model's firmware is known to be
+    /// stricter.
+    fn default() -> Self {
+        Self {
+            max_len: 255,
+            ascii_only: false,
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// Xteink X4.
+    pub fn x4() -> Self {
+        Self {
+            id: "x4".into(),
+            name: "Xteink X4".into(),
+            width: 480,
+            height: 800,
+            levels: Levels::Gray4,
+            books_dir: String::new(),
+            layout: Layout::Flat,
+            naming: FileNaming::default(),
+        }
+    }
+
+    /// Xteink X3.
+    pub fn x3() -> Self {
+        Self {
+            id: "x3".into(),
+            name: "Xteink X3".into(),
+            width: 528,
+            height: 792,
+            levels: Levels::Gray4,
+            books_dir: String::new(),
+            layout: Layout::Flat,
+            naming: FileNaming::default(),
+        }
+    }
+
+    /// `(width, height)` of a captured page.
+    pub fn page_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Encoder defaults for this panel: resampling to the page size and
+    /// `levels` when the quality mode allows it, 1-bit otherwise.
+    pub fn encoder_config(&self, high_quality: bool) -> EncoderConfig {
+        EncoderConfig {
+            levels: if high_quality {
+                self.levels
+            } else {
+                Levels::Mono
+            },
+            resize: Some(Resize {
+                width: self.width,
+                height: self.height,
+                filter: Default::default(),
+                fit: Default::default(),
+            }),
+            ..EncoderConfig::default()
+        }
+    }
+
+    /// Where `book` goes on the card under this profile's layout, relative
+    /// to its root. `extension` is the book's (`xtc` or `xtch`).
+    pub fn book_path(&self, book: &BookInfo, extension: &str) -> PathBuf {
+        let mut path: PathBuf = self
+            .books_dir
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+        let mut stem = book.title.clone();
+        match (self.layout, &book.author, &book.series) {
+            (Layout::Flat, ..) => {}
+            (Layout::ByAuthor, author, _) => {
+                let author = author.as_deref().unwrap_or("Unknown Author");
+                path.push(self.naming.folder_name(author));
+            }
+            (Layout::BySeries, _, Some(series)) => {
+                path.push(self.naming.folder_name(series));
+                if let Some(index) = book.series_index {
+                    stem = format!("{index:02} - {stem}");
+                }
+            }
+            (Layout::BySeries, _, None) => {}
+        }
+        path.push(self.naming.file_name(&stem, extension));
+        path
+    }
+}
+
+impl FileNaming {
+    /// A file name for `title` that FAT32 and these rules accept: reserved
+    /// and control characters become `_`, leading dots (which would hide
+    /// the file) and trailing dots and spaces are dropped, and long titles
+    /// are cut to fit.
+    pub fn file_name(&self, title: &str, extension: &str) -> String {
+        let budget = self.max_len.saturating_sub(extension.chars().count() + 1);
+        format!("{}.{extension}", self.clean(title, budget))
+    }
+
+    /// A folder name for `name`, by the same rules as [`Self::file_name`].
+    pub fn folder_name(&self, name: &str) -> String {
+        self.clean(name, self.max_len)
+    }
+
+    fn clean(&self, name: &str, budget: usize) -> String {
+        let cleaned: String = name
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+                c if c.is_control() => '_',
+                c if self.ascii_only && !c.is_ascii() => '_',
+                c => c,
+            })
+            .collect();
+        let cut: String = cleaned
+            .trim_start_matches(['.', ' '])
+            .chars()
+            .take(budget.max(1))
+            .collect();
+        match cut.trim_end_matches(['.', ' ']) {
+            "" => "Untitled".to_owned(),
+            name => name.to_owned(),
+        }
+    }
+}
+
+/// The profiles the app knows about. Never empty: a registry without
+/// profiles fails to deserialize.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ProfileList")]
+pub struct Profiles {
+    profiles: Vec<DeviceProfile>,
+}
+
+/// [`Profiles`] as stored, before it is checked.
+#[derive(Deserialize)]
+struct ProfileList {
+    profiles: Vec<DeviceProfile>,
+}
+
+impl TryFrom<ProfileList> for Profiles {
+    type Error = &'static str;
+
+    fn try_from(list: ProfileList) -> Result<Self, Self::Error> {
+        if list.profiles.is_empty() {
+            return Err("a profile registry needs at least one profile");
+        }
+        Ok(Self {
+            profiles: list.profiles,
+        })
+    }
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Profiles {
+    /// The models supported out of the box; the first is the default.
+    pub fn builtin() -> Self {
+        Self {
+            profiles: vec![DeviceProfile::x4(), DeviceProfile::x3()],
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&DeviceProfile> {
+        self.profiles.iter().find(|p| p.id == id)
+    }
+
+    /// Adds `profile`, replacing any with the same id.
+    pub fn insert(&mut self, profile: DeviceProfile) {
+        match self.profiles.iter_mut().find(|p| p.id == profile.id) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DeviceProfile> {
+        self.profiles.iter()
+    }
+
+    /// The profile used when the user has not picked one.
+    pub fn default_profile(&self) -> &DeviceProfile {
+        &self.profiles[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_profiles_match_the_presets() {
+        let profiles = Profiles::builtin();
+        assert_eq!(profiles.default_profile().page_size(), (480, 800));
+        assert_eq!(profiles.get("x3").unwrap().page_size(), (528, 792));
+        assert!(profiles.get("kindle").is_none());
+
+        let config = profiles.get("x3").unwrap().encoder_config(false);
+        assert_eq!(config.levels, Levels::Mono);
+        assert_eq!(config.resize.map(|r| (r.width, r.height)), Some((528, 792)));
+    }
+
+    #[test]
+    fn profiles_load_from_json_and_replace_by_id() {
+        let json = r#"{"id":"x4","name":"X4 (books folder)","width":480,"height":800,
+            "levels":"gray4","books_dir":"Books/e-inky","naming":{"ascii_only":true}}"#;
+        let profile: DeviceProfile = serde_json::from_str(json).unwrap();
+        assert_eq!(profile.naming.max_len, 255);
+
+        let mut profiles = Profiles::builtin();
+        profiles.insert(profile);
+        assert_eq!(profiles.iter().count(), 2);
+        assert_eq!(
+            profiles
+                .get("x4")
+                .unwrap()
+                .book_path(&BookInfo::new("Les Misérables"), "xtch"),
+            PathBuf::from("Books/e-inky/Les Mis_rables.xtch")
+        );
+    }
+
+    #[test]
+    fn empty_profile_registries_are_refused() {
+        let err = serde_json::from_str::<Profiles>(r#"{"profiles":[]}"#).unwrap_err();
+        assert!(err.to_string().contains("at least one profile"), "{err}");
+
+        let json = serde_json::to_string(&Profiles::builtin()).unwrap();
+        let profiles: Profiles = serde_json::from_str(&json).unwrap();
+        assert_eq!(profiles.default_profile().id, "x4");
+    }
+
+    #[test]
+    fn layouts_folder_books_by_author_or_series() {
+        let book = BookInfo {
+            title: "Dune Messiah".into(),
+            author: Some("Frank Herbert".into()),
+            series: Some("Dune".into()),
+            series_index: Some(2),
+        };
+        let with = |layout| DeviceProfile {
+            books_dir: "Books".into(),
+            layout,
+            ..DeviceProfile::x4()
+        };
+        let path = |layout, book: &BookInfo| with(layout).book_path(book, "xtc");
+        assert_eq!(
+            path(Layout::Flat, &book),
+            PathBuf::from("Books/Dune Messiah.xtc")
+        );
+        assert_eq!(
+            path(Layout::ByAuthor, &book),
+            PathBuf::from("Books/Frank Herbert/Dune Messiah.xtc")
+        );
+        assert_eq!(
+            path(Layout::BySeries, &book),
+            PathBuf::from("Books/Dune/02 - Dune Messiah.xtc")
+        );
+        let standalone = BookInfo::new("Emma");
+        assert_eq!(
+            path(Layout::ByAuthor, &standalone),
+            PathBuf::from("Books/Unknown Author/Emma.xtc")
+        );
+        assert_eq!(
+            path(Layout::BySeries, &standalone),
+            PathBuf::from("Books/Emma.xtc")
+        );
+    }
+
+    #[test]
+    fn file_names_are_safe_and_bounded() {
+        let naming = FileNaming {
+            max_len: 12,
+            ascii_only: false,
+        };
+        assert_eq!(naming.file_name("Who? What: Why", "xtc"), "Who_ Wha.xtc");
+        assert_eq!(naming.file_name(".hidden", "xtc"), "hidden.xtc");
+        assert_eq!(naming.file_name(" ... ", "xtc"), "Untitled.xtc");
+        assert_eq!(FileNaming::default().file_name("Dune", "xtc"), "Dune.xtc");
+        assert_eq!(naming.folder_name("AC/DC: Live"), "AC_DC_ Live");
+    }
+}
@@ -0,0 +1,35 @@
+//! Per-algorithm encode benchmarks at the X4 panel size.
+//!
+//! Run with `cargo bench -p encoder --features bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use encoder::throughput::{synthetic_page, DITHER_MODES};
+use encoder::{encode_buffer, DitherConfig, EncoderConfig, Levels};
+
+const WIDTH: u32 = 480;
+const HEIGHT: u32 = 800;
+
+fn dither(c: &mut Criterion) {
+    let page = synthetic_page(WIDTH, HEIGHT);
+    for levels in [Levels::Mono, Levels::Gray4] {
+        let mut group = c.benchmark_group(format!("dither/{}", levels.count()));
+        group.throughput(Throughput::Elements(u64::from(WIDTH * HEIGHT)));
+        for (name, algorithm) in DITHER_MODES {
+            let config = EncoderConfig {
+                levels,
+                dither: DitherConfig {
+                    algorithm,
+                    ..DitherConfig::default()
+                },
+                ..EncoderConfig::default()
+            };
+            group.bench_function(name, |b| {
+                b.iter(|| encode_buffer(&config, &page, WIDTH, HEIGHT).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, dither);
+criterion_main!(benches);
@@ -0,0 +1,141 @@
+//! Regenerates the embedded blue-noise threshold masks.
+//!
+//! Uses Ulichney's void-and-cluster method on a torus with a fixed seed, so
+//! the output is reproducible. Run from the workspace root:
+//!
+//! ```text
+//! cargo run --release -p encoder --example blue_noise_masks
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+const SIGMA: f64 = 1.5;
+
+fn main() {
+    let out_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/masks");
+    for size in [64usize, 128] {
+        let mask = void_and_cluster(size);
+        let path = out_dir.join(format!("blue_noise_{size}.bin"));
+        fs::write(&path, mask).expect("write mask");
+        println!("wrote {}", path.display());
+    }
+}
+
+/// Returns `size * size` thresholds in 0..=255, row-major.
+fn void_and_cluster(size: usize) -> Vec<u8> {
+    let n = size * size;
+    let kernel = gaussian_kernel(size);
+    let mut field = Field {
+        size,
+        kernel,
+        ones: vec![false; n],
+        energy: vec![0.0; n],
+    };
+
+    // Deterministic ~10% seed pattern.
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    let mut seeded = 0;
+    while seeded < n / 10 {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        let p = (rng % n as u64) as usize;
+        if !field.ones[p] {
+            field.toggle(p);
+            seeded += 1;
+        }
+    }
+
+    // Spread the seed pattern out until it is stable.
+    loop {
+        let cluster = field.tightest_cluster();
+        field.toggle(cluster);
+        let void = field.largest_void();
+        if void == cluster {
+            field.toggle(cluster);
+            break;
+        }
+        field.toggle(void);
+    }
+
+    let mut rank = vec![0usize; n];
+    let prototype = field.ones.clone();
+    let prototype_energy = field.energy.clone();
+
+    // Phase 1: rank the seed points by removing tightest clusters.
+    let mut count = prototype.iter().filter(|&&b| b).count();
+    while count > 0 {
+        let cluster = field.tightest_cluster();
+        field.toggle(cluster);
+        count -= 1;
+        rank[cluster] = count;
+    }
+
+    // Phases 2 and 3: fill the largest voids until the field is full.
+    field.ones = prototype;
+    field.energy = prototype_energy;
+    let mut count = field.ones.iter().filter(|&&b| b).count();
+    while count < n {
+        let void = field.largest_void();
+        field.toggle(void);
+        rank[void] = count;
+        count += 1;
+    }
+
+    rank.iter().map(|&r| (r * 256 / n) as u8).collect()
+}
+
+fn gaussian_kernel(size: usize) -> Vec<f64> {
+    let mut kernel = vec![0.0; size * size];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f64;
+            let wy = dy.min(size - dy) as f64;
+            kernel[dy * size + dx] = (-(wx * wx + wy * wy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+    kernel
+}
+
+struct Field {
+    size: usize,
+    kernel: Vec<f64>,
+    ones: Vec<bool>,
+    energy: Vec<f64>,
+}
+
+impl Field {
+    fn toggle(&mut self, p: usize) {
+        let sign = if self.ones[p] { -1.0 } else { 1.0 };
+        self.ones[p] = !self.ones[p];
+        let (px, py) = (p % self.size, p / self.size);
+        for y in 0..self.size {
+            let dy = (y + self.size - py) % self.size;
+            for x in 0..self.size {
+                let dx = (x + self.size - px) % self.size;
+                self.energy[y * self.size + x] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        let mut best = None;
+        for (p, &e) in self.energy.iter().enumerate() {
+            if self.ones[p] && best.is_none_or(|(_, b)| e > b) {
+                best = Some((p, e));
+            }
+        }
+        best.expect("no set pixels").0
+    }
+
+    fn largest_void(&self) -> usize {
+        let mut best = None;
+        for (p, &e) in self.energy.iter().enumerate() {
+            if !self.ones[p] && best.is_none_or(|(_, b)| e < b) {
+                best = Some((p, e));
+            }
+        }
+        best.expect("no empty pixels").0
+    }
+}
@@ -0,0 +1,216 @@
+//! Encoding many pages at once.
+//!
+//! Capture is sequential, but each page encodes independently, so whole
+//! books are spread across all cores.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rayon::prelude::*;
+
+use crate::{EncodeError, EncodedFrame, EncoderConfig, EncoderPipeline};
+
+/// A borrowed grayscale8 page awaiting encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrayFrame<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub data: &'a [u8],
+}
+
+/// Encodes `pages` in parallel with one shared pipeline.
+///
+/// Results come back in input order. The first failing page aborts the
+/// batch and its error is returned.
+pub fn encode_pages<'a, I>(
+    config: &EncoderConfig,
+    pages: I,
+) -> Result<Vec<EncodedFrame>, EncodeError>
+where
+    I: IntoParallelIterator<Item = GrayFrame<'a>>,
+{
+    let pipeline = EncoderPipeline::from_config(config)?;
+    pages
+        .into_par_iter()
+        .map(|page| pipeline.run(page.data, page.width, page.height))
+        .collect()
+}
+
+/// Reported after each page of a batch finishes encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeProgress {
+    /// Pages encoded so far; counts up by one per report.
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// Shared flag that stops a running batch.
+///
+/// Clones share the flag, so the UI keeps one clone and hands another to the
+/// worker running [`encode_pages_with_progress`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// [`encode_pages`] with progress reporting and cancellation.
+///
+/// `progress` is called once per finished page, never concurrently, with a
+/// strictly increasing `completed` count. Pages not yet started when
+/// `cancel` fires are skipped and the batch returns
+/// [`EncodeError::Cancelled`].
+pub fn encode_pages_with_progress<'a, I, F>(
+    config: &EncoderConfig,
+    pages: I,
+    progress: F,
+    cancel: &CancelToken,
+) -> Result<Vec<EncodedFrame>, EncodeError>
+where
+    I: IntoParallelIterator<Item = GrayFrame<'a>>,
+    I::Iter: IndexedParallelIterator,
+    F: FnMut(EncodeProgress) + Send,
+{
+    let pipeline = EncoderPipeline::from_config(config)?;
+    let pages = pages.into_par_iter();
+    let total = pages.len();
+    let reporter = Mutex::new((0, progress));
+    let frames = pages
+        .map(|page| {
+            if cancel.is_cancelled() {
+                return Err(EncodeError::Cancelled);
+            }
+            let frame = pipeline.run(page.data, page.width, page.height)?;
+            let mut reporter = reporter.lock().unwrap_or_else(|e| e.into_inner());
+            let (completed, report) = &mut *reporter;
+            *completed += 1;
+            report(EncodeProgress {
+                completed: *completed,
+                total,
+            });
+            Ok(frame)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if cancel.is_cancelled() {
+        return Err(EncodeError::Cancelled);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_buffer;
+
+    #[test]
+    fn matches_sequential_encoding_in_order() {
+        let pages: Vec<Vec<u8>> = (0..12u32)
+            .map(|p| {
+                (0..40 * 60)
+                    .map(|i| ((i * 7 + p * 31) % 256) as u8)
+                    .collect()
+            })
+            .collect();
+        let config = EncoderConfig::default();
+        let frames: Vec<GrayFrame> = pages
+            .iter()
+            .map(|data| GrayFrame {
+                width: 40,
+                height: 60,
+                data,
+            })
+            .collect();
+
+        let parallel = encode_pages(&config, frames).unwrap();
+        let sequential: Vec<EncodedFrame> = pages
+            .iter()
+            .map(|data| encode_buffer(&config, data, 40, 60).unwrap())
+            .collect();
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn propagates_page_errors() {
+        let good = vec![0u8; 16];
+        let bad = vec![0u8; 3];
+        let frames = vec![
+            GrayFrame {
+                width: 4,
+                height: 4,
+                data: &good,
+            },
+            GrayFrame {
+                width: 4,
+                height: 4,
+                data: &bad,
+            },
+        ];
+        assert_eq!(
+            encode_pages(&EncoderConfig::default(), frames),
+            Err(EncodeError::BufferSize {
+                expected: 16,
+                actual: 3
+            })
+        );
+    }
+
+    fn blank_pages(count: usize) -> Vec<Vec<u8>> {
+        vec![vec![255u8; 16 * 16]; count]
+    }
+
+    fn frames(pages: &[Vec<u8>]) -> Vec<GrayFrame<'_>> {
+        pages
+            .iter()
+            .map(|data| GrayFrame {
+                width: 16,
+                height: 16,
+                data,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_progress_for_every_page() {
+        let pages = blank_pages(9);
+        let mut reports = Vec::new();
+        let encoded = encode_pages_with_progress(
+            &EncoderConfig::default(),
+            frames(&pages),
+            |p| reports.push(p),
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!(encoded.len(), 9);
+        let completed: Vec<usize> = reports.iter().map(|p| p.completed).collect();
+        assert_eq!(completed, (1..=9).collect::<Vec<_>>());
+        assert!(reports.iter().all(|p| p.total == 9));
+    }
+
+    #[test]
+    fn cancellation_aborts_the_batch() {
+        let pages = blank_pages(64);
+        let cancel = CancelToken::new();
+        let result = encode_pages_with_progress(
+            &EncoderConfig::default(),
+            frames(&pages),
+            |p| {
+                if p.completed == 2 {
+                    cancel.cancel();
+                }
+            },
+            &cancel,
+        );
+        assert_eq!(result, Err(EncodeError::Cancelled));
+    }
+}
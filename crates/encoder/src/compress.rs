@@ -0,0 +1,179 @@
+//! Optional compression of encoded page payloads.
+//!
+//! This is for storage and transfer of encoded frames (slow USB links, large
+//! libraries). The device reads raw XTG/XTH payloads, so compressed frames
+//! must be decompressed before they are written into a container.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Codec applied to an encoded frame's data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd at the given level (1..=22). Needs the `zstd` feature.
+    Zstd { level: i32 },
+    /// LZ4 block format with the size prepended. Needs the `lz4` feature.
+    Lz4,
+}
+
+impl Compression {
+    /// Whether this build can apply the codec.
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Zstd { .. } => cfg!(feature = "zstd"),
+            Compression::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    pub(crate) fn validate(self) -> Result<(), EncodeError> {
+        if !self.is_available() {
+            return Err(EncodeError::CompressionUnavailable(self));
+        }
+        if let Compression::Zstd { level } = self {
+            if !(1..=22).contains(&level) {
+                return Err(EncodeError::InvalidOutput(
+                    "zstd level must be within 1..=22",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn compress(self, data: Vec<u8>) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            Compression::None => Ok(data),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => zstd::bulk::compress(&data, level)
+                .map_err(|e| EncodeError::Compression(e.to_string())),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(&data)),
+            #[allow(unreachable_patterns)]
+            other => Err(EncodeError::CompressionUnavailable(other)),
+        }
+    }
+
+    /// Reverses [`Compression::compress`]. `expected_len` is the size of the
+    /// uncompressed payload and bounds the output.
+    pub fn decompress(self, data: &[u8], expected_len: usize) -> Result<Vec<u8>, EncodeError> {
+        let out = match self {
+            Compression::None => data.to_vec(),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => zstd::bulk::decompress(data, expected_len)
+                .map_err(|e| EncodeError::Compression(e.to_string()))?,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                // Check the prepended size before trusting it with an
+                // allocation.
+                let prefix = data.get(..4).ok_or_else(|| {
+                    EncodeError::Compression("lz4 frame is missing its size".into())
+                })?;
+                let size = u32::from_le_bytes(prefix.try_into().expect("four bytes")) as usize;
+                if size != expected_len {
+                    return Err(EncodeError::BufferSize {
+                        expected: expected_len,
+                        actual: size,
+                    });
+                }
+                lz4_flex::decompress(&data[4..], expected_len)
+                    .map_err(|e| EncodeError::Compression(e.to_string()))?
+            }
+            #[allow(unreachable_patterns)]
+            other => return Err(EncodeError::CompressionUnavailable(other)),
+        };
+        if out.len() != expected_len {
+            return Err(EncodeError::BufferSize {
+                expected: expected_len,
+                actual: out.len(),
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        (0..4096)
+            .map(|i| if i % 97 < 60 { 0xFF } else { 0x00 })
+            .collect()
+    }
+
+    #[test]
+    fn none_is_passthrough() {
+        let data = sample();
+        let packed = Compression::None.compress(data.clone()).unwrap();
+        assert_eq!(packed, data);
+        assert_eq!(
+            Compression::None.decompress(&packed, data.len()).unwrap(),
+            data
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trips() {
+        let codec = Compression::Zstd { level: 3 };
+        let data = sample();
+        let packed = codec.compress(data.clone()).unwrap();
+        assert!(packed.len() < data.len());
+        assert_eq!(codec.decompress(&packed, data.len()).unwrap(), data);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_round_trips() {
+        let data = sample();
+        let packed = Compression::Lz4.compress(data.clone()).unwrap();
+        assert!(packed.len() < data.len());
+        assert_eq!(
+            Compression::Lz4.decompress(&packed, data.len()).unwrap(),
+            data
+        );
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_rejects_sizes_other_than_expected() {
+        let mut packed = Compression::Lz4.compress(sample()).unwrap();
+        packed[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Compression::Lz4.decompress(&packed, 4096),
+            Err(EncodeError::BufferSize {
+                expected: 4096,
+                actual: u32::MAX as usize,
+            })
+        );
+        assert!(matches!(
+            Compression::Lz4.decompress(&packed[..3], 4096),
+            Err(EncodeError::Compression(_))
+        ));
+    }
+
+    #[test]
+    fn reports_codecs_missing_from_build() {
+        for codec in [Compression::Zstd { level: 3 }, Compression::Lz4] {
+            if !codec.is_available() {
+                assert_eq!(
+                    codec.validate(),
+                    Err(EncodeError::CompressionUnavailable(codec))
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn rejects_out_of_range_zstd_level() {
+        assert!(matches!(
+            Compression::Zstd { level: 40 }.validate(),
+            Err(EncodeError::InvalidOutput(_))
+        ));
+    }
+}
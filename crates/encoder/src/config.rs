@@ -0,0 +1,269 @@
+//! The serializable encoder configuration and its migrations.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Compression, Crop, DitherMode, EncodeError, Levels, OutputFormat, Resize, Segmentation,
+    SharpenConfig, ToneConfig,
+};
+
+/// Schema version written by [`EncoderConfig`]'s serializer.
+///
+/// Version 1 had top-level `dither` (the algorithm), `dither_percent` and
+/// `seed` fields; version 2 groups them under `dither` as a
+/// [`DitherConfig`]. Older configs are migrated on load.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Settings applied when reducing a captured frame.
+///
+/// Serializable so presets can be stored in the library and round-tripped by
+/// the settings UI; missing fields take their defaults. Serialized configs
+/// carry a `version` field (see [`CONFIG_VERSION`]).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ConfigRepr", into = "ConfigRepr")]
+pub struct EncoderConfig {
+    pub levels: Levels,
+    /// Optional margin crop, applied first so the tone curve and resize only
+    /// see page content.
+    pub crop: Option<Crop>,
+    /// Tone curve, applied after cropping.
+    pub tone: ToneConfig,
+    /// Optional unsharp mask applied after the tone curve.
+    pub sharpen: Option<SharpenConfig>,
+    /// Optional resampling to the target size, applied after sharpening.
+    /// Without it the frame must already be at the device resolution.
+    pub resize: Option<Resize>,
+    pub dither: DitherConfig,
+    /// Optional text/image segmentation: text tiles are hard-thresholded and
+    /// only image tiles are dithered.
+    pub segmentation: Option<Segmentation>,
+    /// Pixel layout of the returned buffer.
+    pub output: OutputFormat,
+    /// Swap dark and light levels in the output (a negative image).
+    pub invert_output: bool,
+    /// Optional compression of the final buffer.
+    pub compression: Compression,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            levels: Levels::Gray4,
+            crop: None,
+            tone: ToneConfig::default(),
+            sharpen: None,
+            resize: None,
+            dither: DitherConfig::default(),
+            segmentation: None,
+            output: OutputFormat::Gray8,
+            invert_output: false,
+            compression: Compression::None,
+        }
+    }
+}
+
+/// Dithering algorithm and its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DitherConfig {
+    pub algorithm: DitherMode,
+    /// 0.0 = plain nearest-level quantization, 1.0 = full error diffusion /
+    /// full mask amplitude.
+    pub strength: f32,
+    /// Seed for algorithms with a random component. Blue noise uses it to
+    /// place the mask tiling, which decorrelates the pattern between pages
+    /// without losing its spectrum. The same seed always gives the same
+    /// bytes; error diffusion ignores it.
+    pub seed: u64,
+}
+
+impl Default for DitherConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: DitherMode::default(),
+            strength: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+impl DitherConfig {
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        if !(self.strength.is_finite() && (0.0..=1.0).contains(&self.strength)) {
+            return Err(EncodeError::InvalidDither(
+                "strength must be within 0.0..=1.0",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Strength in 1/1024 steps, the fixed-point unit the dither kernels use.
+    pub(crate) fn strength_q10(&self) -> i32 {
+        (self.strength.clamp(0.0, 1.0) * 1024.0).round() as i32
+    }
+}
+
+/// On-disk shape of [`EncoderConfig`], including the version 1 fields.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigRepr {
+    version: u32,
+    levels: Levels,
+    crop: Option<Crop>,
+    tone: ToneConfig,
+    sharpen: Option<SharpenConfig>,
+    resize: Option<Resize>,
+    dither: DitherRepr,
+    segmentation: Option<Segmentation>,
+    output: OutputFormat,
+    invert_output: bool,
+    compression: Compression,
+    #[serde(skip_serializing)]
+    dither_percent: Option<u8>,
+    #[serde(skip_serializing)]
+    seed: Option<u64>,
+}
+
+/// Version 1 stored the bare algorithm under `dither`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum DitherRepr {
+    Legacy(DitherMode),
+    Current(DitherConfig),
+}
+
+impl Default for ConfigRepr {
+    fn default() -> Self {
+        EncoderConfig::default().into()
+    }
+}
+
+impl From<EncoderConfig> for ConfigRepr {
+    fn from(config: EncoderConfig) -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            levels: config.levels,
+            crop: config.crop,
+            tone: config.tone,
+            sharpen: config.sharpen,
+            resize: config.resize,
+            dither: DitherRepr::Current(config.dither),
+            segmentation: config.segmentation,
+            output: config.output,
+            invert_output: config.invert_output,
+            compression: config.compression,
+            dither_percent: None,
+            seed: None,
+        }
+    }
+}
+
+impl TryFrom<ConfigRepr> for EncoderConfig {
+    type Error = EncodeError;
+
+    fn try_from(repr: ConfigRepr) -> Result<Self, Self::Error> {
+        if repr.version > CONFIG_VERSION {
+            return Err(EncodeError::UnsupportedConfigVersion(repr.version));
+        }
+        let mut dither = match repr.dither {
+            DitherRepr::Legacy(algorithm) => DitherConfig {
+                algorithm,
+                ..DitherConfig::default()
+            },
+            DitherRepr::Current(dither) => dither,
+        };
+        if let Some(percent) = repr.dither_percent {
+            dither.strength = f32::from(percent.min(100)) / 100.0;
+        }
+        if let Some(seed) = repr.seed {
+            dither.seed = seed;
+        }
+        Ok(Self {
+            levels: repr.levels,
+            crop: repr.crop,
+            tone: repr.tone,
+            sharpen: repr.sharpen,
+            resize: repr.resize,
+            dither,
+            segmentation: repr.segmentation,
+            output: repr.output,
+            invert_output: repr.invert_output,
+            compression: repr.compression,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitOrder, Kernel, LevelTable, MaskSize, PackedBits, Polarity};
+
+    #[test]
+    fn round_trips_through_json() {
+        let config = EncoderConfig {
+            levels: Levels::Table(LevelTable::new(&[0, 60, 150, 255]).unwrap()),
+            dither: DitherConfig {
+                algorithm: DitherMode::BlueNoise(MaskSize::Size128),
+                strength: 0.35,
+                seed: 9,
+            },
+            sharpen: Some(SharpenConfig::default()),
+            output: OutputFormat::Packed {
+                bits: PackedBits::Two,
+                order: BitOrder::LsbFirst,
+                polarity: Polarity::ZeroIsBlack,
+            },
+            ..EncoderConfig::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""version":2"#));
+        assert!(!json.contains("dither_percent"));
+        let back: EncoderConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, config);
+    }
+
+    #[test]
+    fn fills_missing_fields_with_defaults() {
+        let config: EncoderConfig =
+            serde_json::from_str(r#"{"levels":"mono","dither":{"strength":0.4}}"#).unwrap();
+        assert_eq!(config.levels, Levels::Mono);
+        assert_eq!(config.dither.strength, 0.4);
+        assert_eq!(config.dither.algorithm, DitherMode::default());
+        assert_eq!(config.tone, ToneConfig::default());
+    }
+
+    #[test]
+    fn migrates_version_1_fields() {
+        let json = r#"{
+            "levels": "mono",
+            "dither": {"error_diffusion": {"kernel": "atkinson", "serpentine": true}},
+            "dither_percent": 40,
+            "seed": 3
+        }"#;
+        let config: EncoderConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            config.dither,
+            DitherConfig {
+                algorithm: DitherMode::ErrorDiffusion {
+                    kernel: Kernel::Atkinson,
+                    serpentine: true,
+                },
+                strength: 0.4,
+                seed: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let result: Result<EncoderConfig, _> = serde_json::from_str(r#"{"version":3}"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_level_table() {
+        let result: Result<EncoderConfig, _> =
+            serde_json::from_str(r#"{"levels":{"table":[0,200,100,255]}}"#);
+        assert!(result.is_err());
+    }
+}
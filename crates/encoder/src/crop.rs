@@ -0,0 +1,193 @@
+//! Margin cropping before resize.
+//!
+//! Scanned comics and PDFs rendered at page size carry wide white margins
+//! that waste the 480x800 panel. Cropping them before the resize stage lets
+//! the content fill the screen.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// A rectangle in source-frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Cropping stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Crop {
+    /// Trim uniform white margins. Rows and columns where every pixel is at
+    /// least `threshold` count as margin; `padding` pixels of margin are kept
+    /// around the detected content. Blank pages are left uncropped.
+    Auto { threshold: u8, padding: u32 },
+    /// Crop to a fixed rectangle, e.g. one picked by the user for a whole
+    /// book. The rectangle must lie inside every frame it is applied to.
+    Manual(CropRect),
+}
+
+impl Default for Crop {
+    fn default() -> Self {
+        Crop::Auto {
+            threshold: 240,
+            padding: 8,
+        }
+    }
+}
+
+impl Crop {
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        match self {
+            Crop::Auto { .. } => Ok(()),
+            Crop::Manual(rect) if rect.width == 0 || rect.height == 0 => {
+                Err(EncodeError::InvalidCrop("crop rect must not be empty"))
+            }
+            Crop::Manual(_) => Ok(()),
+        }
+    }
+
+    /// The rectangle this stage keeps from a `width` x `height` frame.
+    pub fn rect(&self, gray: &[u8], width: u32, height: u32) -> Result<CropRect, EncodeError> {
+        match *self {
+            Crop::Auto { threshold, padding } => {
+                Ok(content_bounds(gray, width, height, threshold, padding))
+            }
+            Crop::Manual(rect) => {
+                let fits = u64::from(rect.x) + u64::from(rect.width) <= u64::from(width)
+                    && u64::from(rect.y) + u64::from(rect.height) <= u64::from(height);
+                if fits {
+                    Ok(rect)
+                } else {
+                    Err(EncodeError::InvalidCrop("crop rect exceeds the frame"))
+                }
+            }
+        }
+    }
+
+    /// Crops a `width` x `height` frame, returning the new buffer and size.
+    pub(crate) fn apply(
+        &self,
+        gray: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<(Vec<u8>, CropRect), EncodeError> {
+        let rect = self.rect(gray, width, height)?;
+        let (x, w) = (rect.x as usize, rect.width as usize);
+        let out = gray
+            .chunks_exact(width as usize)
+            .skip(rect.y as usize)
+            .take(rect.height as usize)
+            .flat_map(|row| &row[x..x + w])
+            .copied()
+            .collect();
+        Ok((out, rect))
+    }
+}
+
+fn content_bounds(gray: &[u8], width: u32, height: u32, threshold: u8, padding: u32) -> CropRect {
+    let (mut left, mut right) = (width, 0);
+    let (mut top, mut bottom) = (height, 0);
+    for (y, row) in gray.chunks_exact(width as usize).enumerate() {
+        let Some(first) = row.iter().position(|&px| px < threshold) else {
+            continue;
+        };
+        let last = row.iter().rposition(|&px| px < threshold).unwrap_or(first);
+        left = left.min(first as u32);
+        right = right.max(last as u32 + 1);
+        top = top.min(y as u32);
+        bottom = y as u32 + 1;
+    }
+    if left >= right {
+        return CropRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+    }
+
+    let x = left.saturating_sub(padding);
+    let y = top.saturating_sub(padding);
+    CropRect {
+        x,
+        y,
+        width: right.saturating_add(padding).min(width) - x,
+        height: bottom.saturating_add(padding).min(height) - y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20x10 white frame with a black block covering x 5..9, y 2..6.
+    fn framed_block() -> Vec<u8> {
+        (0..20 * 10)
+            .map(|i| {
+                let (x, y) = (i % 20, i / 20);
+                if (5..9).contains(&x) && (2..6).contains(&y) {
+                    0
+                } else {
+                    255
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn auto_crop_finds_content_with_padding() {
+        let gray = framed_block();
+        let tight = Crop::Auto {
+            threshold: 240,
+            padding: 0,
+        };
+        assert_eq!(
+            tight.rect(&gray, 20, 10).unwrap(),
+            CropRect {
+                x: 5,
+                y: 2,
+                width: 4,
+                height: 4
+            }
+        );
+        let padded = Crop::Auto {
+            threshold: 240,
+            padding: 3,
+        };
+        assert_eq!(
+            padded.rect(&gray, 20, 10).unwrap(),
+            CropRect {
+                x: 2,
+                y: 0,
+                width: 10,
+                height: 9
+            }
+        );
+        let (out, _) = tight.apply(&gray, 20, 10).unwrap();
+        assert_eq!(out, vec![0; 16]);
+    }
+
+    #[test]
+    fn auto_crop_keeps_blank_page() {
+        let rect = Crop::default().rect(&[255; 6 * 4], 6, 4).unwrap();
+        assert_eq!((rect.width, rect.height), (6, 4));
+    }
+
+    #[test]
+    fn manual_crop_must_fit_the_frame() {
+        let crop = Crop::Manual(CropRect {
+            x: 15,
+            y: 0,
+            width: 6,
+            height: 10,
+        });
+        assert_eq!(
+            crop.rect(&framed_block(), 20, 10),
+            Err(EncodeError::InvalidCrop("crop rect exceeds the frame"))
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Frame differencing for partial e-ink refreshes.
+//!
+//! Compares two encoded frames and reports the regions that changed, so
+//! overlays such as a progress bar can be pushed with a partial update
+//! instead of a full-page flash.
+
+use crate::{pack, Compression, EncodeError, EncodedFrame, LevelTable, OutputFormat};
+
+/// Side of the square tiles frames are compared in. A multiple of 8 keeps
+/// every rectangle byte-aligned for all packed formats.
+const TILE: u32 = 8;
+
+/// A changed area in pixels. `x` and `width` are multiples of 8 except where
+/// clipped by the frame's right edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Returns the regions where `next` differs from `prev`, merged into as few
+/// rectangles as the tile grid allows. Empty when the frames are identical.
+///
+/// Both frames must share size and format; compressed frames are
+/// decompressed first.
+pub fn diff_frames(
+    prev: &EncodedFrame,
+    next: &EncodedFrame,
+) -> Result<Vec<DirtyRect>, EncodeError> {
+    if (prev.width, prev.height) != (next.width, next.height) {
+        return Err(EncodeError::InvalidDimensions {
+            width: next.width,
+            height: next.height,
+        });
+    }
+    if prev.format != next.format {
+        return Err(EncodeError::InvalidOutput("frames use different formats"));
+    }
+    let (width, height) = (next.width, next.height);
+    let a = pack::unpack(
+        &prev.pixels()?,
+        width as usize,
+        height as usize,
+        prev.format,
+    );
+    let b = pack::unpack(
+        &next.pixels()?,
+        width as usize,
+        height as usize,
+        next.format,
+    );
+
+    let cols = width.div_ceil(TILE);
+    let rows = height.div_ceil(TILE);
+    let mut dirty = vec![false; (cols * rows) as usize];
+    for (i, (pa, pb)) in a.iter().zip(&b).enumerate() {
+        if pa != pb {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            dirty[((y / TILE) * cols + x / TILE) as usize] = true;
+        }
+    }
+
+    // Horizontal runs per tile row, extended downwards while a run with the
+    // same columns continues on the next row.
+    let mut open: Vec<(u32, u32, u32)> = Vec::new(); // (col0, col1, row0)
+    let mut rects = Vec::new();
+    for row in 0..=rows {
+        let mut runs = Vec::new();
+        if row < rows {
+            let mut col = 0;
+            while col < cols {
+                if dirty[(row * cols + col) as usize] {
+                    let start = col;
+                    while col < cols && dirty[(row * cols + col) as usize] {
+                        col += 1;
+                    }
+                    runs.push((start, col));
+                } else {
+                    col += 1;
+                }
+            }
+        }
+        let mut still_open = Vec::new();
+        for (c0, c1, r0) in open.drain(..) {
+            if let Some(i) = runs.iter().position(|&r| r == (c0, c1)) {
+                runs.remove(i);
+                still_open.push((c0, c1, r0));
+            } else {
+                rects.push(tile_rect(c0, c1, r0, row, width, height));
+            }
+        }
+        still_open.extend(runs.into_iter().map(|(c0, c1)| (c0, c1, row)));
+        open = still_open;
+    }
+    rects.sort_by_key(|r| (r.y, r.x));
+    Ok(rects)
+}
+
+fn tile_rect(c0: u32, c1: u32, r0: u32, r1: u32, width: u32, height: u32) -> DirtyRect {
+    let x = c0 * TILE;
+    let y = r0 * TILE;
+    DirtyRect {
+        x,
+        y,
+        width: (c1 * TILE).min(width) - x,
+        height: (r1 * TILE).min(height) - y,
+    }
+}
+
+/// Copies `rect` out of `frame` as a standalone frame in the same format
+/// (uncompressed), ready to send as a partial update.
+pub fn extract_region(frame: &EncodedFrame, rect: DirtyRect) -> Result<EncodedFrame, EncodeError> {
+    let fits_x = rect
+        .x
+        .checked_add(rect.width)
+        .is_some_and(|r| r <= frame.width);
+    let fits_y = rect
+        .y
+        .checked_add(rect.height)
+        .is_some_and(|b| b <= frame.height);
+    if rect.width == 0 || rect.height == 0 || !fits_x || !fits_y {
+        return Err(EncodeError::InvalidDimensions {
+            width: rect.width,
+            height: rect.height,
+        });
+    }
+    let (fw, fh) = (frame.width as usize, frame.height as usize);
+    let gray = pack::unpack(&frame.pixels()?, fw, fh, frame.format);
+
+    let (x, w) = (rect.x as usize, rect.width as usize);
+    let mut region = Vec::with_capacity(w * rect.height as usize);
+    for y in rect.y as usize..(rect.y + rect.height) as usize {
+        region.extend_from_slice(&gray[y * fw + x..y * fw + x + w]);
+    }
+
+    let levels = LevelTable::uniform(match frame.format {
+        OutputFormat::Gray8 => 2,
+        OutputFormat::Packed { bits, .. } => 1 << bits.get(),
+    });
+    Ok(EncodedFrame {
+        width: rect.width,
+        height: rect.height,
+        format: frame.format,
+        stride: frame.format.stride(rect.width),
+        compression: Compression::None,
+        data: pack::pack(region, w, &levels, frame.format, false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_buffer, BitOrder, EncoderConfig, Levels, PackedBits, Polarity};
+
+    fn mono_config() -> EncoderConfig {
+        EncoderConfig {
+            levels: Levels::Mono,
+            output: OutputFormat::Packed {
+                bits: PackedBits::One,
+                order: BitOrder::MsbFirst,
+                polarity: Polarity::ZeroIsBlack,
+            },
+            ..EncoderConfig::default()
+        }
+    }
+
+    fn frame_with(width: u32, height: u32, black: &[(u32, u32)]) -> EncodedFrame {
+        let mut gray = vec![255u8; (width * height) as usize];
+        for &(x, y) in black {
+            gray[(y * width + x) as usize] = 0;
+        }
+        encode_buffer(&mono_config(), &gray, width, height).unwrap()
+    }
+
+    #[test]
+    fn identical_frames_have_no_dirty_rects() {
+        let a = frame_with(48, 80, &[(3, 3)]);
+        assert!(diff_frames(&a, &a.clone()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn single_pixel_change_marks_one_tile() {
+        let a = frame_with(48, 80, &[]);
+        let b = frame_with(48, 80, &[(20, 75)]);
+        assert_eq!(
+            diff_frames(&a, &b).unwrap(),
+            vec![DirtyRect {
+                x: 16,
+                y: 72,
+                width: 8,
+                height: 8
+            }]
+        );
+    }
+
+    #[test]
+    fn adjacent_tiles_merge_into_one_rect() {
+        let a = frame_with(48, 80, &[]);
+        // A 2x2 block of tiles at the top-left plus a separate footer change.
+        let b = frame_with(48, 80, &[(0, 0), (9, 0), (0, 9), (9, 9), (40, 79)]);
+        assert_eq!(
+            diff_frames(&a, &b).unwrap(),
+            vec![
+                DirtyRect {
+                    x: 0,
+                    y: 0,
+                    width: 16,
+                    height: 16
+                },
+                DirtyRect {
+                    x: 40,
+                    y: 72,
+                    width: 8,
+                    height: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rects_are_clipped_to_frame() {
+        let a = frame_with(12, 10, &[]);
+        let b = frame_with(12, 10, &[(11, 9)]);
+        assert_eq!(
+            diff_frames(&a, &b).unwrap(),
+            vec![DirtyRect {
+                x: 8,
+                y: 8,
+                width: 4,
+                height: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn extract_region_copies_pixels() {
+        let frame = frame_with(24, 16, &[(9, 2), (15, 5)]);
+        let rect = DirtyRect {
+            x: 8,
+            y: 0,
+            width: 8,
+            height: 8,
+        };
+        let region = extract_region(&frame, rect).unwrap();
+        assert_eq!((region.width, region.height, region.stride), (8, 8, 1));
+        let mut expected = vec![0xFF; 8];
+        expected[2] = 0b1011_1111;
+        expected[5] = 0b1111_1110;
+        assert_eq!(region.data, expected);
+    }
+
+    #[test]
+    fn extract_region_rejects_out_of_bounds() {
+        let frame = frame_with(16, 16, &[]);
+        let rect = DirtyRect {
+            x: 8,
+            y: 8,
+            width: 16,
+            height: 8,
+        };
+        assert!(extract_region(&frame, rect).is_err());
+    }
+}
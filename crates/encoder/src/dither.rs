@@ -0,0 +1,294 @@
+//! Error-diffusion and ordered dithering.
+
+use crate::{Kernel, LevelTable, MaskSize};
+
+static BLUE_NOISE_64: &[u8; 64 * 64] = include_bytes!("masks/blue_noise_64.bin");
+static BLUE_NOISE_128: &[u8; 128 * 128] = include_bytes!("masks/blue_noise_128.bin");
+
+/// One error-diffusion tap: offset from the current pixel and its weight.
+struct Tap {
+    dx: isize,
+    dy: usize,
+    weight: i32,
+}
+
+const fn tap(dx: isize, dy: usize, weight: i32) -> Tap {
+    Tap { dx, dy, weight }
+}
+
+const FLOYD_STEINBERG: &[Tap] = &[tap(1, 0, 7), tap(-1, 1, 3), tap(0, 1, 5), tap(1, 1, 1)];
+// Diffuses only 6/8 of the error, which keeps highlights and shadows clean.
+const ATKINSON: &[Tap] = &[
+    tap(1, 0, 1),
+    tap(2, 0, 1),
+    tap(-1, 1, 1),
+    tap(0, 1, 1),
+    tap(1, 1, 1),
+    tap(0, 2, 1),
+];
+const SIERRA: &[Tap] = &[
+    tap(1, 0, 5),
+    tap(2, 0, 3),
+    tap(-2, 1, 2),
+    tap(-1, 1, 4),
+    tap(0, 1, 5),
+    tap(1, 1, 4),
+    tap(2, 1, 2),
+    tap(-1, 2, 2),
+    tap(0, 2, 3),
+    tap(1, 2, 2),
+];
+const STUCKI: &[Tap] = &[
+    tap(1, 0, 8),
+    tap(2, 0, 4),
+    tap(-2, 1, 2),
+    tap(-1, 1, 4),
+    tap(0, 1, 8),
+    tap(1, 1, 4),
+    tap(2, 1, 2),
+    tap(-2, 2, 1),
+    tap(-1, 2, 2),
+    tap(0, 2, 4),
+    tap(1, 2, 2),
+    tap(2, 2, 1),
+];
+
+impl Kernel {
+    /// Taps (for a left-to-right scan) and the weight divisor.
+    fn taps(self) -> (&'static [Tap], i32) {
+        match self {
+            Kernel::FloydSteinberg => (FLOYD_STEINBERG, 16),
+            Kernel::Atkinson => (ATKINSON, 8),
+            Kernel::Sierra => (SIERRA, 32),
+            Kernel::Stucki => (STUCKI, 42),
+        }
+    }
+}
+
+/// Widest horizontal reach of any kernel, used to pad the error rows.
+const PAD: usize = 2;
+/// Rows of error kept in flight (current row plus two below).
+const ROWS: usize = 3;
+
+/// Full dither strength in the 1/1024 fixed-point unit the kernels take.
+pub(crate) const FULL: i32 = 1024;
+
+/// In-place error-diffusion dithering of a grayscale8 buffer.
+///
+/// `strength` (0..=[`FULL`]) scales the diffused error; 0 degrades to plain
+/// nearest-level quantization. With `serpentine` set, odd rows are scanned
+/// right-to-left with the kernel mirrored. Integer-only so results are
+/// bit-exact on every platform.
+pub(crate) fn error_diffusion(
+    buf: &mut [u8],
+    width: usize,
+    height: usize,
+    levels: &LevelTable,
+    strength: i32,
+    kernel: Kernel,
+    serpentine: bool,
+) {
+    let (taps, divisor) = kernel.taps();
+    // Ring of accumulated (undivided) error rows, padded on both sides.
+    let mut errors = vec![vec![0i32; width + 2 * PAD]; ROWS];
+
+    for y in 0..height {
+        let row = &mut buf[y * width..(y + 1) * width];
+        let reverse = serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let old = i32::from(row[x]) + errors[y % ROWS][x + PAD] / divisor;
+            let new = levels.nearest(old);
+            row[x] = new;
+
+            let err = (old - i32::from(new)) * strength / FULL;
+            if err == 0 {
+                continue;
+            }
+            for t in taps {
+                let dx = if reverse { -t.dx } else { t.dx };
+                let col = (x + PAD).wrapping_add_signed(dx);
+                errors[(y + t.dy) % ROWS][col] += err * t.weight;
+            }
+        }
+        errors[y % ROWS].fill(0);
+    }
+}
+
+/// In-place ordered dithering against a tiled blue-noise threshold mask.
+///
+/// Each pixel picks between the two levels bracketing it; the mask entry
+/// shifts the decision point away from the midpoint by up to half the gap,
+/// and `strength` (0..=[`FULL`]) scales that shift.
+pub(crate) fn blue_noise(
+    buf: &mut [u8],
+    width: usize,
+    levels: &LevelTable,
+    strength: i32,
+    size: MaskSize,
+    seed: u64,
+) {
+    let (mask, side): (&[u8], usize) = match size {
+        MaskSize::Size64 => (BLUE_NOISE_64, 64),
+        MaskSize::Size128 => (BLUE_NOISE_128, 128),
+    };
+    // The masks tile seamlessly, so any offset keeps the blue-noise spectrum.
+    let (dx, dy) = mask_offset(seed, side);
+
+    for (y, row) in buf.chunks_exact_mut(width).enumerate() {
+        let mask_row = &mask[((y + dy) % side) * side..][..side];
+        for (x, px) in row.iter_mut().enumerate() {
+            let (lower, upper) = levels.bracket(*px);
+            if lower == upper {
+                *px = lower;
+                continue;
+            }
+            // Threshold offset in -255..=255 (units of 1/510 of the gap).
+            let t = 2 * i32::from(mask_row[(x + dx) % side]) + 1 - 256;
+            let bias = t * strength / FULL;
+            let above = i32::from(*px - lower) * 510;
+            let gap = i32::from(upper - lower);
+            *px = if above > gap * (255 + bias) {
+                upper
+            } else {
+                lower
+            };
+        }
+    }
+}
+
+/// Mask tiling offset for `seed`; seed 0 keeps the mask unshifted.
+fn mask_offset(seed: u64, side: usize) -> (usize, usize) {
+    if seed == 0 {
+        return (0, 0);
+    }
+    // splitmix64 finalizer.
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    let side = side as u64;
+    ((z % side) as usize, ((z >> 32) % side) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONO: LevelTable = LevelTable::uniform(2);
+
+    #[test]
+    fn zero_strength_is_plain_threshold() {
+        let mut buf = vec![100u8; 16];
+        error_diffusion(&mut buf, 4, 4, &MONO, 0, Kernel::FloydSteinberg, false);
+        assert!(buf.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn full_strength_preserves_mean_tone() {
+        let (w, h) = (64, 64);
+        for kernel in [Kernel::FloydSteinberg, Kernel::Sierra, Kernel::Stucki] {
+            for serpentine in [false, true] {
+                let mut buf = vec![128u8; w * h];
+                error_diffusion(&mut buf, w, h, &MONO, FULL, kernel, serpentine);
+                let white = buf.iter().filter(|&&v| v == 255).count();
+                let ratio = white as f64 / (w * h) as f64;
+                assert!(
+                    (0.45..0.55).contains(&ratio),
+                    "{kernel:?}: white ratio {ratio}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_weights_sum_to_divisor() {
+        for kernel in [Kernel::FloydSteinberg, Kernel::Sierra, Kernel::Stucki] {
+            let (taps, divisor) = kernel.taps();
+            assert_eq!(taps.iter().map(|t| t.weight).sum::<i32>(), divisor);
+        }
+        let (taps, divisor) = Kernel::Atkinson.taps();
+        assert_eq!(taps.iter().map(|t| t.weight).sum::<i32>() * 4, divisor * 3);
+    }
+
+    #[test]
+    fn atkinson_keeps_near_white_clean() {
+        let mut buf = vec![250u8; 32 * 32];
+        error_diffusion(&mut buf, 32, 32, &MONO, FULL, Kernel::Atkinson, false);
+        assert!(buf.iter().all(|&v| v == 255));
+    }
+
+    #[test]
+    fn serpentine_changes_scan_order() {
+        let gray: Vec<u8> = (0..32 * 32).map(|i| (i * 13 % 256) as u8).collect();
+        let mut forward = gray.clone();
+        let mut snake = gray;
+        error_diffusion(
+            &mut forward,
+            32,
+            32,
+            &MONO,
+            FULL,
+            Kernel::FloydSteinberg,
+            false,
+        );
+        error_diffusion(
+            &mut snake,
+            32,
+            32,
+            &MONO,
+            FULL,
+            Kernel::FloydSteinberg,
+            true,
+        );
+        assert_eq!(forward[..32], snake[..32]);
+        assert_ne!(forward, snake);
+    }
+
+    #[test]
+    fn blue_noise_masks_are_uniform() {
+        for mask in [&BLUE_NOISE_64[..], &BLUE_NOISE_128[..]] {
+            let mut histogram = [0usize; 256];
+            for &t in mask {
+                histogram[usize::from(t)] += 1;
+            }
+            assert!(histogram.iter().all(|&c| c == mask.len() / 256));
+        }
+    }
+
+    #[test]
+    fn blue_noise_zero_strength_matches_nearest_level() {
+        let mut buf: Vec<u8> = (0..=255).collect();
+        blue_noise(
+            &mut buf,
+            16,
+            &LevelTable::uniform(4),
+            0,
+            MaskSize::Size64,
+            0,
+        );
+        for (v, out) in (0..=255).zip(buf) {
+            assert_eq!(out, LevelTable::uniform(4).nearest(v));
+        }
+    }
+
+    #[test]
+    fn blue_noise_preserves_mean_tone() {
+        let mut buf = vec![64u8; 128 * 128];
+        blue_noise(&mut buf, 128, &MONO, FULL, MaskSize::Size128, 0);
+        let white = buf.iter().filter(|&&v| v == 255).count();
+        let ratio = white as f64 / buf.len() as f64;
+        assert!((0.23..0.27).contains(&ratio), "white ratio {ratio}");
+    }
+
+    #[test]
+    fn blue_noise_dithers_between_table_neighbours() {
+        let table = LevelTable::new(&[0, 40, 90, 255]).unwrap();
+        let mut buf = vec![65u8; 64 * 64];
+        blue_noise(&mut buf, 64, &table, FULL, MaskSize::Size64, 0);
+        assert!(buf.iter().all(|&v| v == 40 || v == 90));
+        let upper = buf.iter().filter(|&&v| v == 90).count();
+        let ratio = upper as f64 / buf.len() as f64;
+        assert!((0.45..0.55).contains(&ratio), "upper ratio {ratio}");
+    }
+}
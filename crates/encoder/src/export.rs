@@ -0,0 +1,202 @@
+//! Writing encoded pages as ordinary image files.
+//!
+//! For eyeballing conversions on the desktop and for golden files in tests.
+//! Packed buffers are expanded to grayscale8 first, so every format exports
+//! the same way.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::{pack, EncodeError, EncodedFrame, OutputFormat};
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("png encoding failed: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+}
+
+/// Expands `buffer` (laid out per `format`, `size` = width x height) to one
+/// grayscale8 byte per pixel.
+pub fn to_gray8(
+    buffer: &[u8],
+    size: (u32, u32),
+    format: OutputFormat,
+) -> Result<Vec<u8>, EncodeError> {
+    let (width, height) = size;
+    if width == 0 || height == 0 {
+        return Err(EncodeError::InvalidDimensions { width, height });
+    }
+    let expected = format.stride(width) * height as usize;
+    if buffer.len() != expected {
+        return Err(EncodeError::BufferSize {
+            expected,
+            actual: buffer.len(),
+        });
+    }
+    Ok(pack::unpack(
+        buffer,
+        width as usize,
+        height as usize,
+        format,
+    ))
+}
+
+/// Writes `buffer` as an 8-bit grayscale PNG.
+pub fn write_png(
+    path: impl AsRef<Path>,
+    buffer: &[u8],
+    size: (u32, u32),
+    format: OutputFormat,
+) -> Result<(), ExportError> {
+    let gray = to_gray8(buffer, size, format)?;
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.0, size.1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&gray)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes `buffer` as a binary PGM (`P5`, maxval 255).
+pub fn write_pgm(
+    path: impl AsRef<Path>,
+    buffer: &[u8],
+    size: (u32, u32),
+    format: OutputFormat,
+) -> Result<(), ExportError> {
+    let gray = to_gray8(buffer, size, format)?;
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P5\n{} {}\n255\n", size.0, size.1)?;
+    file.write_all(&gray)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes `buffer` as a binary PBM (`P4`). Pixels below mid-gray become
+/// black; note PBM stores black as 1.
+pub fn write_pbm(
+    path: impl AsRef<Path>,
+    buffer: &[u8],
+    size: (u32, u32),
+    format: OutputFormat,
+) -> Result<(), ExportError> {
+    let gray = to_gray8(buffer, size, format)?;
+    let width = size.0 as usize;
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P4\n{} {}\n", size.0, size.1)?;
+    let mut row = vec![0u8; width.div_ceil(8)];
+    for line in gray.chunks_exact(width) {
+        row.fill(0);
+        for (x, &px) in line.iter().enumerate() {
+            if px < 128 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        file.write_all(&row)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+impl EncodedFrame {
+    /// Writes this frame as a PNG (decompressing first if needed).
+    pub fn write_png(&self, path: impl AsRef<Path>) -> Result<(), ExportError> {
+        write_png(
+            path,
+            &self.pixels()?,
+            (self.width, self.height),
+            self.format,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_buffer, BitOrder, EncoderConfig, Levels, PackedBits, Polarity};
+
+    fn checker(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .map(|i| {
+                if (i % width + i / width).is_multiple_of(2) {
+                    0
+                } else {
+                    255
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn png_round_trips_packed_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.png");
+        let config = EncoderConfig {
+            levels: Levels::Mono,
+            output: OutputFormat::Packed {
+                bits: PackedBits::One,
+                order: BitOrder::MsbFirst,
+                polarity: Polarity::ZeroIsBlack,
+            },
+            ..EncoderConfig::default()
+        };
+        let frame = encode_buffer(&config, &checker(10, 6), 10, 6).unwrap();
+        frame.write_png(&path).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (10, 6));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(&pixels[..info.buffer_size()], &checker(10, 6)[..]);
+    }
+
+    #[test]
+    fn pgm_has_header_and_raw_pixels() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.pgm");
+        let gray = vec![0, 85, 170, 255];
+        write_pgm(&path, &gray, (2, 2), OutputFormat::Gray8).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes, b"P5\n2 2\n255\n\x00\x55\xaa\xff");
+    }
+
+    #[test]
+    fn pbm_marks_dark_pixels() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.pbm");
+        let gray = vec![0, 255, 255, 255, 255, 255, 255, 255, 0, 255];
+        write_pbm(&path, &gray, (10, 1), OutputFormat::Gray8).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes, b"P4\n10 1\n\x80\x80");
+    }
+
+    #[test]
+    fn rejects_short_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = write_png(
+            dir.path().join("x.png"),
+            &[0; 3],
+            (2, 2),
+            OutputFormat::Gray8,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ExportError::Encode(EncodeError::BufferSize {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+}
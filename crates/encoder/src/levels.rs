@@ -0,0 +1,232 @@
+//! Output gray levels and the tables that define them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Most levels a [`LevelTable`] may hold (4-bit codes).
+pub const MAX_LEVELS: usize = 16;
+
+/// Number of gray levels the encoded page may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Levels {
+    /// 1-bit black/white ("Fast" quality, XTG pages).
+    Mono,
+    /// 4-level grayscale ("High Quality", XTH pages).
+    #[default]
+    Gray4,
+    /// Caller-supplied (possibly non-linear) level table, e.g. one measured
+    /// for a specific panel waveform.
+    Table(LevelTable),
+}
+
+impl Levels {
+    /// How many distinct output values this mode produces.
+    pub fn count(self) -> u8 {
+        self.table().len() as u8
+    }
+
+    /// The grayscale8 value of each level, darkest first.
+    pub fn table(self) -> LevelTable {
+        match self {
+            Levels::Mono => LevelTable::uniform(2),
+            Levels::Gray4 => LevelTable::uniform(4),
+            Levels::Table(table) => table,
+        }
+    }
+}
+
+/// Ordered set of 2..=16 grayscale8 values a page is quantized to.
+///
+/// Entry `i` is the grayscale8 intensity the panel shows for level code `i`.
+/// Quantization picks the nearest entry, so a table measured from the panel
+/// spends dither error where the display actually has distinct tones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct LevelTable {
+    values: [u8; MAX_LEVELS],
+    len: u8,
+}
+
+impl LevelTable {
+    /// Builds a table from strictly ascending values. The first entry must be
+    /// 0 (black) and the last 255 (white) so the full tonal range stays
+    /// reachable.
+    pub fn new(values: &[u8]) -> Result<Self, EncodeError> {
+        if !(2..=MAX_LEVELS).contains(&values.len()) {
+            return Err(EncodeError::InvalidLevels("table must hold 2..=16 levels"));
+        }
+        if values.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(EncodeError::InvalidLevels(
+                "table values must be strictly ascending",
+            ));
+        }
+        if values[0] != 0 || values[values.len() - 1] != 255 {
+            return Err(EncodeError::InvalidLevels(
+                "table must start at 0 and end at 255",
+            ));
+        }
+        let mut table = [0u8; MAX_LEVELS];
+        table[..values.len()].copy_from_slice(values);
+        Ok(Self {
+            values: table,
+            len: values.len() as u8,
+        })
+    }
+
+    /// `count` evenly spaced levels from 0 to 255 (`count` in 2..=16).
+    pub const fn uniform(count: u8) -> Self {
+        let count = if count < 2 {
+            2
+        } else if count as usize > MAX_LEVELS {
+            MAX_LEVELS as u8
+        } else {
+            count
+        };
+        let steps = count as u16 - 1;
+        let mut values = [0u8; MAX_LEVELS];
+        let mut i = 0;
+        while i < count as usize {
+            values[i] = (i as u16 * 255 / steps) as u8;
+            i += 1;
+        }
+        Self { values, len: count }
+    }
+
+    /// 16 evenly spaced levels, the default for 4-bit panels until a
+    /// measured table is supplied by the device profile.
+    pub fn gray16() -> Self {
+        Self::uniform(16)
+    }
+
+    pub fn values(&self) -> &[u8] {
+        &self.values[..usize::from(self.len)]
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Index of the entry nearest to `value` (clamped to 0..=255).
+    pub fn nearest_index(&self, value: i32) -> usize {
+        let v = value.clamp(0, 255);
+        let values = self.values();
+        // First entry strictly above v; the answer is it or its predecessor.
+        let upper = values.partition_point(|&e| i32::from(e) <= v);
+        if upper == 0 {
+            return 0;
+        }
+        if upper == values.len() {
+            return values.len() - 1;
+        }
+        let (a, b) = (i32::from(values[upper - 1]), i32::from(values[upper]));
+        if 2 * (v - a) > b - a {
+            upper
+        } else {
+            upper - 1
+        }
+    }
+
+    /// The entry nearest to `value`.
+    pub fn nearest(&self, value: i32) -> u8 {
+        self.values[self.nearest_index(value)]
+    }
+
+    /// The pair of entries bracketing `value`, as `(lower, upper)`. Both are
+    /// the same entry when `value` lies on it or outside the table.
+    pub(crate) fn bracket(&self, value: u8) -> (u8, u8) {
+        let values = self.values();
+        let upper = values.partition_point(|&e| e < value);
+        if upper == values.len() {
+            let last = values[values.len() - 1];
+            return (last, last);
+        }
+        if values[upper] == value || upper == 0 {
+            return (values[upper], values[upper]);
+        }
+        (values[upper - 1], values[upper])
+    }
+}
+
+impl TryFrom<Vec<u8>> for LevelTable {
+    type Error = EncodeError;
+
+    fn try_from(values: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::new(&values)
+    }
+}
+
+impl From<LevelTable> for Vec<u8> {
+    fn from(table: LevelTable) -> Self {
+        table.values().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_tables_span_full_range() {
+        assert_eq!(LevelTable::uniform(2).values(), &[0, 255]);
+        assert_eq!(LevelTable::uniform(4).values(), &[0, 85, 170, 255]);
+        let gray16 = LevelTable::gray16();
+        assert_eq!(gray16.len(), 16);
+        assert_eq!(gray16.values()[1], 17);
+        assert_eq!(gray16.values()[15], 255);
+    }
+
+    #[test]
+    fn nearest_snaps_to_grid() {
+        let gray4 = LevelTable::uniform(4);
+        assert_eq!(gray4.nearest(0), 0);
+        assert_eq!(gray4.nearest(42), 0);
+        assert_eq!(gray4.nearest(43), 85);
+        assert_eq!(gray4.nearest(200), 170);
+        assert_eq!(gray4.nearest(300), 255);
+        assert_eq!(gray4.nearest(-20), 0);
+        let mono = LevelTable::uniform(2);
+        assert_eq!(mono.nearest(127), 0);
+        assert_eq!(mono.nearest(128), 255);
+    }
+
+    #[test]
+    fn nearest_respects_non_linear_tables() {
+        let table = LevelTable::new(&[0, 30, 60, 255]).unwrap();
+        assert_eq!(table.nearest(44), 30);
+        assert_eq!(table.nearest(46), 60);
+        assert_eq!(table.nearest(157), 60);
+        assert_eq!(table.nearest(158), 255);
+        assert_eq!(table.nearest_index(158), 3);
+    }
+
+    #[test]
+    fn bracket_finds_neighbours() {
+        let table = LevelTable::new(&[0, 30, 60, 255]).unwrap();
+        assert_eq!(table.bracket(45), (30, 60));
+        assert_eq!(table.bracket(60), (60, 60));
+        assert_eq!(table.bracket(0), (0, 0));
+        assert_eq!(table.bracket(255), (255, 255));
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        for bad in [
+            &[0u8][..],
+            &[0, 128, 128, 255],
+            &[10, 255],
+            &[0, 200],
+            &[0; 17],
+        ] {
+            assert!(matches!(
+                LevelTable::new(bad),
+                Err(EncodeError::InvalidLevels(_))
+            ));
+        }
+    }
+}
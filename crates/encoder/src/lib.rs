@@ -0,0 +1,312 @@
+//! Grayscale8 page frames -> quantized pixel data for the X4 panel.
+//!
+//! The frontend captures each page as a `width * height` grayscale8 buffer
+//! (0 = black, 255 = white). This crate reduces those buffers to the levels
+//! the device can show: 2 levels for XTG pages, 4 levels for XTH pages.
+//! Everything here is deterministic: same input + same config => same bytes.
+
+mod batch;
+mod compress;
+mod config;
+mod crop;
+mod diff;
+mod dither;
+pub mod export;
+mod levels;
+mod pack;
+mod pipeline;
+pub mod resize;
+mod segment;
+mod sharpen;
+#[cfg(feature = "bench")]
+pub mod throughput;
+mod tone;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub use batch::{encode_pages, encode_pages_with_progress, CancelToken, EncodeProgress, GrayFrame};
+pub use compress::Compression;
+pub use config::{DitherConfig, EncoderConfig, CONFIG_VERSION};
+pub use crop::{Crop, CropRect};
+pub use diff::{diff_frames, extract_region, DirtyRect};
+pub use levels::{LevelTable, Levels, MAX_LEVELS};
+pub use pack::{BitOrder, OutputFormat, PackedBits, Polarity};
+pub use pipeline::{EncoderPipeline, Pack, PipelineBuilder, Quantize, Stage};
+pub use resize::{Filter, Fit, Resize};
+pub use segment::{RegionKind, RegionMap, Segmentation};
+pub use sharpen::SharpenConfig;
+#[cfg(feature = "bench")]
+pub use throughput::{encode_throughput_report, ThroughputSample};
+pub use tone::ToneConfig;
+
+/// How quantization error is hidden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMode {
+    /// Error diffusion with the given kernel. Best for text and line art.
+    /// `serpentine` alternates scan direction per row to break up
+    /// directional artifacts.
+    ErrorDiffusion { kernel: Kernel, serpentine: bool },
+    /// Ordered dithering against an embedded blue-noise threshold mask.
+    /// Avoids diffusion "worms" and Bayer crosshatch on photographic pages.
+    BlueNoise(MaskSize),
+}
+
+impl Default for DitherMode {
+    fn default() -> Self {
+        DitherMode::ErrorDiffusion {
+            kernel: Kernel::FloydSteinberg,
+            serpentine: false,
+        }
+    }
+}
+
+/// Error-diffusion kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kernel {
+    #[default]
+    FloydSteinberg,
+    /// Classic e-ink look: lighter texture and less ghosting, at the cost of
+    /// some lost detail in midtones.
+    Atkinson,
+    /// Three-row Sierra kernel.
+    Sierra,
+    Stucki,
+}
+
+/// Tile size of the embedded blue-noise masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskSize {
+    #[default]
+    Size64,
+    Size128,
+}
+
+/// A quantized page ready for packing into a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub format: OutputFormat,
+    /// Bytes per row in `data` once decompressed.
+    pub stride: usize,
+    /// Codec applied to `data`.
+    pub compression: Compression,
+    pub data: Vec<u8>,
+}
+
+impl EncodedFrame {
+    /// The uncompressed pixel data (`stride * height` bytes).
+    pub fn pixels(&self) -> Result<Vec<u8>, EncodeError> {
+        self.compression
+            .decompress(&self.data, self.stride * self.height as usize)
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EncodeError {
+    #[error("invalid frame dimensions {width}x{height}")]
+    InvalidDimensions { width: u32, height: u32 },
+    #[error("frame buffer is {actual} bytes, expected {expected}")]
+    BufferSize { expected: usize, actual: usize },
+    #[error("invalid tone setting: {0}")]
+    InvalidTone(&'static str),
+    #[error("invalid sharpen setting: {0}")]
+    InvalidSharpen(&'static str),
+    #[error("invalid segmentation setting: {0}")]
+    InvalidSegmentation(&'static str),
+    #[error("invalid crop: {0}")]
+    InvalidCrop(&'static str),
+    #[error("invalid dither setting: {0}")]
+    InvalidDither(&'static str),
+    #[error("invalid level table: {0}")]
+    InvalidLevels(&'static str),
+    #[error("invalid output format: {0}")]
+    InvalidOutput(&'static str),
+    #[error("invalid pipeline: {0}")]
+    InvalidPipeline(&'static str),
+    #[error("{0:?} compression is not enabled in this build")]
+    CompressionUnavailable(Compression),
+    #[error("compression failed: {0}")]
+    Compression(String),
+    #[error("encoding was cancelled")]
+    Cancelled,
+    #[error("config version {0} is newer than this encoder supports")]
+    UnsupportedConfigVersion(u32),
+}
+
+/// Runs the canonical pipeline for `config` (see
+/// [`EncoderPipeline::from_config`]) over one grayscale8 frame.
+///
+/// `width` and `height` describe `gray`. The quantized values are restricted
+/// to the levels of the selected mode (e.g. `0, 85, 170, 255`) and laid out
+/// per `config.output`.
+pub fn encode_buffer(
+    config: &EncoderConfig,
+    gray: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<EncodedFrame, EncodeError> {
+    EncoderPipeline::from_config(config)?.run(gray, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_mismatched_buffer() {
+        let err = encode_buffer(&EncoderConfig::default(), &[0; 10], 4, 4).unwrap_err();
+        assert_eq!(
+            err,
+            EncodeError::BufferSize {
+                expected: 16,
+                actual: 10
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_empty_dimensions() {
+        let err = encode_buffer(&EncoderConfig::default(), &[], 0, 4).unwrap_err();
+        assert_eq!(
+            err,
+            EncodeError::InvalidDimensions {
+                width: 0,
+                height: 4
+            }
+        );
+    }
+
+    #[test]
+    fn output_uses_only_device_levels() {
+        let gray: Vec<u8> = (0..64 * 64).map(|i| (i % 256) as u8).collect();
+        for levels in [Levels::Mono, Levels::Gray4] {
+            for algorithm in [
+                DitherMode::default(),
+                DitherMode::ErrorDiffusion {
+                    kernel: Kernel::Atkinson,
+                    serpentine: true,
+                },
+                DitherMode::BlueNoise(MaskSize::Size64),
+                DitherMode::BlueNoise(MaskSize::Size128),
+            ] {
+                let config = EncoderConfig {
+                    levels,
+                    dither: DitherConfig {
+                        algorithm,
+                        ..DitherConfig::default()
+                    },
+                    ..EncoderConfig::default()
+                };
+                let out = encode_buffer(&config, &gray, 64, 64).unwrap();
+                let step = 255 / (levels.count() - 1);
+                assert!(out.data.iter().all(|v| v % step == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn encoding_is_deterministic() {
+        let gray: Vec<u8> = (0..48 * 80).map(|i| (i * 7 % 251) as u8).collect();
+        let config = EncoderConfig::default();
+        assert_eq!(
+            encode_buffer(&config, &gray, 48, 80).unwrap(),
+            encode_buffer(&config, &gray, 48, 80).unwrap()
+        );
+    }
+
+    #[test]
+    fn packed_output_reports_stride() {
+        let config = EncoderConfig {
+            levels: Levels::Mono,
+            output: OutputFormat::Packed {
+                bits: PackedBits::One,
+                order: BitOrder::MsbFirst,
+                polarity: Polarity::ZeroIsBlack,
+            },
+            ..EncoderConfig::default()
+        };
+        let frame = encode_buffer(&config, &[255; 10 * 3], 10, 3).unwrap();
+        assert_eq!(frame.stride, 2);
+        assert_eq!(frame.data, vec![0xFF, 0xC0, 0xFF, 0xC0, 0xFF, 0xC0]);
+    }
+
+    #[test]
+    fn custom_table_output_uses_table_values() {
+        let table = LevelTable::new(&[0, 40, 90, 160, 255]).unwrap();
+        let config = EncoderConfig {
+            levels: Levels::Table(table),
+            ..EncoderConfig::default()
+        };
+        let gray: Vec<u8> = (0..32 * 32).map(|i| (i % 256) as u8).collect();
+        let frame = encode_buffer(&config, &gray, 32, 32).unwrap();
+        assert!(frame.data.iter().all(|v| table.values().contains(v)));
+    }
+
+    #[test]
+    fn resize_stage_sets_output_dimensions() {
+        let config = EncoderConfig {
+            resize: Some(Resize {
+                width: 48,
+                height: 80,
+                filter: Filter::Lanczos3,
+                fit: Fit::Stretch,
+            }),
+            ..EncoderConfig::default()
+        };
+        let frame = encode_buffer(&config, &[255; 120 * 200], 120, 200).unwrap();
+        assert_eq!((frame.width, frame.height), (48, 80));
+        assert_eq!(frame.data.len(), 48 * 80);
+    }
+
+    #[test]
+    fn seed_changes_blue_noise_reproducibly() {
+        let gray = vec![100u8; 64 * 64];
+        let config = |seed| EncoderConfig {
+            dither: DitherConfig {
+                algorithm: DitherMode::BlueNoise(MaskSize::Size64),
+                seed,
+                ..DitherConfig::default()
+            },
+            ..EncoderConfig::default()
+        };
+        let a = encode_buffer(&config(7), &gray, 64, 64).unwrap();
+        assert_eq!(a, encode_buffer(&config(7), &gray, 64, 64).unwrap());
+        assert_ne!(a, encode_buffer(&config(8), &gray, 64, 64).unwrap());
+    }
+
+    #[test]
+    fn auto_crop_fills_target_with_content() {
+        // 100x100 white page with a 20x40 black block in the middle.
+        let gray: Vec<u8> = (0..100 * 100)
+            .map(|i| {
+                let (x, y) = (i % 100, i / 100);
+                if (40..60).contains(&x) && (30..70).contains(&y) {
+                    0
+                } else {
+                    255
+                }
+            })
+            .collect();
+        let config = EncoderConfig {
+            levels: Levels::Mono,
+            crop: Some(Crop::Auto {
+                threshold: 240,
+                padding: 0,
+            }),
+            resize: Some(Resize {
+                width: 10,
+                height: 20,
+                filter: Filter::Bilinear,
+                fit: Fit::Contain,
+            }),
+            ..EncoderConfig::default()
+        };
+        let frame = encode_buffer(&config, &gray, 100, 100).unwrap();
+        assert!(frame.data.iter().all(|&v| v == 0));
+    }
+}
@@ -0,0 +1,321 @@
+//! Packing quantized grayscale8 into sub-byte pixel formats.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EncodeError, LevelTable};
+
+/// Pixel layout of an encoded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One byte per pixel, values restricted to the selected levels.
+    #[default]
+    Gray8,
+    /// `bits` per pixel (1, 2 or 4), rows padded to whole bytes.
+    Packed {
+        bits: PackedBits,
+        order: BitOrder,
+        #[serde(default)]
+        polarity: Polarity,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum PackedBits {
+    One,
+    Two,
+    Four,
+}
+
+impl PackedBits {
+    pub fn get(self) -> u8 {
+        match self {
+            PackedBits::One => 1,
+            PackedBits::Two => 2,
+            PackedBits::Four => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for PackedBits {
+    type Error = EncodeError;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        match bits {
+            1 => Ok(PackedBits::One),
+            2 => Ok(PackedBits::Two),
+            4 => Ok(PackedBits::Four),
+            _ => Err(EncodeError::InvalidOutput(
+                "packed bits per pixel must be 1, 2 or 4",
+            )),
+        }
+    }
+}
+
+impl From<PackedBits> for u8 {
+    fn from(bits: PackedBits) -> Self {
+        bits.get()
+    }
+}
+
+/// Where the leftmost pixel of a byte lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitOrder {
+    /// Leftmost pixel in the most significant bits (XTG, most controllers).
+    #[default]
+    MsbFirst,
+    /// Leftmost pixel in the least significant bits.
+    LsbFirst,
+}
+
+/// What packed code 0 means. Controllers disagree, so it is explicit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Polarity {
+    /// Code 0 is black and the all-ones code is white (XTG).
+    #[default]
+    ZeroIsBlack,
+    /// Code 0 is white and the all-ones code is black.
+    ZeroIsWhite,
+}
+
+impl OutputFormat {
+    pub fn bits_per_pixel(self) -> u8 {
+        match self {
+            OutputFormat::Gray8 => 8,
+            OutputFormat::Packed { bits, .. } => bits.get(),
+        }
+    }
+
+    /// Bytes per row for a frame `width` pixels wide.
+    pub fn stride(self, width: u32) -> usize {
+        (width as usize * usize::from(self.bits_per_pixel())).div_ceil(8)
+    }
+}
+
+/// Packs a quantized grayscale8 frame into `format`.
+///
+/// When `levels` has exactly `2^bits` entries each pixel is stored as its
+/// level index, so non-linear tables keep distinct codes. Otherwise pixels
+/// map to the nearest of the `2^bits` evenly spaced codes. Codes then follow
+/// the format's [`Polarity`]. With `invert` set, dark and light levels swap
+/// (a negative image); Gray8 output is otherwise returned as is.
+pub(crate) fn pack(
+    mut gray: Vec<u8>,
+    width: usize,
+    levels: &LevelTable,
+    format: OutputFormat,
+    invert: bool,
+) -> Vec<u8> {
+    let OutputFormat::Packed {
+        bits,
+        order,
+        polarity,
+    } = format
+    else {
+        if invert {
+            let values = levels.values();
+            for px in &mut gray {
+                *px = values[values.len() - 1 - levels.nearest_index(i32::from(*px))];
+            }
+        }
+        return gray;
+    };
+    let flip = invert != (polarity == Polarity::ZeroIsWhite);
+    let bits = usize::from(bits.get());
+    let max = (1u16 << bits) - 1;
+    let per_byte = 8 / bits;
+    let stride = (width * bits).div_ceil(8);
+    let height = gray.len() / width;
+    let by_index = levels.len() == usize::from(max) + 1;
+
+    let mut out = vec![0u8; stride * height];
+    for (src, dst) in gray.chunks_exact(width).zip(out.chunks_exact_mut(stride)) {
+        for (x, &px) in src.iter().enumerate() {
+            let code = if by_index {
+                levels.nearest_index(i32::from(px)) as u8
+            } else {
+                ((u16::from(px) * max + 127) / 255) as u8
+            };
+            let code = if flip { max as u8 - code } else { code };
+            let slot = x % per_byte;
+            let shift = match order {
+                BitOrder::MsbFirst => 8 - bits * (slot + 1),
+                BitOrder::LsbFirst => bits * slot,
+            };
+            dst[x / per_byte] |= code << shift;
+        }
+    }
+    out
+}
+
+/// Expands a buffer in `format` back to one grayscale8 byte per pixel.
+///
+/// Codes map to evenly spaced values (code `c` of `2^bits` becomes
+/// `c * 255 / (2^bits - 1)` under [`Polarity::ZeroIsBlack`]), which is exact
+/// for Mono/Gray4/gray16 output.
+pub(crate) fn unpack(data: &[u8], width: usize, height: usize, format: OutputFormat) -> Vec<u8> {
+    let OutputFormat::Packed {
+        bits,
+        order,
+        polarity,
+    } = format
+    else {
+        return data[..width * height].to_vec();
+    };
+    let bits = usize::from(bits.get());
+    let max = (1u16 << bits) - 1;
+    let per_byte = 8 / bits;
+    let stride = (width * bits).div_ceil(8);
+
+    let mut out = Vec::with_capacity(width * height);
+    for row in data.chunks_exact(stride).take(height) {
+        for x in 0..width {
+            let slot = x % per_byte;
+            let shift = match order {
+                BitOrder::MsbFirst => 8 - bits * (slot + 1),
+                BitOrder::LsbFirst => bits * slot,
+            };
+            let mut code = u16::from(row[x / per_byte] >> shift) & max;
+            if polarity == Polarity::ZeroIsWhite {
+                code = max - code;
+            }
+            out.push((code * 255 / max) as u8);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(bits: PackedBits, order: BitOrder) -> OutputFormat {
+        OutputFormat::Packed {
+            bits,
+            order,
+            polarity: Polarity::ZeroIsBlack,
+        }
+    }
+
+    #[test]
+    fn stride_rounds_up_to_whole_bytes() {
+        assert_eq!(OutputFormat::Gray8.stride(10), 10);
+        assert_eq!(packed(PackedBits::One, BitOrder::MsbFirst).stride(10), 2);
+        assert_eq!(packed(PackedBits::Two, BitOrder::MsbFirst).stride(10), 3);
+        assert_eq!(packed(PackedBits::Four, BitOrder::MsbFirst).stride(3), 2);
+        assert_eq!(packed(PackedBits::One, BitOrder::MsbFirst).stride(480), 60);
+    }
+
+    #[test]
+    fn packs_one_bit_msb_first() {
+        let gray = vec![0, 255, 255, 0, 0, 0, 0, 0, 255, 255];
+        let out = pack(
+            gray,
+            10,
+            &LevelTable::uniform(2),
+            packed(PackedBits::One, BitOrder::MsbFirst),
+            false,
+        );
+        assert_eq!(out, vec![0b0110_0000, 0b1100_0000]);
+    }
+
+    #[test]
+    fn packs_one_bit_lsb_first() {
+        let gray = vec![0, 255, 255, 0, 0, 0, 0, 0, 255, 255];
+        let out = pack(
+            gray,
+            10,
+            &LevelTable::uniform(2),
+            packed(PackedBits::One, BitOrder::LsbFirst),
+            false,
+        );
+        assert_eq!(out, vec![0b0000_0110, 0b0000_0011]);
+    }
+
+    #[test]
+    fn packs_two_bit_levels() {
+        let gray = vec![0, 85, 170, 255, 255];
+        let out = pack(
+            gray,
+            5,
+            &LevelTable::uniform(4),
+            packed(PackedBits::Two, BitOrder::MsbFirst),
+            false,
+        );
+        assert_eq!(out, vec![0b00_01_10_11, 0b11_00_00_00]);
+    }
+
+    #[test]
+    fn packs_four_bit_rows_independently() {
+        let gray = vec![255, 0, 17, 136, 34, 255];
+        let out = pack(
+            gray,
+            3,
+            &LevelTable::gray16(),
+            packed(PackedBits::Four, BitOrder::MsbFirst),
+            false,
+        );
+        assert_eq!(out, vec![0xF0, 0x10, 0x82, 0xF0]);
+    }
+
+    #[test]
+    fn packs_table_levels_by_index() {
+        let table = LevelTable::new(&[0, 40, 90, 255]).unwrap();
+        let gray = vec![0, 40, 90, 255];
+        let out = pack(
+            gray,
+            4,
+            &table,
+            packed(PackedBits::Two, BitOrder::MsbFirst),
+            false,
+        );
+        assert_eq!(out, vec![0b00_01_10_11]);
+    }
+
+    #[test]
+    fn unpack_reverses_pack() {
+        let gray: Vec<u8> = (0..7 * 3).map(|i| [0, 85, 170, 255][i % 4]).collect();
+        for bits in [PackedBits::One, PackedBits::Two, PackedBits::Four] {
+            for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+                let format = packed(bits, order);
+                let levels = LevelTable::uniform(1 << bits.get());
+                let expected: Vec<u8> =
+                    gray.iter().map(|&v| levels.nearest(i32::from(v))).collect();
+                let data = pack(gray.clone(), 7, &levels, format, false);
+                assert_eq!(unpack(&data, 7, 3, format), expected, "{bits:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn polarity_and_invert_flip_codes() {
+        let gray = vec![0, 85, 170, 255];
+        let levels = LevelTable::uniform(4);
+        let zero_white = OutputFormat::Packed {
+            bits: PackedBits::Two,
+            order: BitOrder::MsbFirst,
+            polarity: Polarity::ZeroIsWhite,
+        };
+        let data = pack(gray.clone(), 4, &levels, zero_white, false);
+        assert_eq!(data, vec![0b11_10_01_00]);
+        assert_eq!(unpack(&data, 4, 1, zero_white), gray);
+
+        let zero_black = packed(PackedBits::Two, BitOrder::MsbFirst);
+        let inverted = pack(gray.clone(), 4, &levels, zero_black, true);
+        assert_eq!(inverted, data);
+        assert_eq!(
+            pack(gray, 4, &levels, zero_white, true),
+            vec![0b00_01_10_11]
+        );
+    }
+
+    #[test]
+    fn invert_mirrors_gray8_levels() {
+        let table = LevelTable::new(&[0, 40, 90, 255]).unwrap();
+        let out = pack(vec![0, 40, 90, 255], 4, &table, OutputFormat::Gray8, true);
+        assert_eq!(out, vec![255, 90, 40, 0]);
+    }
+}
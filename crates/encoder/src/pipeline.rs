@@ -0,0 +1,485 @@
+//! Encoder pipeline: an ordered list of stages run over one frame.
+
+use crate::{
+    dither, pack, resize, Compression, Crop, DitherConfig, DitherMode, EncodeError, EncodedFrame,
+    EncoderConfig, LevelTable, Levels, OutputFormat, RegionKind, Resize, Segmentation,
+    SharpenConfig, ToneConfig,
+};
+
+/// One step of an [`EncoderPipeline`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    Crop(Crop),
+    Tone(ToneConfig),
+    Sharpen(SharpenConfig),
+    Resize(Resize),
+    Quantize(Quantize),
+    Pack(Pack),
+    Compress(Compression),
+}
+
+/// Layout of the quantized frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pack {
+    pub format: OutputFormat,
+    /// Swap dark and light levels (a negative image), independent of the
+    /// format's polarity.
+    pub invert: bool,
+}
+
+/// Reduction to the device levels; the one mandatory stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantize {
+    pub levels: Levels,
+    pub dither: DitherConfig,
+    /// When set, tiles classified as text are hard-thresholded (nearest
+    /// level) and only image tiles are dithered.
+    pub segmentation: Option<Segmentation>,
+}
+
+impl Quantize {
+    /// Quantizes a `width` x `height` frame in place and returns the level
+    /// table used.
+    fn apply(&self, buf: &mut [u8], width: u32, height: u32) -> LevelTable {
+        let levels = self.levels.table();
+        let map = self
+            .segmentation
+            .map(|s| s.classify(buf, width, height))
+            .filter(|map| map.kinds().contains(&RegionKind::Text));
+        let Some(map) = map else {
+            self.dither(buf, width, &levels);
+            return levels;
+        };
+
+        let mut dithered = buf.to_vec();
+        if map.has_images() {
+            self.dither(&mut dithered, width, &levels);
+        }
+        for (i, px) in buf.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            *px = match map.kind_at(x, y) {
+                RegionKind::Text => levels.nearest(i32::from(*px)),
+                RegionKind::Image => dithered[i],
+            };
+        }
+        levels
+    }
+
+    fn dither(&self, buf: &mut [u8], width: u32, levels: &LevelTable) {
+        let w = width as usize;
+        let h = buf.len() / w;
+        let strength = self.dither.strength_q10();
+        match self.dither.algorithm {
+            DitherMode::ErrorDiffusion { kernel, serpentine } => {
+                dither::error_diffusion(buf, w, h, levels, strength, kernel, serpentine)
+            }
+            DitherMode::BlueNoise(size) => {
+                dither::blue_noise(buf, w, levels, strength, size, self.dither.seed)
+            }
+        }
+    }
+}
+
+/// A validated sequence of stages.
+///
+/// Pixel stages (crop, tone, sharpen, resize) may appear in any order before the
+/// single quantize stage; optional pack and compress stages follow it, with
+/// compress always last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderPipeline {
+    stages: Vec<Stage>,
+}
+
+impl EncoderPipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// The canonical pipeline for a config:
+    /// crop -> tone -> sharpen -> resize -> quantize -> pack -> compress.
+    /// Identity stages are left out.
+    pub fn from_config(config: &EncoderConfig) -> Result<Self, EncodeError> {
+        let mut builder = Self::builder();
+        if let Some(crop) = config.crop {
+            builder = builder.crop(crop);
+        }
+        if !config.tone.is_identity() {
+            builder = builder.tone(config.tone);
+        }
+        if let Some(sharpen) = config.sharpen {
+            builder = builder.sharpen(sharpen);
+        }
+        if let Some(stage) = config.resize {
+            builder = builder.resize(stage);
+        }
+        builder = builder.stage(Stage::Quantize(Quantize {
+            levels: config.levels,
+            dither: config.dither,
+            segmentation: config.segmentation,
+        }));
+        if config.output != OutputFormat::Gray8 || config.invert_output {
+            builder = builder.stage(Stage::Pack(Pack {
+                format: config.output,
+                invert: config.invert_output,
+            }));
+        }
+        if config.compression != Compression::None {
+            builder = builder.compress(config.compression);
+        }
+        builder.build()
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Runs every stage over a `width` x `height` grayscale8 frame.
+    pub fn run(&self, gray: &[u8], width: u32, height: u32) -> Result<EncodedFrame, EncodeError> {
+        let expected = frame_len(width, height)?;
+        if gray.len() != expected {
+            return Err(EncodeError::BufferSize {
+                expected,
+                actual: gray.len(),
+            });
+        }
+
+        let (mut buf, mut width, mut height) = (gray.to_vec(), width, height);
+        let mut levels = LevelTable::uniform(2);
+        let mut format = OutputFormat::Gray8;
+        let mut compression = Compression::None;
+        for stage in &self.stages {
+            match stage {
+                Stage::Crop(crop) => {
+                    let (cropped, rect) = crop.apply(&buf, width, height)?;
+                    (buf, width, height) = (cropped, rect.width, rect.height);
+                }
+                Stage::Tone(tone) => tone.apply(&mut buf),
+                Stage::Sharpen(sharpen) => sharpen.apply(&mut buf, width as usize),
+                Stage::Resize(stage) => {
+                    buf = resize::resize_to_fit(&buf, (width, height), stage)?;
+                    (width, height) = (stage.width, stage.height);
+                }
+                Stage::Quantize(q) => levels = q.apply(&mut buf, width, height),
+                Stage::Pack(stage) => {
+                    format = stage.format;
+                    buf = pack::pack(buf, width as usize, &levels, format, stage.invert);
+                }
+                Stage::Compress(codec) => {
+                    compression = *codec;
+                    buf = codec.compress(buf)?;
+                }
+            }
+        }
+
+        Ok(EncodedFrame {
+            width,
+            height,
+            format,
+            stride: format.stride(width),
+            compression,
+            data: buf,
+        })
+    }
+}
+
+/// Collects stages in call order; [`PipelineBuilder::build`] validates them.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineBuilder {
+    stages: Vec<Stage>,
+}
+
+impl PipelineBuilder {
+    pub fn crop(self, crop: Crop) -> Self {
+        self.stage(Stage::Crop(crop))
+    }
+
+    pub fn tone(self, tone: ToneConfig) -> Self {
+        self.stage(Stage::Tone(tone))
+    }
+
+    pub fn sharpen(self, sharpen: SharpenConfig) -> Self {
+        self.stage(Stage::Sharpen(sharpen))
+    }
+
+    pub fn resize(self, stage: Resize) -> Self {
+        self.stage(Stage::Resize(stage))
+    }
+
+    pub fn quantize(self, levels: Levels, dither: DitherConfig) -> Self {
+        self.stage(Stage::Quantize(Quantize {
+            levels,
+            dither,
+            segmentation: None,
+        }))
+    }
+
+    pub fn pack(self, format: OutputFormat) -> Self {
+        self.stage(Stage::Pack(Pack {
+            format,
+            invert: false,
+        }))
+    }
+
+    pub fn compress(self, compression: Compression) -> Self {
+        self.stage(Stage::Compress(compression))
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn build(self) -> Result<EncoderPipeline, EncodeError> {
+        let mut quantized = false;
+        let mut packed = false;
+        let mut compressed = false;
+        for stage in &self.stages {
+            if compressed {
+                return Err(EncodeError::InvalidPipeline(
+                    "compress must be the last stage",
+                ));
+            }
+            if packed && !matches!(stage, Stage::Compress(_)) {
+                return Err(EncodeError::InvalidPipeline(
+                    "only compress may follow pack",
+                ));
+            }
+            match stage {
+                Stage::Crop(crop) => crop.validate()?,
+                Stage::Tone(tone) => tone.validate()?,
+                Stage::Sharpen(sharpen) => sharpen.validate()?,
+                Stage::Resize(stage) => {
+                    frame_len(stage.width, stage.height)?;
+                }
+                Stage::Quantize(_) if quantized => {
+                    return Err(EncodeError::InvalidPipeline(
+                        "pipeline must quantize exactly once",
+                    ));
+                }
+                Stage::Quantize(q) => {
+                    q.dither.validate()?;
+                    if let Some(segmentation) = &q.segmentation {
+                        segmentation.validate()?;
+                    }
+                    quantized = true;
+                }
+                Stage::Pack(_) if !quantized => {
+                    return Err(EncodeError::InvalidPipeline(
+                        "pack requires a preceding quantize stage",
+                    ));
+                }
+                Stage::Pack(_) => packed = true,
+                Stage::Compress(_) if !quantized => {
+                    return Err(EncodeError::InvalidPipeline(
+                        "compress requires a preceding quantize stage",
+                    ));
+                }
+                Stage::Compress(codec) => {
+                    codec.validate()?;
+                    compressed = true;
+                }
+            }
+            if quantized
+                && matches!(
+                    stage,
+                    Stage::Crop(_) | Stage::Tone(_) | Stage::Sharpen(_) | Stage::Resize(_)
+                )
+            {
+                return Err(EncodeError::InvalidPipeline(
+                    "pixel stages must run before quantize",
+                ));
+            }
+        }
+        if !quantized {
+            return Err(EncodeError::InvalidPipeline(
+                "pipeline must quantize exactly once",
+            ));
+        }
+        Ok(EncoderPipeline {
+            stages: self.stages,
+        })
+    }
+}
+
+pub(crate) fn frame_len(width: u32, height: u32) -> Result<usize, EncodeError> {
+    if width == 0 || height == 0 {
+        return Err(EncodeError::InvalidDimensions { width, height });
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .ok_or(EncodeError::InvalidDimensions { width, height })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitOrder, Filter, Fit, PackedBits, Polarity};
+
+    fn mono_pack() -> OutputFormat {
+        OutputFormat::Packed {
+            bits: PackedBits::One,
+            order: BitOrder::MsbFirst,
+            polarity: Polarity::ZeroIsBlack,
+        }
+    }
+
+    #[test]
+    fn from_config_orders_stages_canonically() {
+        let config = EncoderConfig {
+            tone: ToneConfig {
+                gamma: 1.4,
+                ..ToneConfig::default()
+            },
+            sharpen: Some(SharpenConfig::default()),
+            resize: Some(Resize {
+                width: 10,
+                height: 10,
+                filter: Filter::Bilinear,
+                fit: Fit::Stretch,
+            }),
+            output: mono_pack(),
+            ..EncoderConfig::default()
+        };
+        let pipeline = EncoderPipeline::from_config(&config).unwrap();
+        let expected = [
+            Stage::Tone(config.tone),
+            Stage::Sharpen(config.sharpen.unwrap()),
+            Stage::Resize(config.resize.unwrap()),
+            Stage::Quantize(Quantize {
+                levels: config.levels,
+                dither: config.dither,
+                segmentation: config.segmentation,
+            }),
+            Stage::Pack(Pack {
+                format: config.output,
+                invert: config.invert_output,
+            }),
+        ];
+        assert_eq!(pipeline.stages(), expected);
+    }
+
+    #[test]
+    fn identity_config_is_quantize_only() {
+        let pipeline = EncoderPipeline::from_config(&EncoderConfig::default()).unwrap();
+        assert!(matches!(pipeline.stages(), [Stage::Quantize(_)]));
+    }
+
+    #[test]
+    fn builder_runs_stages_in_call_order() {
+        let pipeline = EncoderPipeline::builder()
+            .resize(Resize {
+                width: 8,
+                height: 2,
+                filter: Filter::Nearest,
+                fit: Fit::Stretch,
+            })
+            .tone(ToneConfig {
+                brightness: 1.0,
+                ..ToneConfig::default()
+            })
+            .quantize(
+                Levels::Mono,
+                DitherConfig {
+                    strength: 0.0,
+                    ..DitherConfig::default()
+                },
+            )
+            .pack(mono_pack())
+            .build()
+            .unwrap();
+        let frame = pipeline.run(&[0; 4 * 4], 4, 4).unwrap();
+        assert_eq!((frame.width, frame.height, frame.stride), (8, 2, 1));
+        assert_eq!(frame.data, vec![0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_orderings() {
+        let quantize = Stage::Quantize(Quantize {
+            levels: Levels::Gray4,
+            dither: DitherConfig::default(),
+            segmentation: None,
+        });
+        let cases = [
+            vec![],
+            vec![Stage::Pack(Pack {
+                format: mono_pack(),
+                invert: false,
+            })],
+            vec![quantize.clone(), quantize.clone()],
+            vec![quantize.clone(), Stage::Tone(ToneConfig::default())],
+            vec![
+                quantize.clone(),
+                Stage::Pack(Pack {
+                    format: mono_pack(),
+                    invert: false,
+                }),
+                Stage::Pack(Pack {
+                    format: mono_pack(),
+                    invert: false,
+                }),
+            ],
+            vec![Stage::Compress(Compression::None), quantize.clone()],
+            vec![
+                quantize.clone(),
+                Stage::Compress(Compression::None),
+                Stage::Pack(Pack {
+                    format: mono_pack(),
+                    invert: false,
+                }),
+            ],
+        ];
+        for stages in cases {
+            let builder = stages
+                .into_iter()
+                .fold(EncoderPipeline::builder(), PipelineBuilder::stage);
+            assert!(matches!(
+                builder.build(),
+                Err(EncodeError::InvalidPipeline(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn builder_validates_stage_settings() {
+        let result = EncoderPipeline::builder()
+            .tone(ToneConfig {
+                gamma: -1.0,
+                ..ToneConfig::default()
+            })
+            .quantize(Levels::Gray4, DitherConfig::default())
+            .build();
+        assert!(matches!(result, Err(EncodeError::InvalidTone(_))));
+    }
+
+    #[test]
+    fn segmentation_thresholds_text_and_dithers_images() {
+        // Left half: black strokes on white ("text"); right half: flat gray
+        // ("photo").
+        let (w, h) = (32u32, 16u32);
+        let gray: Vec<u8> = (0..w * h)
+            .map(|i| match i % w {
+                x if x >= 16 => 100,
+                x if x % 4 == 0 => 0,
+                _ => 255,
+            })
+            .collect();
+        let pipeline = EncoderPipeline::builder()
+            .stage(Stage::Quantize(Quantize {
+                levels: Levels::Mono,
+                dither: DitherConfig::default(),
+                segmentation: Some(Segmentation::default()),
+            }))
+            .build()
+            .unwrap();
+        let frame = pipeline.run(&gray, w, h).unwrap();
+        let (text, photo): (Vec<_>, Vec<_>) = frame
+            .data
+            .iter()
+            .zip(&gray)
+            .enumerate()
+            .partition(|(i, _)| (*i as u32 % w) < 16);
+        assert!(text.iter().all(|(_, (out, src))| out == src));
+        let photo: Vec<u8> = photo.iter().map(|(_, (&out, _))| out).collect();
+        assert!(photo.contains(&0) && photo.contains(&255));
+    }
+}
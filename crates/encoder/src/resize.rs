@@ -0,0 +1,285 @@
+//! Separable resampling of grayscale8 frames.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Reconstruction filter used when scaling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    /// Fastest; blocky when enlarging, aliased when shrinking.
+    Nearest,
+    /// Linear interpolation (tent filter).
+    Bilinear,
+    /// Cubic with B = 0, C = 0.5. Sharp without much ringing.
+    #[default]
+    CatmullRom,
+    /// Windowed sinc with 3 lobes. Sharpest; can ring on hard edges.
+    Lanczos3,
+}
+
+impl Filter {
+    fn support(self) -> f64 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::CatmullRom => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::CatmullRom => {
+                if x < 1.0 {
+                    1.5 * x * x * x - 2.5 * x * x + 1.0
+                } else if x < 2.0 {
+                    -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos3 => {
+                if x < 1e-8 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = std::f64::consts::PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// How the source is mapped onto the target rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    /// Scale each axis independently to fill the target exactly.
+    Stretch,
+    /// Preserve aspect ratio and center the image, padding with white.
+    #[default]
+    Contain,
+}
+
+/// Resampling stage: scale the incoming frame to `width` x `height`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resize {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub fit: Fit,
+}
+
+/// Fixed-point precision of the filter weights.
+const PRECISION: u32 = 14;
+
+/// Scales a grayscale8 frame from `src` to `dst` (width, height) with
+/// `filter`, stretching to fill.
+///
+/// Weights are quantized to fixed point before use so the pixel arithmetic
+/// is integer-only.
+pub fn resize(
+    gray: &[u8],
+    src: (u32, u32),
+    dst: (u32, u32),
+    filter: Filter,
+) -> Result<Vec<u8>, EncodeError> {
+    let (sw, sh) = (src.0 as usize, src.1 as usize);
+    let (dw, dh) = (dst.0 as usize, dst.1 as usize);
+    for (width, height) in [src, dst] {
+        if width == 0 || height == 0 {
+            return Err(EncodeError::InvalidDimensions { width, height });
+        }
+    }
+    if gray.len() != sw * sh {
+        return Err(EncodeError::BufferSize {
+            expected: sw * sh,
+            actual: gray.len(),
+        });
+    }
+    if src == dst {
+        return Ok(gray.to_vec());
+    }
+
+    let columns = contributions(sw, dw, filter);
+    let mut horizontal = vec![0u8; dw * sh];
+    for (src_row, dst_row) in gray.chunks_exact(sw).zip(horizontal.chunks_exact_mut(dw)) {
+        for (out, c) in dst_row.iter_mut().zip(&columns) {
+            *out = convolve(c, |i| src_row[i]);
+        }
+    }
+
+    let rows = contributions(sh, dh, filter);
+    let mut out = vec![0u8; dw * dh];
+    for (y, c) in rows.iter().enumerate() {
+        let dst_row = &mut out[y * dw..(y + 1) * dw];
+        for (x, px) in dst_row.iter_mut().enumerate() {
+            *px = convolve(c, |i| horizontal[i * dw + x]);
+        }
+    }
+    Ok(out)
+}
+
+/// Scales `gray` into a `dst`-sized frame according to `fit`. With
+/// [`Fit::Contain`] the scaled image is centered on a white background.
+pub fn resize_to_fit(gray: &[u8], src: (u32, u32), stage: &Resize) -> Result<Vec<u8>, EncodeError> {
+    let dst = (stage.width, stage.height);
+    match stage.fit {
+        Fit::Stretch => resize(gray, src, dst, stage.filter),
+        Fit::Contain => {
+            let inner = contain(src, dst);
+            let scaled = resize(gray, src, inner, stage.filter)?;
+            if inner == dst {
+                return Ok(scaled);
+            }
+            let (dw, dh) = (dst.0 as usize, dst.1 as usize);
+            let (iw, ih) = (inner.0 as usize, inner.1 as usize);
+            let (ox, oy) = ((dw - iw) / 2, (dh - ih) / 2);
+            let mut out = vec![255u8; dw * dh];
+            for (y, row) in scaled.chunks_exact(iw).enumerate() {
+                let start = (y + oy) * dw + ox;
+                out[start..start + iw].copy_from_slice(row);
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Largest size with `src`'s aspect ratio that fits inside `dst`.
+fn contain(src: (u32, u32), dst: (u32, u32)) -> (u32, u32) {
+    let (sw, sh) = (u64::from(src.0), u64::from(src.1));
+    let (dw, dh) = (u64::from(dst.0), u64::from(dst.1));
+    if sw * dh > sh * dw {
+        (dst.0, ((sh * dw + sw / 2) / sw).max(1) as u32)
+    } else {
+        (((sw * dh + sh / 2) / sh).max(1) as u32, dst.1)
+    }
+}
+
+/// Source taps feeding one output sample.
+struct Contribution {
+    start: usize,
+    weights: Vec<i32>,
+}
+
+fn contributions(src: usize, dst: usize, filter: Filter) -> Vec<Contribution> {
+    let scale = src as f64 / dst as f64;
+    // Widen the filter when shrinking so it also acts as a low-pass.
+    let blur = scale.max(1.0);
+    let support = filter.support() * blur;
+    let one = f64::from(1u32 << PRECISION);
+
+    (0..dst)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            let start = ((center - support).floor().max(0.0)) as usize;
+            let end = ((center + support).ceil() as usize).min(src);
+            let raw: Vec<f64> = (start..end)
+                .map(|j| filter.weight((j as f64 + 0.5 - center) / blur))
+                .collect();
+            let sum: f64 = raw.iter().sum();
+            if sum == 0.0 {
+                // Degenerate nearest-neighbour case: take the closest pixel.
+                let nearest = (center as usize).min(src - 1);
+                return Contribution {
+                    start: nearest,
+                    weights: vec![1 << PRECISION],
+                };
+            }
+            let weights = raw.iter().map(|w| (w / sum * one).round() as i32).collect();
+            Contribution { start, weights }
+        })
+        .collect()
+}
+
+fn convolve(c: &Contribution, sample: impl Fn(usize) -> u8) -> u8 {
+    let acc: i32 = c
+        .weights
+        .iter()
+        .enumerate()
+        .map(|(k, &w)| w * i32::from(sample(c.start + k)))
+        .sum();
+    ((acc + (1 << (PRECISION - 1))) >> PRECISION).clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 4] = [
+        Filter::Nearest,
+        Filter::Bilinear,
+        Filter::CatmullRom,
+        Filter::Lanczos3,
+    ];
+
+    #[test]
+    fn flat_frames_stay_flat() {
+        let gray = vec![137u8; 30 * 50];
+        for filter in FILTERS {
+            for dst in [(480, 800), (12, 20), (31, 7)] {
+                let out = resize(&gray, (30, 50), dst, filter).unwrap();
+                assert_eq!(out.len(), (dst.0 * dst.1) as usize);
+                assert!(out.iter().all(|&v| v == 137), "{filter:?} {dst:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_doubles_pixels() {
+        let gray = vec![0, 255, 255, 0];
+        let out = resize(&gray, (2, 2), (4, 4), Filter::Nearest).unwrap();
+        assert_eq!(
+            out,
+            vec![0, 0, 255, 255, 0, 0, 255, 255, 255, 255, 0, 0, 255, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn downscale_averages_checkerboard() {
+        let gray: Vec<u8> = (0..64 * 64)
+            .map(|i| if (i % 64 + i / 64) % 2 == 0 { 0 } else { 255 })
+            .collect();
+        let out = resize(&gray, (64, 64), (8, 8), Filter::Bilinear).unwrap();
+        assert!(out.iter().all(|&v| (120..=135).contains(&v)), "{out:?}");
+    }
+
+    #[test]
+    fn contain_letterboxes_with_white() {
+        let gray = vec![0u8; 100 * 100];
+        let stage = Resize {
+            width: 48,
+            height: 80,
+            filter: Filter::CatmullRom,
+            fit: Fit::Contain,
+        };
+        let out = resize_to_fit(&gray, (100, 100), &stage).unwrap();
+        assert_eq!(out.len(), 48 * 80);
+        // 48x48 image centered vertically: rows 16..64 are black.
+        assert!(out[..16 * 48].iter().all(|&v| v == 255));
+        assert!(out[16 * 48..64 * 48].iter().all(|&v| v == 0));
+        assert!(out[64 * 48..].iter().all(|&v| v == 255));
+    }
+
+    #[test]
+    fn contain_matches_aspect() {
+        assert_eq!(contain((1000, 500), (480, 800)), (480, 240));
+        assert_eq!(contain((600, 1000), (480, 800)), (480, 800));
+        assert_eq!(contain((300, 1000), (480, 800)), (240, 800));
+    }
+}
@@ -0,0 +1,192 @@
+//! Text/image segmentation.
+//!
+//! Rendered pages mix crisp text with photos and illustrations. Dithering
+//! text leaves speckled glyph edges, while hard thresholding images destroys
+//! them. Segmentation classifies each tile of the page so the quantize stage
+//! can threshold text tiles and dither image tiles.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Tile classification settings.
+///
+/// A tile counts as image content when at least `midtone_ratio` of its
+/// pixels are midtones (not near black or near white). Anti-aliased text only
+/// produces midtones along glyph edges, so it stays well below the default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Segmentation {
+    /// Tile side in pixels (4..=128).
+    pub tile: u32,
+    /// Fraction of midtone pixels (0.0..=1.0) that marks a tile as image.
+    pub midtone_ratio: f32,
+    /// Values at or below this are "near black".
+    pub dark: u8,
+    /// Values at or above this are "near white".
+    pub light: u8,
+}
+
+impl Default for Segmentation {
+    fn default() -> Self {
+        Self {
+            tile: 16,
+            midtone_ratio: 0.4,
+            dark: 48,
+            light: 208,
+        }
+    }
+}
+
+/// What a tile contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Text,
+    Image,
+}
+
+/// Per-tile classification of one page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionMap {
+    pub tile: u32,
+    pub cols: u32,
+    pub rows: u32,
+    kinds: Vec<RegionKind>,
+}
+
+impl RegionMap {
+    /// Kind of the tile containing pixel (`x`, `y`).
+    pub fn kind_at(&self, x: u32, y: u32) -> RegionKind {
+        self.kinds[((y / self.tile) * self.cols + x / self.tile) as usize]
+    }
+
+    /// Kinds of all tiles, row-major.
+    pub fn kinds(&self) -> &[RegionKind] {
+        &self.kinds
+    }
+
+    pub fn has_images(&self) -> bool {
+        self.kinds.contains(&RegionKind::Image)
+    }
+}
+
+impl Segmentation {
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        if !(4..=128).contains(&self.tile) {
+            return Err(EncodeError::InvalidSegmentation(
+                "tile must be within 4..=128",
+            ));
+        }
+        if !(self.midtone_ratio.is_finite() && (0.0..=1.0).contains(&self.midtone_ratio)) {
+            return Err(EncodeError::InvalidSegmentation(
+                "midtone_ratio must be within 0.0..=1.0",
+            ));
+        }
+        if self.dark >= self.light {
+            return Err(EncodeError::InvalidSegmentation("dark must be below light"));
+        }
+        Ok(())
+    }
+
+    /// Classifies each tile of a `width` x `height` grayscale8 frame.
+    pub fn classify(&self, gray: &[u8], width: u32, height: u32) -> RegionMap {
+        let tile = self.tile;
+        let cols = width.div_ceil(tile);
+        let rows = height.div_ceil(tile);
+        let mut midtones = vec![0u32; (cols * rows) as usize];
+        let mut totals = vec![0u32; (cols * rows) as usize];
+
+        for (i, &px) in gray.iter().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let t = ((y / tile) * cols + x / tile) as usize;
+            totals[t] += 1;
+            if px > self.dark && px < self.light {
+                midtones[t] += 1;
+            }
+        }
+
+        let kinds = midtones
+            .iter()
+            .zip(&totals)
+            .map(|(&m, &n)| {
+                if m as f32 >= self.midtone_ratio * n as f32 && m > 0 {
+                    RegionKind::Image
+                } else {
+                    RegionKind::Text
+                }
+            })
+            .collect();
+        RegionMap {
+            tile,
+            cols,
+            rows,
+            kinds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64x32 page: left half fake text (black strokes on white with a thin
+    /// anti-aliased edge), right half a smooth gradient "photo".
+    fn mixed_page() -> Vec<u8> {
+        let (w, h) = (64u32, 32u32);
+        (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                if x < 32 {
+                    match x % 6 {
+                        0 | 1 => 0,
+                        2 => 128,
+                        _ => 255,
+                    }
+                } else {
+                    (60 + (x - 32) * 3 + y) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn separates_text_from_gradient() {
+        let map = Segmentation::default().classify(&mixed_page(), 64, 32);
+        assert_eq!((map.cols, map.rows), (4, 2));
+        assert_eq!(map.kind_at(0, 0), RegionKind::Text);
+        assert_eq!(map.kind_at(20, 20), RegionKind::Text);
+        assert_eq!(map.kind_at(40, 0), RegionKind::Image);
+        assert_eq!(map.kind_at(63, 31), RegionKind::Image);
+        assert!(map.has_images());
+    }
+
+    #[test]
+    fn blank_page_is_text() {
+        let map = Segmentation::default().classify(&[255; 32 * 32], 32, 32);
+        assert!(!map.has_images());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for bad in [
+            Segmentation {
+                tile: 2,
+                ..Segmentation::default()
+            },
+            Segmentation {
+                midtone_ratio: 1.5,
+                ..Segmentation::default()
+            },
+            Segmentation {
+                dark: 200,
+                light: 100,
+                ..Segmentation::default()
+            },
+        ] {
+            assert!(matches!(
+                bad.validate(),
+                Err(EncodeError::InvalidSegmentation(_))
+            ));
+        }
+    }
+}
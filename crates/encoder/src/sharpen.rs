@@ -0,0 +1,173 @@
+//! Unsharp-mask sharpening.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Unsharp mask applied before quantization.
+///
+/// Each pixel is pushed away from its Gaussian-blurred neighbourhood by
+/// `amount` times the difference, but only where that difference reaches
+/// `threshold`, so flat paper texture is not amplified.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SharpenConfig {
+    /// Gaussian sigma in pixels (0.1..=10.0).
+    pub radius: f32,
+    /// Strength; 1.0 adds the full difference back (0.0..=5.0).
+    pub amount: f32,
+    /// Minimum absolute difference (0..=255) before a pixel is sharpened.
+    pub threshold: u8,
+}
+
+impl Default for SharpenConfig {
+    /// Mild settings suited to small text at the X4's pixel density.
+    fn default() -> Self {
+        Self {
+            radius: 0.8,
+            amount: 0.6,
+            threshold: 2,
+        }
+    }
+}
+
+const PRECISION: u32 = 14;
+
+impl SharpenConfig {
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        if !(self.radius.is_finite() && (0.1..=10.0).contains(&self.radius)) {
+            return Err(EncodeError::InvalidSharpen(
+                "radius must be within 0.1..=10.0",
+            ));
+        }
+        if !(self.amount.is_finite() && (0.0..=5.0).contains(&self.amount)) {
+            return Err(EncodeError::InvalidSharpen(
+                "amount must be within 0.0..=5.0",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sharpens a `width`-wide grayscale8 frame in place.
+    pub(crate) fn apply(&self, buf: &mut [u8], width: usize) {
+        let height = buf.len() / width;
+        let kernel = gaussian(self.radius);
+        let reach = kernel.len() / 2;
+        let amount = (f64::from(self.amount) * 256.0).round() as i32;
+        let threshold = i32::from(self.threshold);
+
+        // Horizontal then vertical pass, edges clamped.
+        let mut horizontal = vec![0u8; buf.len()];
+        for (src, dst) in buf
+            .chunks_exact(width)
+            .zip(horizontal.chunks_exact_mut(width))
+        {
+            for (x, out) in dst.iter_mut().enumerate() {
+                *out = convolve(&kernel, |k| {
+                    src[(x + k).saturating_sub(reach).min(width - 1)]
+                });
+            }
+        }
+        let mut blurred = vec![0u8; buf.len()];
+        for y in 0..height {
+            for x in 0..width {
+                blurred[y * width + x] = convolve(&kernel, |k| {
+                    horizontal[(y + k).saturating_sub(reach).min(height - 1) * width + x]
+                });
+            }
+        }
+
+        for (px, &blur) in buf.iter_mut().zip(&blurred) {
+            let diff = i32::from(*px) - i32::from(blur);
+            if diff.abs() < threshold {
+                continue;
+            }
+            let v = i32::from(*px) + (diff * amount + 128).div_euclid(256);
+            *px = v.clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// Normalized fixed-point Gaussian kernel covering +-3 sigma.
+fn gaussian(sigma: f32) -> Vec<i32> {
+    let sigma = f64::from(sigma);
+    let reach = (3.0 * sigma).ceil().max(1.0) as i32;
+    let raw: Vec<f64> = (-reach..=reach)
+        .map(|i| (-f64::from(i * i) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = raw.iter().sum();
+    let one = f64::from(1u32 << PRECISION);
+    raw.iter().map(|w| (w / sum * one).round() as i32).collect()
+}
+
+fn convolve(kernel: &[i32], sample: impl Fn(usize) -> u8) -> u8 {
+    let acc: i32 = kernel
+        .iter()
+        .enumerate()
+        .map(|(k, &w)| w * i32::from(sample(k)))
+        .sum();
+    ((acc + (1 << (PRECISION - 1))) >> PRECISION).clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_frame_is_unchanged() {
+        let mut buf = vec![90u8; 16 * 16];
+        SharpenConfig::default().apply(&mut buf, 16);
+        assert!(buf.iter().all(|&v| v == 90));
+    }
+
+    #[test]
+    fn edges_gain_contrast() {
+        // Vertical edge: dark gray left half, light gray right half.
+        let (w, h) = (16, 4);
+        let mut buf: Vec<u8> = (0..w * h)
+            .map(|i| if i % w < w / 2 { 60 } else { 190 })
+            .collect();
+        let config = SharpenConfig {
+            radius: 1.0,
+            amount: 1.0,
+            threshold: 0,
+        };
+        config.apply(&mut buf, w);
+        assert!(buf[w / 2 - 1] < 60, "dark side {}", buf[w / 2 - 1]);
+        assert!(buf[w / 2] > 190, "light side {}", buf[w / 2]);
+        assert_eq!(buf[0], 60);
+        assert_eq!(buf[w - 1], 190);
+    }
+
+    #[test]
+    fn threshold_skips_small_differences() {
+        let mut buf: Vec<u8> = (0..32).map(|i| if i < 16 { 100 } else { 104 }).collect();
+        let original = buf.clone();
+        let config = SharpenConfig {
+            radius: 1.0,
+            amount: 2.0,
+            threshold: 5,
+        };
+        config.apply(&mut buf, 32);
+        assert_eq!(buf, original);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for bad in [
+            SharpenConfig {
+                radius: 0.0,
+                ..SharpenConfig::default()
+            },
+            SharpenConfig {
+                amount: -1.0,
+                ..SharpenConfig::default()
+            },
+        ] {
+            assert!(matches!(
+                bad.validate(),
+                Err(EncodeError::InvalidSharpen(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Encode throughput measurement, for comparing dither algorithms on the
+//! machine doing the conversion.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    encode_buffer, DitherConfig, DitherMode, EncodeError, EncoderConfig, Kernel, MaskSize,
+};
+
+/// Every dither algorithm, with a stable display name.
+pub const DITHER_MODES: [(&str, DitherMode); 6] = [
+    (
+        "floyd_steinberg",
+        DitherMode::ErrorDiffusion {
+            kernel: Kernel::FloydSteinberg,
+            serpentine: false,
+        },
+    ),
+    (
+        "atkinson",
+        DitherMode::ErrorDiffusion {
+            kernel: Kernel::Atkinson,
+            serpentine: false,
+        },
+    ),
+    (
+        "sierra",
+        DitherMode::ErrorDiffusion {
+            kernel: Kernel::Sierra,
+            serpentine: false,
+        },
+    ),
+    (
+        "stucki",
+        DitherMode::ErrorDiffusion {
+            kernel: Kernel::Stucki,
+            serpentine: false,
+        },
+    ),
+    ("blue_noise_64", DitherMode::BlueNoise(MaskSize::Size64)),
+    ("blue_noise_128", DitherMode::BlueNoise(MaskSize::Size128)),
+];
+
+/// Timing of one algorithm over a run of pages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputSample {
+    pub name: &'static str,
+    pub dither: DitherMode,
+    pub pages: usize,
+    pub elapsed: Duration,
+    pub pages_per_second: f64,
+    pub megapixels_per_second: f64,
+}
+
+/// A deterministic `width` x `height` test page: a diagonal gradient with a
+/// band of text-like strokes, so both smooth and sharp content is timed.
+pub fn synthetic_page(width: u32, height: u32) -> Vec<u8> {
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            if y % 32 < 12 && x % 7 < 2 {
+                0
+            } else {
+                ((x + y) * 255 / (width + height).max(1)) as u8
+            }
+        })
+        .collect()
+}
+
+/// Encodes `pages` synthetic pages with every algorithm in [`DITHER_MODES`]
+/// and reports throughput. Apart from the dither mode, `config` is used as
+/// given. Pages run one at a time so the numbers reflect the per-page hot
+/// path rather than core count.
+pub fn encode_throughput_report(
+    config: &EncoderConfig,
+    width: u32,
+    height: u32,
+    pages: usize,
+) -> Result<Vec<ThroughputSample>, EncodeError> {
+    let page = synthetic_page(width, height);
+    let megapixels = f64::from(width) * f64::from(height) / 1e6;
+    DITHER_MODES
+        .iter()
+        .map(|&(name, dither)| {
+            let config = EncoderConfig {
+                dither: DitherConfig {
+                    algorithm: dither,
+                    ..config.dither
+                },
+                ..*config
+            };
+            let start = Instant::now();
+            for _ in 0..pages {
+                encode_buffer(&config, &page, width, height)?;
+            }
+            let elapsed = start.elapsed();
+            let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+            Ok(ThroughputSample {
+                name,
+                dither,
+                pages,
+                elapsed,
+                pages_per_second: pages as f64 / seconds,
+                megapixels_per_second: pages as f64 * megapixels / seconds,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_algorithm() {
+        let report = encode_throughput_report(&EncoderConfig::default(), 48, 80, 2).unwrap();
+        let names: Vec<_> = report.iter().map(|s| s.name).collect();
+        let expected: Vec<_> = DITHER_MODES.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, expected);
+        assert!(report
+            .iter()
+            .all(|s| s.pages == 2 && s.pages_per_second > 0.0));
+    }
+}
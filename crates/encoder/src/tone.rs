@@ -0,0 +1,252 @@
+//! Tone-curve adjustments applied before quantization.
+
+use serde::{Deserialize, Serialize};
+
+use crate::EncodeError;
+
+/// Gamma / brightness / contrast applied to the frame before it is dithered.
+///
+/// When `auto_contrast` is set the frame is first stretched so its clipped
+/// histogram spans the full 0..=255 range. The curve is then applied in order
+/// on a normalized 0.0..=1.0 scale: `v = v.powf(gamma)`, then
+/// `v = (v - 0.5) * contrast + 0.5`, then `v += brightness`. The default is
+/// the identity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneConfig {
+    /// Exponent on the normalized value; above 1.0 darkens midtones, which
+    /// counters the washed-out look of anti-aliased text on the panel.
+    pub gamma: f32,
+    /// Additive offset in -1.0..=1.0.
+    pub brightness: f32,
+    /// Slope around mid-gray; 1.0 leaves contrast unchanged.
+    pub contrast: f32,
+    /// Stretch black and white points from the page histogram. Useful for
+    /// scanned pages and faded covers.
+    pub auto_contrast: bool,
+    /// Percent of darkest pixels allowed to clip to black (0.0..=50.0).
+    pub black_clip: f32,
+    /// Percent of lightest pixels allowed to clip to white (0.0..=50.0).
+    pub white_clip: f32,
+}
+
+impl Default for ToneConfig {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            auto_contrast: false,
+            black_clip: 0.5,
+            white_clip: 0.5,
+        }
+    }
+}
+
+impl ToneConfig {
+    pub fn is_identity(&self) -> bool {
+        !self.auto_contrast && self.curve_is_identity()
+    }
+
+    fn curve_is_identity(&self) -> bool {
+        self.gamma == 1.0 && self.brightness == 0.0 && self.contrast == 1.0
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        if !(self.gamma.is_finite() && self.gamma > 0.0) {
+            return Err(EncodeError::InvalidTone("gamma must be a positive number"));
+        }
+        if !(self.brightness.is_finite() && (-1.0..=1.0).contains(&self.brightness)) {
+            return Err(EncodeError::InvalidTone(
+                "brightness must be within -1.0..=1.0",
+            ));
+        }
+        if !(self.contrast.is_finite() && self.contrast >= 0.0) {
+            return Err(EncodeError::InvalidTone(
+                "contrast must be a non-negative number",
+            ));
+        }
+        for clip in [self.black_clip, self.white_clip] {
+            if !(clip.is_finite() && (0.0..=50.0).contains(&clip)) {
+                return Err(EncodeError::InvalidTone(
+                    "clip percentiles must be within 0.0..=50.0",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the 256-entry lookup table for this curve.
+    pub(crate) fn lut(&self) -> [u8; 256] {
+        let gamma = f64::from(self.gamma);
+        let contrast = f64::from(self.contrast);
+        let brightness = f64::from(self.brightness);
+        let mut lut = [0u8; 256];
+        for (i, out) in lut.iter_mut().enumerate() {
+            let mut v = i as f64 / 255.0;
+            v = v.powf(gamma);
+            v = (v - 0.5) * contrast + 0.5;
+            v += brightness;
+            *out = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        lut
+    }
+
+    /// Applies auto-contrast and the curve in place. No-op for the identity.
+    pub(crate) fn apply(&self, buf: &mut [u8]) {
+        if self.auto_contrast {
+            if let Some(lut) = self.stretch_lut(buf) {
+                remap(buf, &lut);
+            }
+        }
+        if !self.curve_is_identity() {
+            remap(buf, &self.lut());
+        }
+    }
+
+    /// Lookup table stretching the clipped histogram of `buf` to 0..=255, or
+    /// `None` when the page is flat (nothing to stretch).
+    pub(crate) fn stretch_lut(&self, buf: &[u8]) -> Option<[u8; 256]> {
+        let mut histogram = [0u64; 256];
+        for &px in buf {
+            histogram[usize::from(px)] += 1;
+        }
+        let total = buf.len() as f64;
+        let black_budget = (total * f64::from(self.black_clip) / 100.0) as u64;
+        let white_budget = (total * f64::from(self.white_clip) / 100.0) as u64;
+
+        let low = percentile_edge(histogram.iter().copied(), black_budget);
+        let high = 255 - percentile_edge(histogram.iter().rev().copied(), white_budget);
+        if high <= low {
+            return None;
+        }
+
+        let (low, span) = (i32::from(low), i32::from(high - low));
+        let mut lut = [0u8; 256];
+        for (i, out) in lut.iter_mut().enumerate() {
+            let v = (i as i32 - low) * 255 + span / 2;
+            *out = (v / span).clamp(0, 255) as u8;
+        }
+        Some(lut)
+    }
+}
+
+/// Index of the first histogram bucket where the running count exceeds
+/// `budget`, i.e. how many levels may be clipped from this end.
+fn percentile_edge(buckets: impl Iterator<Item = u64>, budget: u64) -> u8 {
+    let mut seen = 0;
+    for (i, count) in buckets.enumerate() {
+        seen += count;
+        if seen > budget {
+            return i as u8;
+        }
+    }
+    255
+}
+
+fn remap(buf: &mut [u8], lut: &[u8; 256]) {
+    for px in buf {
+        *px = lut[usize::from(*px)];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_lut_is_identity() {
+        let lut = ToneConfig::default().lut();
+        assert!(lut.iter().enumerate().all(|(i, &v)| usize::from(v) == i));
+    }
+
+    #[test]
+    fn gamma_above_one_darkens_midtones() {
+        let tone = ToneConfig {
+            gamma: 1.8,
+            ..ToneConfig::default()
+        };
+        let lut = tone.lut();
+        assert_eq!(lut[0], 0);
+        assert_eq!(lut[255], 255);
+        assert!(lut[128] < 128);
+    }
+
+    #[test]
+    fn contrast_and_brightness_clamp() {
+        let tone = ToneConfig {
+            contrast: 3.0,
+            brightness: 0.2,
+            ..ToneConfig::default()
+        };
+        let lut = tone.lut();
+        assert_eq!(lut[0], 0);
+        assert_eq!(lut[200], 255);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let bad = [
+            ToneConfig {
+                gamma: 0.0,
+                ..ToneConfig::default()
+            },
+            ToneConfig {
+                brightness: 1.5,
+                ..ToneConfig::default()
+            },
+            ToneConfig {
+                contrast: f32::NAN,
+                ..ToneConfig::default()
+            },
+            ToneConfig {
+                white_clip: 60.0,
+                ..ToneConfig::default()
+            },
+        ];
+        for tone in bad {
+            assert!(matches!(tone.validate(), Err(EncodeError::InvalidTone(_))));
+        }
+    }
+
+    #[test]
+    fn auto_contrast_stretches_faded_page() {
+        let tone = ToneConfig {
+            auto_contrast: true,
+            black_clip: 0.0,
+            white_clip: 0.0,
+            ..ToneConfig::default()
+        };
+        let mut buf: Vec<u8> = (0..1000).map(|i| 80 + (i % 101) as u8).collect();
+        tone.apply(&mut buf);
+        assert_eq!(*buf.iter().min().unwrap(), 0);
+        assert_eq!(*buf.iter().max().unwrap(), 255);
+    }
+
+    #[test]
+    fn auto_contrast_clips_outliers() {
+        let tone = ToneConfig {
+            auto_contrast: true,
+            black_clip: 1.0,
+            white_clip: 1.0,
+            ..ToneConfig::default()
+        };
+        // 1% pure black specks on a 100..=200 page.
+        let mut buf = vec![0u8; 10];
+        buf.extend((0..990).map(|i| 100 + (i % 101) as u8));
+        let lut = tone.stretch_lut(&buf).unwrap();
+        assert_eq!(lut[100], 0);
+        assert_eq!(lut[200], 255);
+    }
+
+    #[test]
+    fn auto_contrast_leaves_flat_page_alone() {
+        let tone = ToneConfig {
+            auto_contrast: true,
+            ..ToneConfig::default()
+        };
+        let mut buf = vec![200u8; 64];
+        tone.apply(&mut buf);
+        assert!(buf.iter().all(|&v| v == 200));
+    }
+}
@@ -0,0 +1,436 @@
+//! Cover thumbnails, cached on disk.
+//!
+//! Covers are pulled out of the book on import and scaled once to every
+//! [`ThumbnailSize`], in grayscale (how the reader will show them) and in
+//! color (for the library view). Files live under the app's cache folder
+//! keyed by the book's content hash, so a book imported twice, or moved,
+//! shares one set and clearing the cache only costs a regeneration.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+
+use encoder::resize::resize_to_fit;
+use encoder::{Filter, Fit, Resize};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::extract::read_cover;
+use crate::{BookId, BookMetadata, Library, LibraryError};
+
+/// The fixed sizes thumbnails are generated at, in portrait 2:3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+    /// Grid and list views.
+    Small,
+    /// The book details view.
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [Self; 2] = [Self::Small, Self::Large];
+
+    /// Width and height in pixels.
+    pub fn dimensions(self) -> (u32, u32) {
+        match self {
+            Self::Small => (120, 180),
+            Self::Large => (300, 450),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Small => "small",
+            Self::Large => "large",
+        }
+    }
+}
+
+/// PNG files of one book's cover at one size. Covers that aren't 2:3 are
+/// centered on white.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverThumbnail {
+    pub gray: PathBuf,
+    pub color: PathBuf,
+}
+
+/// The folder thumbnails are cached in.
+#[derive(Debug, Clone)]
+pub struct CoverCache {
+    dir: PathBuf,
+}
+
+impl CoverCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn thumbnail(&self, hash: &str, size: ThumbnailSize) -> CoverThumbnail {
+        let dir = self.dir.join(hash);
+        CoverThumbnail {
+            gray: dir.join(format!("{}-gray.png", size.name())),
+            color: dir.join(format!("{}-color.png", size.name())),
+        }
+    }
+
+    /// Marks a book as having no cover, so we don't reopen it every time.
+    fn no_cover_marker(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash).join("none")
+    }
+
+    /// The cached thumbnail for `metadata`, generating the set if needed.
+    /// `None` for books without a cover or that were never fingerprinted.
+    pub(crate) fn get(
+        &self,
+        metadata: &BookMetadata,
+        size: ThumbnailSize,
+    ) -> Result<Option<CoverThumbnail>, LibraryError> {
+        let Some(hash) = &metadata.content_hash else {
+            return Ok(None);
+        };
+        let thumbnail = self.thumbnail(hash, size);
+        if thumbnail.gray.is_file() && thumbnail.color.is_file() {
+            return Ok(Some(thumbnail));
+        }
+        if self.no_cover_marker(hash).exists() || !self.generate(metadata)? {
+            return Ok(None);
+        }
+        Ok(Some(thumbnail))
+    }
+
+    /// Extracts the cover of `metadata` and writes every size. Returns
+    /// whether the book has a cover.
+    pub(crate) fn generate(&self, metadata: &BookMetadata) -> Result<bool, LibraryError> {
+        let Some(hash) = &metadata.content_hash else {
+            return Ok(false);
+        };
+        fs::create_dir_all(self.dir.join(hash))?;
+        let Some(data) = read_cover(&metadata.path)? else {
+            File::create(self.no_cover_marker(hash))?;
+            return Ok(false);
+        };
+        let path = &metadata.path;
+        let image = Rgb::decode(&data).map_err(|reason| LibraryError::Malformed {
+            path: path.clone(),
+            reason: format!("cover image: {reason}"),
+        })?;
+        let gray = image.luma();
+        for size in ThumbnailSize::ALL {
+            let thumbnail = self.thumbnail(hash, size);
+            let resize = Resize {
+                width: size.dimensions().0,
+                height: size.dimensions().1,
+                filter: Filter::CatmullRom,
+                fit: Fit::Contain,
+            };
+            let scale = |plane: &[u8]| {
+                resize_to_fit(plane, (image.width, image.height), &resize).map_err(|err| {
+                    LibraryError::Malformed {
+                        path: path.clone(),
+                        reason: format!("cover image: {err}"),
+                    }
+                })
+            };
+            let planes = image
+                .planes()
+                .iter()
+                .map(|plane| scale(plane))
+                .collect::<Result<Vec<_>, _>>()?;
+            let color: Vec<u8> = (0..planes[0].len())
+                .flat_map(|i| planes.iter().map(move |plane| plane[i]))
+                .collect();
+            write_png(
+                &thumbnail.gray,
+                size,
+                png::ColorType::Grayscale,
+                &scale(&gray)?,
+            )?;
+            write_png(&thumbnail.color, size, png::ColorType::Rgb, &color)?;
+        }
+        debug!(path = %path.display(), "cached cover thumbnails");
+        Ok(true)
+    }
+}
+
+/// Writes through a temporary file so a half-written PNG is never served.
+fn write_png(
+    path: &Path,
+    size: ThumbnailSize,
+    color: png::ColorType,
+    data: &[u8],
+) -> Result<(), LibraryError> {
+    let tmp = path.with_extension("png.tmp");
+    let (width, height) = size.dimensions();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(&tmp)?), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(data)?;
+            writer.finish()
+        })
+        .map_err(io::Error::other)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// A decoded cover, RGB8 interleaved.
+struct Rgb {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Largest cover decoded, in pixels. A few bytes of image header can
+/// claim any size, so larger ones are refused before anything is
+/// allocated.
+const MAX_COVER_PIXELS: usize = 8192 * 8192;
+
+/// Bytes a decoder may allocate: four per pixel covers RGBA and CMYK.
+const MAX_COVER_BYTES: usize = MAX_COVER_PIXELS * 4;
+
+fn check_dimensions(width: u32, height: u32) -> Result<(), String> {
+    if u64::from(width) * u64::from(height) > MAX_COVER_PIXELS as u64 {
+        return Err(format!("{width}x{height} is too large for a cover"));
+    }
+    Ok(())
+}
+
+impl Rgb {
+    /// Decodes a PNG or JPEG, told apart by signature. Transparency is
+    /// composited onto white.
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        if data.starts_with(b"\x89PNG") {
+            Self::decode_png(data)
+        } else {
+            Self::decode_jpeg(data)
+        }
+    }
+
+    fn decode_png(data: &[u8]) -> Result<Self, String> {
+        let limits = png::Limits {
+            bytes: MAX_COVER_BYTES,
+        };
+        let mut decoder = png::Decoder::new_with_limits(Cursor::new(data), limits);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        check_dimensions(reader.info().width, reader.info().height)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+        let pixels = &buf[..info.buffer_size()];
+        let pixels = match info.color_type {
+            png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|p| [over_white(p[0], p[1]); 3])
+                .collect(),
+            png::ColorType::Rgb => pixels.to_vec(),
+            png::ColorType::Rgba => pixels
+                .chunks_exact(4)
+                .flat_map(|p| [0, 1, 2].map(|i| over_white(p[i], p[3])))
+                .collect(),
+            // EXPAND turns palettes into RGB(A).
+            png::ColorType::Indexed => unreachable!("palette left unexpanded"),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    fn decode_jpeg(data: &[u8]) -> Result<Self, String> {
+        let mut decoder = jpeg_decoder::Decoder::new(data);
+        decoder.set_max_decoding_buffer_size(MAX_COVER_BYTES);
+        decoder.read_info().map_err(|e| e.to_string())?;
+        let info = decoder.info().ok_or("missing JPEG header")?;
+        check_dimensions(info.width.into(), info.height.into())?;
+        let pixels = decoder.decode().map_err(|e| e.to_string())?;
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|&g| [g; 3]).collect(),
+            // Big-endian samples; keep the high byte.
+            jpeg_decoder::PixelFormat::L16 => {
+                pixels.chunks_exact(2).flat_map(|p| [p[0]; 3]).collect()
+            }
+            jpeg_decoder::PixelFormat::RGB24 => pixels,
+            jpeg_decoder::PixelFormat::CMYK32 => pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let k = 255 - u16::from(p[3]);
+                    [0, 1, 2].map(|i| ((255 - u16::from(p[i])) * k / 255) as u8)
+                })
+                .collect(),
+        };
+        Ok(Self {
+            width: info.width.into(),
+            height: info.height.into(),
+            pixels,
+        })
+    }
+
+    /// Rec. 601 luma.
+    fn luma(&self) -> Vec<u8> {
+        self.pixels
+            .chunks_exact(3)
+            .map(|p| {
+                let [r, g, b] = [0, 1, 2].map(|i| u32::from(p[i]));
+                ((299 * r + 587 * g + 114 * b + 500) / 1000) as u8
+            })
+            .collect()
+    }
+
+    /// The red, green and blue channels as separate grayscale planes, for
+    /// the grayscale scaler.
+    fn planes(&self) -> [Vec<u8>; 3] {
+        [0, 1, 2].map(|c| self.pixels.iter().skip(c).step_by(3).copied().collect())
+    }
+}
+
+fn over_white(value: u8, alpha: u8) -> u8 {
+    let (value, alpha) = (u32::from(value), u32::from(alpha));
+    ((value * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+}
+
+impl Library {
+    /// Generates cover thumbnails into `cache` for books imported from now
+    /// on, and serves [`cover_thumbnail`](Self::cover_thumbnail) from it.
+    pub fn with_cover_cache(mut self, cache: CoverCache) -> Self {
+        self.covers = Some(cache);
+        self
+    }
+
+    /// Book `id`'s cover at `size`, generated on first use if the import
+    /// didn't already. `None` if the book has no cover or the library has
+    /// no cover cache.
+    pub fn cover_thumbnail(
+        &self,
+        id: BookId,
+        size: ThumbnailSize,
+    ) -> Result<Option<CoverThumbnail>, LibraryError> {
+        let book = self.get(id)?.ok_or(LibraryError::NotFound(id))?;
+        match &self.covers {
+            Some(cache) => cache.get(&book.metadata, size),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::*;
+
+    /// A 4x2 PNG, red on the left half and blue on the right.
+    fn cover_png() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 4, 2);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        let row = [[255, 0, 0], [255, 0, 0], [0, 0, 255], [0, 0, 255]].concat();
+        writer.write_image_data(&row.repeat(2)).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    fn read_png(path: &Path) -> (png::ColorType, u32, u32) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let info = decoder.read_info().unwrap().info().clone();
+        (info.color_type, info.width, info.height)
+    }
+
+    #[test]
+    fn refuses_covers_too_large_to_decode() {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 65535, 65535);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_chunk(png::chunk::IDAT, &[0; 16]).unwrap();
+        drop(writer);
+        let Err(err) = Rgb::decode(&data) else {
+            panic!("decoded a 65535x65535 cover");
+        };
+        assert!(err.contains("65535x65535"), "{err}");
+    }
+
+    #[test]
+    fn caches_thumbnails_by_content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = dir.path().join("Picnic.fb2");
+        let cover = base64::engine::general_purpose::STANDARD.encode(cover_png());
+        fs::write(
+            &book,
+            format!(
+                r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description><title-info>
+    <book-title>Roadside Picnic</book-title>
+    <coverpage><image l:href="#cover.png"/></coverpage>
+  </title-info></description>
+  <body/>
+  <binary id="cover.png" content-type="image/png">
+{cover}
+  </binary>
+</FictionBook>"##
+            ),
+        )
+        .unwrap();
+        fs::write(dir.path().join("notes.txt"), b"no cover here").unwrap();
+
+        let cache = dir.path().join("covers");
+        let library = Library::open_in_memory()
+            .unwrap()
+            .with_cover_cache(CoverCache::new(&cache));
+        let report = library.import_dir(dir.path()).unwrap();
+        let [picnic, notes] = report.added[..] else {
+            panic!("expected two books, got {report:?}");
+        };
+
+        let hash = library
+            .get(picnic)
+            .unwrap()
+            .unwrap()
+            .metadata
+            .content_hash
+            .unwrap();
+        let small = library
+            .cover_thumbnail(picnic, ThumbnailSize::Small)
+            .unwrap()
+            .unwrap();
+        assert!(small.gray.starts_with(cache.join(&hash)));
+        assert_eq!(read_png(&small.gray), (png::ColorType::Grayscale, 120, 180));
+        assert_eq!(read_png(&small.color), (png::ColorType::Rgb, 120, 180));
+        let large = library
+            .cover_thumbnail(picnic, ThumbnailSize::Large)
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_png(&large.color), (png::ColorType::Rgb, 300, 450));
+
+        // Regenerated when the cache has been cleared.
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(
+            library
+                .cover_thumbnail(picnic, ThumbnailSize::Small)
+                .unwrap(),
+            Some(small)
+        );
+
+        assert_eq!(
+            library
+                .cover_thumbnail(notes, ThumbnailSize::Small)
+                .unwrap(),
+            None
+        );
+        assert!(matches!(
+            library.cover_thumbnail(BookId(99), ThumbnailSize::Small),
+            Err(LibraryError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,542 @@
+//! Reading metadata from inside book files.
+//!
+//! EPUB metadata comes from the package (OPF) document and FB2 metadata
+//! from `<title-info>`. Calibre's series tags and EPUB 3 collections are
+//! both understood. Other formats only get what the file name says.
+//!
+//! [`read_cover`] finds the cover image the same way: the EPUB manifest's
+//! cover item, or the image FB2's `<coverpage>` points at.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use base64::Engine;
+use roxmltree::{Document, Node, ParsingOptions};
+use zip::ZipArchive;
+
+use crate::{BookFormat, BookMetadata, LibraryError};
+
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Largest EPUB entry read into memory. Books are untrusted, and a small
+/// deflate bomb would otherwise be inflated in full.
+const MAX_ENTRY_SIZE: u64 = 16 << 20;
+
+/// Largest FB2 file read; they embed their images, so allow more.
+const MAX_FB2_SIZE: u64 = 64 << 20;
+
+impl BookMetadata {
+    /// Reads what `path` says about itself. Missing fields stay empty and
+    /// a book without a title is titled after its file name. Fails on
+    /// files that are not the format their extension claims.
+    pub fn read(path: &Path) -> Result<Self, LibraryError> {
+        let mut metadata = match BookFormat::from_path(path) {
+            Some(BookFormat::Epub) => read_epub(path)?,
+            Some(BookFormat::Fb2) => read_fb2(path)?,
+            _ => return Ok(Self::from_path(path)),
+        };
+        if metadata.title.is_empty() {
+            metadata.title = Self::from_path(path).title;
+        }
+        Ok(metadata)
+    }
+}
+
+/// The bytes of the book's cover image, if it has one. The image format
+/// is whatever the book embeds, normally JPEG or PNG.
+pub(crate) fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, LibraryError> {
+    match BookFormat::from_path(path) {
+        Some(BookFormat::Epub) => epub_cover(path),
+        Some(BookFormat::Fb2) => fb2_cover(path),
+        _ => Ok(None),
+    }
+}
+
+/// An open EPUB and the text of its package document.
+struct Epub<'p> {
+    path: &'p Path,
+    archive: ZipArchive<File>,
+    opf_path: String,
+    opf: String,
+}
+
+impl<'p> Epub<'p> {
+    fn open(path: &'p Path) -> Result<Self, LibraryError> {
+        let archive = ZipArchive::new(File::open(path)?).map_err(|err| malformed(path, err))?;
+        let mut epub = Self {
+            path,
+            archive,
+            opf_path: String::new(),
+            opf: String::new(),
+        };
+        let container = String::from_utf8(epub.entry("META-INF/container.xml")?)
+            .map_err(|err| malformed(path, err))?;
+        epub.opf_path = parse(path, &container)?
+            .descendants()
+            .find(|n| n.has_tag_name("rootfile"))
+            .and_then(|n| n.attribute("full-path"))
+            .ok_or_else(|| malformed(path, "container.xml names no package document"))?
+            .to_owned();
+        epub.opf = String::from_utf8(epub.entry(&epub.opf_path.clone())?)
+            .map_err(|err| malformed(path, err))?;
+        Ok(epub)
+    }
+
+    fn entry(&mut self, name: &str) -> Result<Vec<u8>, LibraryError> {
+        let too_big = || {
+            malformed(
+                self.path,
+                format!("{name} is larger than {MAX_ENTRY_SIZE} bytes"),
+            )
+        };
+        let file = self
+            .archive
+            .by_name(name)
+            .map_err(|err| malformed(self.path, format!("{name}: {err}")))?;
+        if file.size() > MAX_ENTRY_SIZE {
+            return Err(too_big());
+        }
+        // The declared size may lie; never inflate past the cap.
+        let mut data = Vec::new();
+        file.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(too_big());
+        }
+        Ok(data)
+    }
+
+    /// The archive path of `href`, which is relative to the package
+    /// document.
+    fn resolve(&self, href: &str) -> String {
+        let mut parts: Vec<&str> = self.opf_path.split('/').collect();
+        parts.pop();
+        for part in href.split('/') {
+            match part {
+                "." | "" => {}
+                ".." => {
+                    parts.pop();
+                }
+                _ => parts.push(part),
+            }
+        }
+        parts.join("/")
+    }
+}
+
+fn read_epub(path: &Path) -> Result<BookMetadata, LibraryError> {
+    let epub = Epub::open(path)?;
+    let opf = parse(path, &epub.opf)?;
+    let meta = opf
+        .descendants()
+        .find(|n| n.has_tag_name("metadata"))
+        .ok_or_else(|| malformed(path, "package document has no <metadata>"))?;
+
+    let mut book = BookMetadata::new("", path);
+    let dc = |name: &'static str| {
+        meta.children()
+            .filter(move |n| n.tag_name().namespace() == Some(DC) && n.tag_name().name() == name)
+            .map(text)
+            .filter(|s| !s.is_empty())
+    };
+    book.title = dc("title").next().unwrap_or_default();
+    book.authors = dc("creator").collect();
+    book.language = dc("language").next();
+    book.publisher = dc("publisher").next();
+    book.subjects = dc("subject").collect();
+    book.description = dc("description").next();
+    // EPUB 2 may list several dates; the publication date is the one
+    // that says so, or else the first.
+    book.published = meta
+        .children()
+        .filter(|n| n.has_tag_name((DC, "date")))
+        .reduce(|first, n| match n.attributes().any(is_publication) {
+            true if !first.attributes().any(is_publication) => n,
+            _ => first,
+        })
+        .map(text)
+        .filter(|s| !s.is_empty());
+
+    let metas = || meta.children().filter(|n| n.has_tag_name("meta"));
+    let calibre = |name: &str| {
+        metas()
+            .find(|n| n.attribute("name") == Some(name))
+            .and_then(|n| n.attribute("content"))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+    };
+    if let Some(series) = calibre("calibre:series") {
+        book.series = Some(series.to_owned());
+        book.series_index = calibre("calibre:series_index").and_then(|i| i.parse().ok());
+    } else if let Some(collection) =
+        metas().find(|n| n.attribute("property") == Some("belongs-to-collection"))
+    {
+        book.series = Some(text(collection)).filter(|s| !s.is_empty());
+        book.series_index = collection.attribute("id").and_then(|id| {
+            metas()
+                .find(|n| {
+                    n.attribute("property") == Some("group-position")
+                        && n.attribute("refines").and_then(|r| r.strip_prefix('#')) == Some(id)
+                })
+                .and_then(|n| text(n).parse().ok())
+        });
+    }
+    Ok(book)
+}
+
+/// The EPUB 3 `cover-image` item, else the item EPUB 2's `cover` meta
+/// names, else an image item that calls itself a cover.
+fn epub_cover(path: &Path) -> Result<Option<Vec<u8>>, LibraryError> {
+    let mut epub = Epub::open(path)?;
+    let opf = parse(path, &epub.opf)?;
+    let items: Vec<Node<'_, '_>> = opf
+        .descendants()
+        .filter(|n| n.has_tag_name("item"))
+        .collect();
+    let is_image = |n: &Node<'_, '_>| {
+        n.attribute("media-type")
+            .is_some_and(|t| t.starts_with("image/"))
+    };
+    let cover_id = opf
+        .descendants()
+        .find(|n| n.has_tag_name("meta") && n.attribute("name") == Some("cover"))
+        .and_then(|n| n.attribute("content"));
+    let item = items
+        .iter()
+        .find(|n| {
+            n.attribute("properties")
+                .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+        })
+        .or_else(|| {
+            let id = cover_id?;
+            items.iter().find(|n| n.attribute("id") == Some(id))
+        })
+        .or_else(|| {
+            items.iter().find(|n| {
+                is_image(n)
+                    && ["id", "href"].iter().any(|attr| {
+                        n.attribute(*attr)
+                            .is_some_and(|v| v.to_ascii_lowercase().contains("cover"))
+                    })
+            })
+        });
+    let Some(href) = item.and_then(|n| n.attribute("href")) else {
+        return Ok(None);
+    };
+    let name = epub.resolve(href);
+    epub.entry(&name).map(Some)
+}
+
+/// The `<binary>` that `<coverpage>`'s first image links to.
+fn fb2_cover(path: &Path) -> Result<Option<Vec<u8>>, LibraryError> {
+    let xml = read_fb2_text(path)?;
+    let doc = parse(path, &xml)?;
+    let Some(id) = doc
+        .descendants()
+        .find(|n| n.has_tag_name("coverpage"))
+        .and_then(|cover| cover.descendants().find(|n| n.tag_name().name() == "image"))
+        .and_then(|image| image.attributes().find(|a| a.name() == "href"))
+        .and_then(|href| href.value().strip_prefix('#'))
+    else {
+        return Ok(None);
+    };
+    let Some(binary) = doc
+        .descendants()
+        .find(|n| n.tag_name().name() == "binary" && n.attribute("id") == Some(id))
+    else {
+        return Ok(None);
+    };
+    let encoded: String = binary
+        .text()
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map(Some)
+        .map_err(|err| malformed(path, format!("cover image: {err}")))
+}
+
+/// Non-UTF-8 FB2s (mostly windows-1251) fail here and fall back to the
+/// file name.
+fn read_fb2_text(path: &Path) -> Result<String, LibraryError> {
+    if fs::metadata(path)?.len() > MAX_FB2_SIZE {
+        return Err(malformed(path, format!("larger than {MAX_FB2_SIZE} bytes")));
+    }
+    String::from_utf8(fs::read(path)?).map_err(|err| malformed(path, err))
+}
+
+fn read_fb2(path: &Path) -> Result<BookMetadata, LibraryError> {
+    let xml = read_fb2_text(path)?;
+    let doc = parse(path, &xml)?;
+    let info = doc
+        .descendants()
+        .find(|n| n.has_tag_name("title-info"))
+        .ok_or_else(|| malformed(path, "no <title-info>"))?;
+    let child = |node: Node<'_, '_>, name: &str| {
+        node.children()
+            .find(|n| n.tag_name().name() == name)
+            .map(text)
+            .filter(|s| !s.is_empty())
+    };
+
+    let mut book = BookMetadata::new(child(info, "book-title").unwrap_or_default(), path);
+    book.authors = info
+        .children()
+        .filter(|n| n.tag_name().name() == "author")
+        .filter_map(|author| {
+            let name = ["first-name", "middle-name", "last-name"]
+                .iter()
+                .filter_map(|part| child(author, part))
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some(name)
+                .filter(|s| !s.is_empty())
+                .or_else(|| child(author, "nickname"))
+        })
+        .collect();
+    book.language = child(info, "lang");
+    book.description = child(info, "annotation");
+    book.subjects = info
+        .children()
+        .filter(|n| n.tag_name().name() == "genre")
+        .map(text)
+        .filter(|s| !s.is_empty())
+        .collect();
+    let publish = doc.descendants().find(|n| n.has_tag_name("publish-info"));
+    book.publisher = publish.and_then(|p| child(p, "publisher"));
+    // The edition's year, or else when the work was written.
+    book.published = publish.and_then(|p| child(p, "year")).or_else(|| {
+        let date = info.children().find(|n| n.tag_name().name() == "date")?;
+        date.attribute("value")
+            .map(str::to_owned)
+            .or_else(|| Some(text(date)).filter(|s| !s.is_empty()))
+    });
+    if let Some(sequence) = info.children().find(|n| n.tag_name().name() == "sequence") {
+        book.series = sequence
+            .attribute("name")
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned);
+        book.series_index = sequence
+            .attribute("number")
+            .and_then(|i| i.trim().parse().ok());
+    }
+    Ok(book)
+}
+
+fn is_publication(attr: roxmltree::Attribute<'_, '_>) -> bool {
+    attr.name() == "event" && attr.value() == "publication"
+}
+
+fn parse<'a>(path: &Path, xml: &'a str) -> Result<Document<'a>, LibraryError> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(xml, options).map_err(|err| malformed(path, err))
+}
+
+/// All text under `node`, with runs of whitespace collapsed.
+fn text(node: Node<'_, '_>) -> String {
+    let raw: String = node
+        .descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect();
+    raw.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn malformed(path: &Path, reason: impl ToString) -> LibraryError {
+    LibraryError::Malformed {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn write_epub(path: &Path, metadata: &str) {
+        write_epub_with(path, metadata, "", &[]);
+    }
+
+    fn write_epub_with(path: &Path, metadata: &str, manifest: &str, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = SimpleFileOptions::default();
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(
+            br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#,
+        )
+        .unwrap();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        write!(
+            zip,
+            r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">{metadata}</metadata>
+  <manifest>{manifest}</manifest>
+</package>"#
+        )
+        .unwrap();
+        for (name, data) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn reads_epub_package_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dune_messiah.epub");
+        write_epub(
+            &path,
+            r##"<dc:title>Dune Messiah</dc:title>
+               <dc:creator>Frank Herbert</dc:creator>
+               <dc:creator>  </dc:creator>
+               <dc:language>en</dc:language>
+               <dc:date opf:event="creation">1968</dc:date>
+               <dc:date opf:event="publication">1969-10-15</dc:date>
+               <dc:publisher>Putnam</dc:publisher>
+               <dc:subject>Science Fiction</dc:subject>
+               <dc:description>Twelve years
+                 after Arrakis.</dc:description>
+               <meta property="belongs-to-collection" id="c1">Dune</meta>
+               <meta refines="#c1" property="group-position">2</meta>"##,
+        );
+        let book = BookMetadata::read(&path).unwrap();
+        assert_eq!(book.title, "Dune Messiah");
+        assert_eq!(book.authors, ["Frank Herbert"]);
+        assert_eq!(book.language.as_deref(), Some("en"));
+        assert_eq!(book.published.as_deref(), Some("1969-10-15"));
+        assert_eq!(book.publisher.as_deref(), Some("Putnam"));
+        assert_eq!(book.subjects, ["Science Fiction"]);
+        assert_eq!(book.format, Some(BookFormat::Epub));
+        assert_eq!(
+            book.description.as_deref(),
+            Some("Twelve years after Arrakis.")
+        );
+        assert_eq!(book.series.as_deref(), Some("Dune"));
+        assert_eq!(book.series_index, Some(2.0));
+
+        assert_eq!(read_cover(&path).unwrap(), None);
+
+        write_epub(
+            &path,
+            r#"<meta name="calibre:series" content="Dune"/>
+               <meta name="calibre:series_index" content="2.5"/>"#,
+        );
+        let book = BookMetadata::read(&path).unwrap();
+        assert_eq!(book.title, "dune_messiah");
+        assert_eq!(book.series_index, Some(2.5));
+
+        fs::write(&path, b"not a zip").unwrap();
+        assert!(matches!(
+            BookMetadata::read(&path),
+            Err(LibraryError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn finds_epub_covers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.epub");
+        write_epub_with(
+            &path,
+            r#"<meta name="cover" content="front"/>"#,
+            r#"<item id="ch1" href="text/ch1.xhtml" media-type="application/xhtml+xml"/>
+               <item id="front" href="../Images/front.jpg" media-type="image/jpeg"/>"#,
+            &[("Images/front.jpg", b"jpeg bytes")],
+        );
+        assert_eq!(
+            read_cover(&path).unwrap().as_deref(),
+            Some(&b"jpeg bytes"[..])
+        );
+
+        write_epub_with(
+            &path,
+            "",
+            r#"<item id="c" href="images/c.png" media-type="image/png" properties="cover-image"/>"#,
+            &[("OEBPS/images/c.png", b"png bytes")],
+        );
+        assert_eq!(
+            read_cover(&path).unwrap().as_deref(),
+            Some(&b"png bytes"[..])
+        );
+    }
+
+    #[test]
+    fn refuses_oversized_entries_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.epub");
+        let huge = vec![0; MAX_ENTRY_SIZE as usize + 1];
+        write_epub_with(
+            &path,
+            "<dc:title>Bomb</dc:title>",
+            r#"<item id="c" href="c.png" media-type="image/png" properties="cover-image"/>"#,
+            &[("OEBPS/c.png", &huge)],
+        );
+        assert_eq!(BookMetadata::read(&path).unwrap().title, "Bomb");
+        assert!(matches!(
+            read_cover(&path),
+            Err(LibraryError::Malformed { reason, .. }) if reason.contains("OEBPS/c.png")
+        ));
+
+        let fb2 = dir.path().join("book.fb2");
+        File::create(&fb2)
+            .unwrap()
+            .set_len(MAX_FB2_SIZE + 1)
+            .unwrap();
+        assert!(matches!(
+            BookMetadata::read(&fb2),
+            Err(LibraryError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_fb2_title_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.fb2");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0">
+  <description><title-info>
+    <author><first-name>Arkady</first-name><last-name>Strugatsky</last-name></author>
+    <author><nickname>Boris</nickname></author>
+    <genre>sf_social</genre>
+    <date value="1971-01-01">1971</date>
+    <book-title>Roadside Picnic</book-title>
+    <annotation><p>The Zone.</p></annotation>
+    <lang>ru</lang>
+    <sequence name="Noon Universe" number="3"/>
+  </title-info>
+  <publish-info><publisher>Macmillan</publisher><year>1977</year></publish-info>
+  </description>
+  <body/>
+</FictionBook>"#,
+        )
+        .unwrap();
+        let book = BookMetadata::read(&path).unwrap();
+        assert_eq!(book.title, "Roadside Picnic");
+        assert_eq!(book.authors, ["Arkady Strugatsky", "Boris"]);
+        assert_eq!(book.language.as_deref(), Some("ru"));
+        assert_eq!(book.description.as_deref(), Some("The Zone."));
+        assert_eq!(book.series.as_deref(), Some("Noon Universe"));
+        assert_eq!(book.series_index, Some(3.0));
+        assert_eq!(book.subjects, ["sf_social"]);
+        assert_eq!(book.publisher.as_deref(), Some("Macmillan"));
+        assert_eq!(book.published.as_deref(), Some("1977"));
+    }
+}
@@ -0,0 +1,488 @@
+//! Bringing book files into the library.
+//!
+//! [`Library::import_dir`] scans a folder tree once. With the `watch`
+//! feature a [`FolderWatcher`] also notices books dropped into the user's
+//! library folders later, and [`Library::import_watched`] imports them as
+//! they arrive.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::{BookFormat, BookId, BookMetadata, Library, LibraryError};
+
+/// Extensions of the book formats we import.
+pub const BOOK_EXTENSIONS: &[&str] = &["epub", "fb2", "mobi", "txt"];
+
+/// Whether `path` names a book format we import.
+pub fn is_importable(path: &Path) -> bool {
+    BookFormat::from_path(path).is_some()
+}
+
+/// What happened to one file during an import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImportEvent {
+    Added {
+        id: BookId,
+        path: PathBuf,
+    },
+    /// Already in the library, but the file changed since, so its
+    /// metadata was read again.
+    Updated {
+        id: BookId,
+        path: PathBuf,
+    },
+    /// Already in the library and unchanged.
+    Skipped {
+        path: PathBuf,
+    },
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+/// Totals of an [`Library::import_dir`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub added: Vec<BookId>,
+    pub updated: Vec<BookId>,
+    pub skipped: usize,
+    /// Files that could not be imported, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl ImportReport {
+    fn record(&mut self, event: ImportEvent) {
+        match event {
+            ImportEvent::Added { id, .. } => self.added.push(id),
+            ImportEvent::Updated { id, .. } => self.updated.push(id),
+            ImportEvent::Skipped { .. } => self.skipped += 1,
+            ImportEvent::Failed { path, error } => self.failed.push((path, error)),
+        }
+    }
+}
+
+impl Library {
+    /// Adds every book under `dir`, recursing into subfolders but not
+    /// hidden ones. Books already in the library are skipped, and a file
+    /// that fails is reported without stopping the scan.
+    pub fn import_dir(&self, dir: &Path) -> Result<ImportReport, LibraryError> {
+        let mut report = ImportReport::default();
+        let walk = WalkDir::new(dir)
+            .follow_links(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.path()));
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                // The root itself missing is the caller's mistake; anything
+                // deeper is one unreadable folder.
+                Err(err) if err.depth() == 0 => return Err(err.into()),
+                Err(err) => {
+                    warn!(%err, "skipping unreadable path during import");
+                    continue;
+                }
+            };
+            if entry.file_type().is_file() && is_importable(entry.path()) {
+                report.record(self.import_file(entry.path()));
+            }
+        }
+        debug!(dir = %dir.display(), added = report.added.len(), "imported folder");
+        Ok(report)
+    }
+
+    /// Adds one book file, reading its metadata from the file. A book
+    /// whose metadata can't be read is still added, titled after its file
+    /// name. A book already in the library is read again only if its size
+    /// or modification time changed since.
+    pub fn import_file(&self, path: &Path) -> ImportEvent {
+        let path = path.to_path_buf();
+        match self.import_or_refresh(&path) {
+            Ok(Some((id, false))) => ImportEvent::Added { id, path },
+            Ok(Some((id, true))) => ImportEvent::Updated { id, path },
+            Ok(None) => ImportEvent::Skipped { path },
+            Err(err) => ImportEvent::Failed {
+                path,
+                error: err.to_string(),
+            },
+        }
+    }
+
+    /// The book's id and whether it was already there, or `None` if it
+    /// was there and unchanged.
+    fn import_or_refresh(&self, path: &Path) -> Result<Option<(BookId, bool)>, LibraryError> {
+        let found = self.find_by_path(path)?;
+        if let Some(book) = &found {
+            if !book.metadata.is_stale()? {
+                return Ok(None);
+            }
+        }
+        let mut metadata = BookMetadata::read(path).unwrap_or_else(|err| {
+            warn!(%err, "falling back to the file name");
+            BookMetadata::from_path(path)
+        });
+        metadata.fingerprint()?;
+        let id = match &found {
+            Some(book) => {
+                self.update(book.id, &metadata)?;
+                book.id
+            }
+            None => self.add(&metadata)?,
+        };
+        if let Some(covers) = &self.covers {
+            if let Err(err) = covers.generate(&metadata) {
+                warn!(%err, "no cover thumbnails");
+            }
+        }
+        Ok(Some((id, found.is_some())))
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(feature = "watch")]
+pub use watch::FolderWatcher;
+
+#[cfg(feature = "watch")]
+mod watch {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::ops::ControlFlow;
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+
+    use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind, RenameMode};
+    use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+    use super::*;
+
+    /// How long a file's size must stay put before it counts as copied,
+    /// on platforms that don't report a writer closing it.
+    const SETTLE: Duration = Duration::from_secs(2);
+
+    /// How often files still being written are looked at.
+    const POLL: Duration = Duration::from_millis(250);
+
+    /// What a file system event says about a file.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Signal {
+        /// A writer closed it, or it was moved in whole.
+        Complete,
+        /// It appeared or changed and may still be being written.
+        Changing,
+    }
+
+    /// A file seen changing, with its size when last looked at.
+    struct Pending {
+        size: Option<u64>,
+        since: Instant,
+    }
+
+    /// Watches library folders for new book files.
+    pub struct FolderWatcher {
+        // Stops watching when dropped.
+        _watcher: RecommendedWatcher,
+        folders: Vec<PathBuf>,
+        events: Receiver<(PathBuf, Signal)>,
+        pending: HashMap<PathBuf, Pending>,
+    }
+
+    impl std::fmt::Debug for FolderWatcher {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("FolderWatcher").finish_non_exhaustive()
+        }
+    }
+
+    impl FolderWatcher {
+        /// Starts watching every folder in `folders` and their subfolders.
+        pub fn new(folders: &[PathBuf]) -> Result<Self, LibraryError> {
+            let (tx, events) = mpsc::channel();
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        warn!(%err, "library folder watch error");
+                        return;
+                    }
+                };
+                let Some(signal) = signal(&event.kind) else {
+                    return;
+                };
+                for path in event.paths {
+                    let _ = tx.send((path, signal));
+                }
+            })?;
+            for folder in folders {
+                watcher.watch(folder, RecursiveMode::Recursive)?;
+            }
+            Ok(Self {
+                _watcher: watcher,
+                folders: folders.to_vec(),
+                events,
+                pending: HashMap::new(),
+            })
+        }
+
+        /// The next book file that finished arriving. Gives up once
+        /// nothing has happened for `timeout`, but not while a file is
+        /// still being written.
+        pub fn next_file(&mut self, timeout: Duration) -> Option<PathBuf> {
+            let mut deadline = Instant::now() + timeout;
+            loop {
+                if let Some(path) = self.settled() {
+                    return Some(path);
+                }
+                let now = Instant::now();
+                let wait = match self.pending.is_empty() {
+                    true if now >= deadline => return None,
+                    true => deadline - now,
+                    false => POLL,
+                };
+                match self.events.recv_timeout(wait) {
+                    Ok((path, Signal::Complete)) => {
+                        self.pending.remove(&path);
+                        if self.is_candidate(&path) {
+                            return Some(path);
+                        }
+                    }
+                    Ok((path, Signal::Changing)) => {
+                        if is_importable(&path) {
+                            deadline = Instant::now() + timeout;
+                            let size = fs::metadata(&path).ok().map(|m| m.len());
+                            self.pending.insert(
+                                path,
+                                Pending {
+                                    size,
+                                    since: Instant::now(),
+                                },
+                            );
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            }
+        }
+
+        /// A pending file whose size hasn't changed for [`SETTLE`].
+        fn settled(&mut self) -> Option<PathBuf> {
+            let now = Instant::now();
+            let mut ready = None;
+            self.pending.retain(|path, pending| {
+                let Ok(meta) = fs::metadata(path) else {
+                    return false;
+                };
+                if pending.size != Some(meta.len()) {
+                    *pending = Pending {
+                        size: Some(meta.len()),
+                        since: now,
+                    };
+                    true
+                } else if ready.is_none() && now - pending.since >= SETTLE {
+                    ready = Some(path.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+            ready.filter(|path| self.is_candidate(path))
+        }
+
+        /// A book file not inside a hidden folder of a watched folder.
+        fn is_candidate(&self, path: &Path) -> bool {
+            let visible = |root: &PathBuf| {
+                path.strip_prefix(root).is_ok_and(|rest| {
+                    rest.parent().is_none_or(|dirs| {
+                        !dirs.iter().any(|d| d.to_string_lossy().starts_with('.'))
+                    })
+                })
+            };
+            is_importable(path) && path.is_file() && self.folders.iter().any(visible)
+        }
+    }
+
+    /// A new file isn't imported when it is created, since it is usually
+    /// still empty then. Where the platform reports the writer closing it
+    /// that is the cue; elsewhere the file is watched until its size
+    /// settles. Copies show up as several events; importing is
+    /// idempotent, so that is harmless.
+    fn signal(kind: &EventKind) -> Option<Signal> {
+        match kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both)) => {
+                Some(Signal::Complete)
+            }
+            EventKind::Create(CreateKind::File | CreateKind::Any)
+            | EventKind::Modify(
+                ModifyKind::Data(_) | ModifyKind::Name(RenameMode::Any) | ModifyKind::Any,
+            ) => Some(Signal::Changing),
+            _ => None,
+        }
+    }
+
+    impl Library {
+        /// Imports books from `watcher` as they arrive, passing each
+        /// outcome to `on_event`, until `on_event` breaks or no book
+        /// arrives for `idle`.
+        pub fn import_watched(
+            &self,
+            watcher: &mut FolderWatcher,
+            idle: Duration,
+            mut on_event: impl FnMut(&ImportEvent) -> ControlFlow<()>,
+        ) {
+            while let Some(path) = watcher.next_file(idle) {
+                let event = self.import_file(&path);
+                if matches!(event, ImportEvent::Skipped { .. }) {
+                    continue;
+                }
+                if on_event(&event).is_break() {
+                    return;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Without close events, a file counts once its size has held
+        /// for [`SETTLE`].
+        #[test]
+        fn files_settle_once_their_size_holds() {
+            let dir = tempfile::tempdir().unwrap();
+            let mut watcher = FolderWatcher::new(&[dir.path().to_path_buf()]).unwrap();
+            let (done, growing) = (
+                dir.path().join("done.epub"),
+                dir.path().join("growing.epub"),
+            );
+            fs::write(&done, b"book").unwrap();
+            fs::write(&growing, b"more").unwrap();
+            let long_ago = Instant::now() - SETTLE;
+            for (path, size) in [(&done, 4), (&growing, 2)] {
+                let pending = Pending {
+                    size: Some(size),
+                    since: long_ago,
+                };
+                watcher.pending.insert(path.clone(), pending);
+            }
+            assert_eq!(watcher.settled(), Some(done));
+            assert_eq!(watcher.settled(), None);
+            assert!(watcher.pending.contains_key(&growing));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn imports_books_recursively_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Herbert/Dune")).unwrap();
+        fs::create_dir_all(root.join(".cache")).unwrap();
+        for name in [
+            "Emma.EPUB",
+            "notes.md",
+            "Herbert/Dune/Dune.fb2",
+            "Herbert/Messiah.mobi",
+            ".cache/hidden.epub",
+        ] {
+            fs::write(root.join(name), b"").unwrap();
+        }
+        let library = Library::open_in_memory().unwrap();
+        let report = library.import_dir(root).unwrap();
+        assert_eq!(report.added.len(), 3);
+        assert!(report.failed.is_empty());
+        let mut titles: Vec<String> = library
+            .books()
+            .unwrap()
+            .into_iter()
+            .map(|book| book.metadata.title)
+            .collect();
+        titles.sort();
+        assert_eq!(titles, ["Dune", "Emma", "Messiah"]);
+        let emma = library
+            .find_by_path(&root.join("Emma.EPUB"))
+            .unwrap()
+            .unwrap()
+            .metadata;
+        assert_eq!(emma.format, Some(BookFormat::Epub));
+        assert_eq!(emma.file_size, Some(0));
+        assert!(emma.content_hash.unwrap().starts_with("e3b0c442"));
+
+        let again = library.import_dir(root).unwrap();
+        assert!(again.added.is_empty());
+        assert_eq!(again.skipped, 3);
+        assert!(library.import_dir(&root.join("missing")).is_err());
+
+        // A file that changed since it was imported is read again.
+        let messiah = root.join("Herbert/Messiah.mobi");
+        fs::write(&messiah, b"now with content").unwrap();
+        let event = library.import_file(&messiah);
+        let ImportEvent::Updated { id, .. } = event else {
+            panic!("expected an update, got {event:?}");
+        };
+        let book = library.get(id).unwrap().unwrap().metadata;
+        assert_eq!(book.file_size, Some(16));
+        assert!(matches!(
+            library.import_file(&messiah),
+            ImportEvent::Skipped { .. }
+        ));
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watcher_imports_dropped_books_once_written() {
+        use std::io::Write;
+        use std::ops::ControlFlow;
+        use std::thread;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let library = Library::open_in_memory().unwrap();
+        let mut watcher = FolderWatcher::new(&[dir.path().to_path_buf()]).unwrap();
+        fs::write(dir.path().join("readme.txt.part"), b"").unwrap();
+        fs::create_dir(dir.path().join("new")).unwrap();
+        // Give the watcher a moment to pick up the new subfolder.
+        thread::sleep(Duration::from_millis(100));
+        // A slow copy: the book exists, but is incomplete, for a while.
+        let book = dir.path().join("new/Persuasion.epub");
+        let copy = thread::spawn({
+            let book = book.clone();
+            move || {
+                let mut file = fs::File::create(book).unwrap();
+                for _ in 0..5 {
+                    file.write_all(&[b'x'; 1000]).unwrap();
+                    file.flush().unwrap();
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        });
+
+        let mut events = Vec::new();
+        library.import_watched(&mut watcher, Duration::from_secs(5), |event| {
+            events.push(event.clone());
+            ControlFlow::Break(())
+        });
+        copy.join().unwrap();
+        let [ImportEvent::Added { id, path }] = &events[..] else {
+            panic!("expected one import, got {events:?}");
+        };
+        assert_eq!(path, &book);
+        let metadata = library.get(*id).unwrap().unwrap().metadata;
+        assert_eq!(metadata.file_size, Some(5000));
+    }
+}
@@ -0,0 +1,53 @@
+//! The user's book library.
+//!
+//! Books and what we know about them live in one SQLite database in the
+//! app's data folder, so the library survives restarts and every other
+//! part of the app (conversion, sync, progress) can look books up by id.
+//! The schema is versioned and upgraded in place on open.
+
+mod covers;
+mod extract;
+mod import;
+mod metadata;
+mod schema;
+mod store;
+
+use std::io;
+
+use thiserror::Error;
+
+pub use covers::{CoverCache, CoverThumbnail, ThumbnailSize};
+#[cfg(feature = "watch")]
+pub use import::FolderWatcher;
+pub use import::{is_importable, ImportEvent, ImportReport, BOOK_EXTENSIONS};
+pub use metadata::{BookFormat, BookMetadata, METADATA_VERSION};
+pub use schema::SCHEMA_VERSION;
+pub use store::{Book, BookId, Library};
+
+#[derive(Debug, Error)]
+pub enum LibraryError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("library schema version {0} is newer than this app supports")]
+    UnsupportedSchema(u32),
+    #[error("no book with id {0}")]
+    NotFound(BookId),
+    #[error("{} is already in the library", .0.display())]
+    Duplicate(std::path::PathBuf),
+    #[error("{} is not a readable book: {reason}", path.display())]
+    Malformed {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+    #[cfg(feature = "watch")]
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+}
+
+impl From<walkdir::Error> for LibraryError {
+    fn from(err: walkdir::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
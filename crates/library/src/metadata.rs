@@ -0,0 +1,186 @@
+//! What the library records about a book.
+
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Version of the serialized [`BookMetadata`] layout. Bump it when fields
+/// change meaning; added fields only need a serde default.
+pub const METADATA_VERSION: u32 = 2;
+
+/// The book file formats the library knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BookFormat {
+    Epub,
+    Fb2,
+    Mobi,
+    Txt,
+}
+
+impl BookFormat {
+    /// The format a file extension names, ignoring case.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "epub" => Some(Self::Epub),
+            "fb2" => Some(Self::Fb2),
+            "mobi" => Some(Self::Mobi),
+            "txt" => Some(Self::Txt),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(&path.extension()?.to_string_lossy())
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Epub => "epub",
+            Self::Fb2 => "fb2",
+            Self::Mobi => "mobi",
+            Self::Txt => "txt",
+        }
+    }
+}
+
+/// Serialized records written before [`METADATA_VERSION`] existed lack
+/// the `version` field and any field added since; both take defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookMetadata {
+    /// The [`METADATA_VERSION`] this record was written with.
+    #[serde(default = "first_version")]
+    pub version: u32,
+    pub title: String,
+    /// In the order the book lists them.
+    pub authors: Vec<String>,
+    /// A language tag such as `en` or `ru-RU`, as the book gives it.
+    pub language: Option<String>,
+    pub series: Option<String>,
+    /// Position within [`series`](Self::series); may be fractional.
+    pub series_index: Option<f64>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    /// Publication date as the book gives it: a year, or an ISO 8601 date.
+    #[serde(default)]
+    pub published: Option<String>,
+    /// Subjects, genres or tags.
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    pub description: Option<String>,
+    /// The book file in the user's collection. Unique within a library.
+    pub path: PathBuf,
+    /// Size of the book file in bytes when it was read.
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    /// Lowercase hex SHA-256 of the book file when it was read.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Modification time of the book file when it was read, to the
+    /// millisecond.
+    #[serde(default)]
+    pub modified: Option<SystemTime>,
+    #[serde(default)]
+    pub format: Option<BookFormat>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl BookMetadata {
+    /// A book with nothing known but its title and file. The format is
+    /// taken from the file's extension.
+    pub fn new(title: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            version: METADATA_VERSION,
+            title: title.into(),
+            authors: Vec::new(),
+            language: None,
+            series: None,
+            series_index: None,
+            publisher: None,
+            published: None,
+            subjects: Vec::new(),
+            description: None,
+            format: BookFormat::from_path(&path),
+            path,
+            file_size: None,
+            content_hash: None,
+            modified: None,
+        }
+    }
+
+    /// A book known only by its file, titled after the file name.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let title = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        Self::new(title, path)
+    }
+
+    /// Records the size, modification time and hash of the file at
+    /// [`path`](Self::path).
+    pub fn fingerprint(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let modified = modified_millis(&file.metadata()?);
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)?;
+        self.file_size = Some(size);
+        self.modified = modified;
+        self.content_hash = Some(
+            hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    /// Whether the file at [`path`](Self::path) no longer has the size or
+    /// modification time [`fingerprint`](Self::fingerprint) recorded.
+    /// Unfingerprinted books count as changed.
+    pub fn is_stale(&self) -> io::Result<bool> {
+        let current = std::fs::metadata(&self.path)?;
+        Ok(self.file_size != Some(current.len())
+            || self.modified.is_none()
+            || self.modified != modified_millis(&current))
+    }
+}
+
+/// The file's modification time, truncated to what the library stores.
+fn modified_millis(metadata: &Metadata) -> Option<SystemTime> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(since_epoch.as_millis() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_records_written_before_versioning() {
+        let old = r#"{"title":"Emma","authors":["Jane Austen"],"language":null,
+            "series":null,"series_index":null,"description":null,"path":"/b/Emma.epub"}"#;
+        let book: BookMetadata = serde_json::from_str(old).unwrap();
+        assert_eq!(book.version, 1);
+        assert_eq!(book.authors, ["Jane Austen"]);
+        assert!(book.subjects.is_empty());
+        assert_eq!(book.format, None);
+
+        let current = BookMetadata::from_path("/b/Emma.EPUB");
+        let json = serde_json::to_string(&current).unwrap();
+        assert!(json.contains(r#""version":2"#) && json.contains(r#""format":"epub""#));
+        assert_eq!(
+            serde_json::from_str::<BookMetadata>(&json).unwrap(),
+            current
+        );
+    }
+}
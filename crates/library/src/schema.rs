@@ -0,0 +1,109 @@
+//! Creating and upgrading the database schema.
+//!
+//! The schema version lives in SQLite's `user_version`. Each entry of
+//! [`MIGRATIONS`] takes the schema from its index to the next version;
+//! released migrations are never edited, only appended to.
+
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::LibraryError;
+
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE books (
+        id     INTEGER PRIMARY KEY,
+        title  TEXT NOT NULL,
+        author TEXT,
+        path   TEXT NOT NULL UNIQUE
+    );
+    ",
+    // Metadata read from the book itself. Authors become a JSON array.
+    "
+    ALTER TABLE books ADD COLUMN authors TEXT NOT NULL DEFAULT '[]';
+    UPDATE books SET authors = json_array(author) WHERE author IS NOT NULL;
+    ALTER TABLE books DROP COLUMN author;
+    ALTER TABLE books ADD COLUMN language TEXT;
+    ALTER TABLE books ADD COLUMN series TEXT;
+    ALTER TABLE books ADD COLUMN series_index REAL;
+    ALTER TABLE books ADD COLUMN description TEXT;
+    ",
+    // Publication details and the file's fingerprint.
+    "
+    ALTER TABLE books ADD COLUMN publisher TEXT;
+    ALTER TABLE books ADD COLUMN published TEXT;
+    ALTER TABLE books ADD COLUMN subjects TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE books ADD COLUMN file_size INTEGER;
+    ALTER TABLE books ADD COLUMN content_hash TEXT;
+    ALTER TABLE books ADD COLUMN format TEXT;
+    CREATE INDEX books_content_hash ON books (content_hash);
+    ",
+    // File modification time in milliseconds since the Unix epoch.
+    "
+    ALTER TABLE books ADD COLUMN modified INTEGER;
+    ",
+];
+
+/// The version a freshly opened library is at.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Brings `conn` up to [`SCHEMA_VERSION`], each step in its own
+/// transaction. Fails on databases written by a newer app.
+pub(crate) fn migrate(conn: &mut Connection) -> Result<(), LibraryError> {
+    let current: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if current > SCHEMA_VERSION {
+        return Err(LibraryError::UnsupportedSchema(current));
+    }
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", version as u32 + 1)?;
+        tx.commit()?;
+        info!(version = version + 1, "migrated library schema");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_once_and_refuses_newer_schemas() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let version: u32 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&mut conn),
+            Err(LibraryError::UnsupportedSchema(v)) if v == SCHEMA_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn moves_single_authors_into_the_authors_list() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO books (title, author, path) VALUES ('Emma', 'Jane Austen', '/a.epub');
+             INSERT INTO books (title, author, path) VALUES ('Beowulf', NULL, '/b.epub');",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        let authors: Vec<String> = conn
+            .prepare("SELECT authors FROM books ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(authors, [r#"["Jane Austen"]"#, "[]"]);
+    }
+}
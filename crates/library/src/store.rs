@@ -0,0 +1,304 @@
+//! The SQLite-backed [`Library`].
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::types::{ToSql, Type};
+use rusqlite::{params_from_iter, Connection, ErrorCode, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::migrate;
+use crate::{BookFormat, BookMetadata, CoverCache, LibraryError, METADATA_VERSION};
+
+/// A book's stable id within one library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BookId(pub i64);
+
+impl fmt::Display for BookId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A book in the library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Book {
+    pub id: BookId,
+    pub metadata: BookMetadata,
+}
+
+/// The library database. Every call commits before it returns.
+#[derive(Debug)]
+pub struct Library {
+    conn: Connection,
+    pub(crate) covers: Option<CoverCache>,
+}
+
+impl Library {
+    /// Opens the library at `path`, creating it if needed and upgrading
+    /// its schema to the current version.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LibraryError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// A library that lives only as long as the value, for tests and
+    /// previews.
+    pub fn open_in_memory() -> Result<Self, LibraryError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, LibraryError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn, covers: None })
+    }
+
+    /// Adds a book. Fails with [`LibraryError::Duplicate`] if its file is
+    /// already in the library.
+    pub fn add(&self, metadata: &BookMetadata) -> Result<BookId, LibraryError> {
+        self.conn
+            .execute(
+                &format!(
+                    "INSERT INTO books ({FIELDS})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)"
+                ),
+                params_from_iter(fields(metadata)),
+            )
+            .map_err(|err| duplicate_path(err, &metadata.path))?;
+        Ok(BookId(self.conn.last_insert_rowid()))
+    }
+
+    /// Replaces what is recorded about book `id`.
+    pub fn update(&self, id: BookId, metadata: &BookMetadata) -> Result<(), LibraryError> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE books SET title = ?1, authors = ?2, language = ?3, series = ?4,
+                     series_index = ?5, publisher = ?6, published = ?7, subjects = ?8,
+                     description = ?9, path = ?10, file_size = ?11, content_hash = ?12,
+                     format = ?13, modified = ?14
+                 WHERE id = ?15",
+                params_from_iter(
+                    fields(metadata)
+                        .into_iter()
+                        .chain([Box::new(id.0) as Box<dyn ToSql>]),
+                ),
+            )
+            .map_err(|err| duplicate_path(err, &metadata.path))?;
+        match changed {
+            0 => Err(LibraryError::NotFound(id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Forgets book `id`. The book file itself is left alone.
+    pub fn remove(&self, id: BookId) -> Result<(), LibraryError> {
+        match self
+            .conn
+            .execute("DELETE FROM books WHERE id = ?1", [id.0])?
+        {
+            0 => Err(LibraryError::NotFound(id)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get(&self, id: BookId) -> Result<Option<Book>, LibraryError> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT id, {FIELDS} FROM books WHERE id = ?1"),
+                [id.0],
+                book_from_row,
+            )
+            .optional()?)
+    }
+
+    /// The book whose file is at `path`.
+    pub fn find_by_path(&self, path: &Path) -> Result<Option<Book>, LibraryError> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT id, {FIELDS} FROM books WHERE path = ?1"),
+                [path_text(path)],
+                book_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Every book, sorted by title.
+    pub fn books(&self) -> Result<Vec<Book>, LibraryError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, {FIELDS} FROM books ORDER BY title COLLATE NOCASE, id"
+        ))?;
+        let books = stmt.query_map([], book_from_row)?;
+        Ok(books.collect::<Result<_, _>>()?)
+    }
+
+    pub fn len(&self) -> Result<usize, LibraryError> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM books", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, LibraryError> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/// The metadata columns, in the order [`fields`] binds them and
+/// [`book_from_row`] reads them after the id.
+const FIELDS: &str = "title, authors, language, series, series_index, publisher, published, \
+                      subjects, description, path, file_size, content_hash, format, modified";
+
+/// Parameters for [`FIELDS`].
+fn fields(metadata: &BookMetadata) -> Vec<Box<dyn ToSql + '_>> {
+    vec![
+        Box::new(&metadata.title),
+        Box::new(json_list(&metadata.authors)),
+        Box::new(&metadata.language),
+        Box::new(&metadata.series),
+        Box::new(metadata.series_index),
+        Box::new(&metadata.publisher),
+        Box::new(&metadata.published),
+        Box::new(json_list(&metadata.subjects)),
+        Box::new(&metadata.description),
+        Box::new(path_text(&metadata.path)),
+        // SQLite integers are signed; no book file comes near the limit.
+        Box::new(metadata.file_size.map(|size| size as i64)),
+        Box::new(&metadata.content_hash),
+        Box::new(metadata.format.map(BookFormat::extension)),
+        Box::new(metadata.modified.and_then(unix_millis)),
+    ]
+}
+
+fn book_from_row(row: &Row<'_>) -> rusqlite::Result<Book> {
+    Ok(Book {
+        id: BookId(row.get(0)?),
+        metadata: BookMetadata {
+            version: METADATA_VERSION,
+            title: row.get(1)?,
+            authors: list_from_json(row, 2)?,
+            language: row.get(3)?,
+            series: row.get(4)?,
+            series_index: row.get(5)?,
+            publisher: row.get(6)?,
+            published: row.get(7)?,
+            subjects: list_from_json(row, 8)?,
+            description: row.get(9)?,
+            path: PathBuf::from(row.get::<_, String>(10)?),
+            file_size: row.get::<_, Option<i64>>(11)?.map(|size| size as u64),
+            content_hash: row.get(12)?,
+            format: row
+                .get::<_, Option<String>>(13)?
+                .and_then(|ext| BookFormat::from_extension(&ext)),
+            modified: row
+                .get::<_, Option<i64>>(14)?
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms as u64)),
+        },
+    })
+}
+
+fn unix_millis(time: SystemTime) -> Option<i64> {
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_millis() as i64)
+}
+
+/// Lists (authors, subjects) are stored as JSON arrays, in order.
+fn json_list(list: &[String]) -> String {
+    serde_json::to_string(list).expect("strings serialize")
+}
+
+fn list_from_json(row: &Row<'_>, column: usize) -> rusqlite::Result<Vec<String>> {
+    let text: String = row.get(column)?;
+    serde_json::from_str(&text)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, err.into()))
+}
+
+/// Paths are stored as text; the rare non-UTF-8 path is stored lossily.
+fn path_text(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn duplicate_path(err: rusqlite::Error, path: &Path) -> LibraryError {
+    match err.sqlite_error_code() {
+        Some(ErrorCode::ConstraintViolation) => LibraryError::Duplicate(path.to_path_buf()),
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str, path: &str) -> BookMetadata {
+        BookMetadata {
+            authors: vec!["Frank Herbert".into()],
+            series: Some("Dune".into()),
+            series_index: Some(1.0),
+            subjects: vec!["Science Fiction".into()],
+            file_size: Some(1 << 20),
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            ..BookMetadata::new(title, path)
+        }
+    }
+
+    #[test]
+    fn adds_updates_and_removes_books() {
+        let library = Library::open_in_memory().unwrap();
+        assert!(library.is_empty().unwrap());
+        let dune = library.add(&book("Dune", "/books/dune.epub")).unwrap();
+        let messiah = library
+            .add(&book("dune messiah", "/books/messiah.epub"))
+            .unwrap();
+        assert!(matches!(
+            library.add(&book("Copy", "/books/dune.epub")),
+            Err(LibraryError::Duplicate(_))
+        ));
+
+        let mut renamed = library.get(messiah).unwrap().unwrap().metadata;
+        renamed.title = "Children of Dune".into();
+        renamed.authors.push("Brian Herbert".into());
+        library.update(messiah, &renamed).unwrap();
+        assert_eq!(library.get(messiah).unwrap().unwrap().metadata, renamed);
+        let titles: Vec<String> = library
+            .books()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.metadata.title)
+            .collect();
+        assert_eq!(titles, ["Children of Dune", "Dune"]);
+        assert_eq!(
+            library
+                .find_by_path(Path::new("/books/dune.epub"))
+                .unwrap()
+                .unwrap()
+                .id,
+            dune
+        );
+
+        library.remove(dune).unwrap();
+        assert!(matches!(library.remove(dune), Err(LibraryError::NotFound(id)) if id == dune));
+        assert!(library.get(dune).unwrap().is_none());
+        assert!(matches!(
+            library.update(dune, &renamed),
+            Err(LibraryError::NotFound(_))
+        ));
+        assert_eq!(library.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn persists_across_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("library.sqlite3");
+        let id = Library::open(&path)
+            .unwrap()
+            .add(&BookMetadata::from_path("/books/Emma.epub"))
+            .unwrap();
+        let library = Library::open(&path).unwrap();
+        let emma = library.get(id).unwrap().unwrap();
+        assert_eq!(emma.metadata.title, "Emma");
+        assert!(emma.metadata.authors.is_empty());
+    }
+}
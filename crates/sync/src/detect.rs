@@ -0,0 +1,324 @@
+//! Finding mounted removable volumes and picking out the reader's card.
+//!
+//! The X4 firmware writes no identity file, so a volume is taken to be the
+//! reader's card when it carries our sync manifest or already holds XTC
+//! books at its root or one folder down. A fresh card is not recognised; the UI lets the user pick from
+//! [`removable_volumes`] instead.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+/// A mounted volume and its size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub mount_path: PathBuf,
+    /// Total size in bytes.
+    pub capacity: u64,
+    /// Bytes available to this user.
+    pub free: u64,
+}
+
+/// Removable volumes that look like the reader's card.
+pub fn detect_devices() -> Vec<Device> {
+    removable_volumes()
+        .into_iter()
+        .filter(|device| holds_books(&device.mount_path))
+        .collect()
+}
+
+/// Every mounted removable volume. Volumes whose size cannot be read are
+/// skipped.
+pub fn removable_volumes() -> Vec<Device> {
+    platform::mount_points()
+        .into_iter()
+        .filter_map(|mount_path| match platform::space(&mount_path) {
+            Ok((capacity, free)) => Some(Device {
+                mount_path,
+                capacity,
+                free,
+            }),
+            Err(err) => {
+                debug!(path = %mount_path.display(), %err, "skipping volume");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Bytes available to this user on the volume holding `path`. The
+/// volume's root may not exist yet on a fresh card, so the nearest existing
+/// ancestor is measured.
+pub(crate) fn free_space(path: &Path) -> io::Result<u64> {
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+    platform::space(existing).map(|(_, free)| free)
+}
+
+/// Whether `root` was synced before or it or one of its top-level folders
+/// holds an XTC book.
+fn holds_books(root: &Path) -> bool {
+    if root.join(crate::MANIFEST_PATH).is_file() {
+        return true;
+    }
+    let has_book = |dir: &Path| -> io::Result<bool> {
+        for entry in fs::read_dir(dir)? {
+            if is_book(&entry?.path()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+    if has_book(root).unwrap_or(false) {
+        return true;
+    }
+    let Ok(entries) = fs::read_dir(root) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        !hidden && entry.path().is_dir() && has_book(&entry.path()).unwrap_or(false)
+    })
+}
+
+pub(crate) fn is_book(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xtc") || ext.eq_ignore_ascii_case("xtch"))
+}
+
+/// One line of `/proc/mounts`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MountEntry {
+    pub source: String,
+    pub target: PathBuf,
+    pub fs_type: String,
+}
+
+/// Parses `/proc/mounts`, undoing the octal escapes used for spaces and
+/// other awkward bytes in mount points.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn parse_mounts(text: &str) -> Vec<MountEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_ascii_whitespace();
+            let (source, target, fs_type) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(MountEntry {
+                source: unescape(source),
+                target: PathBuf::from(unescape(target)),
+                fs_type: fs_type.to_owned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|d| bytes[i] == b'\\' && d.iter().all(|c| (b'0'..=b'7').contains(c)));
+        match octal {
+            Some(d) => {
+                out.push(d.iter().fold(0u8, |n, c| n.wrapping_mul(8) + (c - b'0')));
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// File systems an SD card is formatted with; `fuseblk` covers exFAT and
+/// NTFS mounted through FUSE.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const CARD_FS_TYPES: &[&str] = &["vfat", "msdos", "exfat", "fuseblk", "ntfs", "ntfs3"];
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+
+    pub fn mount_points() -> Vec<PathBuf> {
+        let Ok(text) = fs::read_to_string("/proc/self/mounts") else {
+            return Vec::new();
+        };
+        parse_mounts(&text)
+            .into_iter()
+            .filter(|m| CARD_FS_TYPES.contains(&m.fs_type.as_str()) && is_removable(&m.source))
+            .map(|m| m.target)
+            .collect()
+    }
+
+    /// `/dev/sdb1` -> checks `/sys/block/sdb`. Card readers and USB mass
+    /// storage report `removable` inconsistently, so a USB parent counts too.
+    fn is_removable(source: &str) -> bool {
+        let Some(name) = source.strip_prefix("/dev/") else {
+            return false;
+        };
+        let disk = if name.starts_with("mmcblk") || name.starts_with("nvme") {
+            name.split_once('p').map_or(name, |(disk, _)| disk)
+        } else {
+            name.trim_end_matches(|c: char| c.is_ascii_digit())
+        };
+        if disk.starts_with("mmcblk") {
+            return true;
+        }
+        let block = Path::new("/sys/block").join(disk);
+        let flagged = fs::read_to_string(block.join("removable")).is_ok_and(|v| v.trim() == "1");
+        flagged
+            || fs::canonicalize(&block).is_ok_and(|path| path.to_string_lossy().contains("/usb"))
+    }
+
+    pub fn space(path: &Path) -> io::Result<(u64, u64)> {
+        super::statvfs_space(path)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    /// Everything under `/Volumes` except the symlink to the boot volume.
+    pub fn mount_points() -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir("/Volumes") else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|entry| entry.path())
+            .collect()
+    }
+
+    pub fn space(path: &Path) -> io::Result<(u64, u64)> {
+        super::statvfs_space(path)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetDiskFreeSpaceExW, GetDriveTypeW, GetLogicalDrives,
+    };
+
+    use super::*;
+
+    const DRIVE_REMOVABLE: u32 = 2;
+
+    pub fn mount_points() -> Vec<PathBuf> {
+        // SAFETY: no arguments; returns a bitmask of drive letters.
+        let drives = unsafe { GetLogicalDrives() };
+        (0..26u8)
+            .filter(|i| drives & (1 << i) != 0)
+            .map(|i| PathBuf::from(format!("{}:\\", char::from(b'A' + i))))
+            .filter(|root| {
+                // SAFETY: `wide` is NUL-terminated and outlives the call.
+                let wide = wide(root);
+                unsafe { GetDriveTypeW(wide.as_ptr()) == DRIVE_REMOVABLE }
+            })
+            .collect()
+    }
+
+    pub fn space(path: &Path) -> io::Result<(u64, u64)> {
+        let wide = wide(path);
+        let (mut available, mut total, mut free) = (0u64, 0u64, 0u64);
+        // SAFETY: `wide` is NUL-terminated and the out-pointers are valid.
+        let ok =
+            unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((total, available))
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain([0]).collect()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::*;
+
+    pub fn mount_points() -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    pub fn space(_: &Path) -> io::Result<(u64, u64)> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Capacity and space available to this user, from `statvfs`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[allow(clippy::unnecessary_cast)] // field widths differ between platforms
+fn statvfs_space(path: &Path) -> io::Result<(u64, u64)> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is valid for writes.
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it filled `stat`.
+    let stat = unsafe { stat.assume_init() };
+    let block = stat.f_frsize as u64;
+    Ok((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_mounts() {
+        let text = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+/dev/sdb1 /media/me/X4\\040CARD vfat rw,nosuid 0 0
+tmpfs /run tmpfs rw 0 0
+";
+        let mounts = parse_mounts(text);
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(
+            mounts[1],
+            MountEntry {
+                source: "/dev/sdb1".into(),
+                target: "/media/me/X4 CARD".into(),
+                fs_type: "vfat".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn recognises_cards_holding_books() {
+        let card = tempfile::tempdir().unwrap();
+        assert!(!holds_books(card.path()));
+        fs::create_dir(card.path().join("Books")).unwrap();
+        fs::write(card.path().join("Books/Moby Dick.XTCH"), b"").unwrap();
+        assert!(holds_books(card.path()));
+
+        let hidden = tempfile::tempdir().unwrap();
+        fs::create_dir(hidden.path().join(".Trash")).unwrap();
+        fs::write(hidden.path().join(".Trash/old.xtc"), b"").unwrap();
+        assert!(!holds_books(hidden.path()));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn reads_volume_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let (capacity, free) = statvfs_space(dir.path()).unwrap();
+        assert!(capacity > 0 && free <= capacity);
+    }
+}
@@ -0,0 +1,185 @@
+//! Flushing and detaching the card once a sync is done.
+//!
+//! FAT drivers hold writes back for seconds, so a card pulled right after
+//! the last copy can lose books or the manifest. Ejecting through the OS
+//! flushes everything and only succeeds once the volume is safe to remove.
+
+use std::path::Path;
+use std::process::Command;
+
+use tracing::{debug, info};
+
+use crate::SyncError;
+
+/// Flushes pending writes to the volume mounted at `mount_path` and
+/// unmounts it, powering the card reader off where the OS supports it.
+/// Returns once the card is safe to unplug.
+pub fn eject(mount_path: &Path) -> Result<(), SyncError> {
+    platform::eject(mount_path)?;
+    info!(path = %mount_path.display(), "ejected");
+    Ok(())
+}
+
+fn failed(mount_path: &Path, reason: impl Into<String>) -> SyncError {
+    SyncError::Eject {
+        path: mount_path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
+
+/// Runs an eject helper, turning a non-zero exit into its stderr.
+#[cfg_attr(windows, allow(dead_code))]
+fn run(mount_path: &Path, command: &mut Command) -> Result<(), SyncError> {
+    let output = command
+        .output()
+        .map_err(|err| failed(mount_path, format!("{command:?}: {err}")))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(failed(
+        mount_path,
+        format!("{command:?}: {}", stderr.trim()),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs::{self, File};
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+    use crate::detect::parse_mounts;
+
+    /// `syncfs`, then `udisksctl unmount` and `power-off` on the block
+    /// device. Powering off is best effort: some readers refuse it, and the
+    /// card is already safe once unmounted.
+    pub fn eject(mount_path: &Path) -> Result<(), SyncError> {
+        let dir = File::open(mount_path)?;
+        // SAFETY: the descriptor is open for the duration of the call.
+        if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        drop(dir);
+
+        let mounts = fs::read_to_string("/proc/self/mounts")?;
+        let source = parse_mounts(&mounts)
+            .into_iter()
+            .find(|m| m.target == mount_path)
+            .map(|m| m.source)
+            .ok_or_else(|| failed(mount_path, "not a mount point"))?;
+        run(
+            mount_path,
+            Command::new("udisksctl").args(["unmount", "--no-user-interaction", "-b", &source]),
+        )?;
+        let powered_off = run(
+            mount_path,
+            Command::new("udisksctl").args(["power-off", "--no-user-interaction", "-b", &source]),
+        );
+        if let Err(err) = powered_off {
+            debug!(%err, "unmounted but could not power off");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    /// `diskutil eject` flushes, unmounts every volume on the disk and
+    /// ejects it.
+    pub fn eject(mount_path: &Path) -> Result<(), SyncError> {
+        // SAFETY: no arguments.
+        unsafe { libc::sync() };
+        run(
+            mount_path,
+            Command::new("diskutil").arg("eject").arg(mount_path),
+        )
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::OpenOptions;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Component;
+    use std::ptr;
+
+    use windows_sys::Win32::System::Ioctl::{
+        FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, IOCTL_STORAGE_EJECT_MEDIA,
+        IOCTL_STORAGE_MEDIA_REMOVAL, PREVENT_MEDIA_REMOVAL,
+    };
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+
+    use super::*;
+
+    /// The sequence Explorer's "Eject" uses on the volume handle: flush,
+    /// lock, dismount, allow removal, eject.
+    pub fn eject(mount_path: &Path) -> Result<(), SyncError> {
+        let Some(Component::Prefix(prefix)) = mount_path.components().next() else {
+            return Err(failed(mount_path, "not a drive path"));
+        };
+        let volume = Path::new(r"\\.\").join(prefix.as_os_str());
+        let handle = OpenOptions::new().read(true).write(true).open(&volume)?;
+        handle.sync_all()?;
+        let allow = PREVENT_MEDIA_REMOVAL {
+            PreventMediaRemoval: 0,
+        };
+        let steps: [(u32, *const PREVENT_MEDIA_REMOVAL, &str); 4] = [
+            (FSCTL_LOCK_VOLUME, ptr::null(), "volume is in use"),
+            (FSCTL_DISMOUNT_VOLUME, ptr::null(), "could not dismount"),
+            (IOCTL_STORAGE_MEDIA_REMOVAL, &allow, "removal is locked"),
+            (IOCTL_STORAGE_EJECT_MEDIA, ptr::null(), "could not eject"),
+        ];
+        for (code, input, reason) in steps {
+            let mut returned = 0u32;
+            let size = if input.is_null() {
+                0
+            } else {
+                std::mem::size_of::<PREVENT_MEDIA_REMOVAL>() as u32
+            };
+            // SAFETY: the handle is open, `input` is null or points at
+            // `allow` which outlives the call, and no output is requested.
+            let ok = unsafe {
+                DeviceIoControl(
+                    handle.as_raw_handle(),
+                    code,
+                    input.cast(),
+                    size,
+                    ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                let err = std::io::Error::last_os_error();
+                return Err(failed(mount_path, format!("{reason}: {err}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::*;
+
+    pub fn eject(mount_path: &Path) -> Result<(), SyncError> {
+        Err(failed(mount_path, "ejecting is not supported on this OS"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn refuses_folders_that_are_not_mount_points() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = eject(dir.path()).unwrap_err();
+        assert!(matches!(err, SyncError::Eject { .. }), "{err}");
+    }
+}
@@ -0,0 +1,509 @@
+//! Carrying out a [`SyncPlan`].
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use xtc::XtcReader;
+
+use crate::detect::is_book;
+use crate::pace::{enter_idle_priority, Paced, Throttle};
+use crate::transfer::{is_partial, partial_path};
+use crate::{
+    FileCopy, Manifest, ReadSeek, SyncAction, SyncError, SyncPlan, SyncTarget, Transfer, TRASH_DIR,
+};
+
+/// What a finished sync did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncReport {
+    pub copied: usize,
+    pub updated: usize,
+    /// Books renamed or moved on the device.
+    pub moved: usize,
+    pub deleted: usize,
+    /// Books moved to the device trash.
+    pub trashed: usize,
+    pub skipped: usize,
+    /// Books changed on the device and kept there.
+    pub kept: usize,
+    /// Books changed on the device that were left alone awaiting a
+    /// decision.
+    pub conflicts: usize,
+    /// Copies that continued a partial file from an interrupted sync.
+    pub resumed: usize,
+    /// Bytes actually written, not counting resumed prefixes.
+    pub bytes_written: u64,
+    /// Leftover partial files that no action needed, now deleted.
+    pub stale_partials_removed: usize,
+    /// The target was ejected after the sync.
+    pub ejected: bool,
+}
+
+/// How [`execute_plan`] runs a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecuteOptions {
+    /// Most copies writing to the device at once. Several small books
+    /// finish sooner side by side; large ones gain little since the card is
+    /// the bottleneck. `0` is treated as `1`.
+    pub concurrency: usize,
+    /// Re-read each copied book from the device and check every page
+    /// before it replaces the old copy and enters the manifest.
+    pub verify: bool,
+    /// Eject the target once the sync has succeeded, so the card can be
+    /// pulled without losing delayed writes.
+    pub eject: bool,
+    /// Cap on bytes read from the library per second, shared by all
+    /// concurrent copies. `None` copies as fast as the card allows.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Run copies at idle I/O and CPU priority, so the sync yields to
+    /// whatever else the computer is doing.
+    pub idle_priority: bool,
+}
+
+impl Default for ExecuteOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            verify: true,
+            eject: false,
+            max_bytes_per_sec: None,
+            idle_priority: false,
+        }
+    }
+}
+
+/// Runs every action of `plan` on `target` in order, stopping at the first
+/// error.
+///
+/// On an [`FsTarget`](crate::FsTarget) each copy is atomic, so an
+/// interrupted sync never leaves a truncated book visible, and running the
+/// plan again picks up where it stopped. Nothing is written unless the
+/// target has room for the whole plan (see [`SyncPlan::check_space`]). The
+/// device manifest is saved at the end, and also when an action fails, so
+/// it records everything that did complete.
+///
+/// Consecutive copies and updates run up to `options.concurrency` at a
+/// time. Their results are applied to the manifest and report in plan
+/// order, whichever finished first; deletes never overlap a copy.
+///
+/// With `options.eject` the target is ejected after a successful sync; an
+/// eject failure is returned as the sync's error, with every book already
+/// in place.
+pub fn execute_plan(
+    target: &dyn SyncTarget,
+    plan: &SyncPlan,
+    options: &ExecuteOptions,
+) -> Result<SyncReport, SyncError> {
+    match target.free_space() {
+        Ok(available) => plan.check_space(available)?,
+        Err(SyncError::Io(err)) if err.kind() == io::ErrorKind::Unsupported => {}
+        Err(err) => return Err(err),
+    }
+    let throttle = options.max_bytes_per_sec.map(Throttle::new);
+    let mut manifest = plan.manifest.clone();
+    let mut report = SyncReport {
+        stale_partials_removed: remove_stale_partials(target, plan)?,
+        ..SyncReport::default()
+    };
+    let result = plan
+        .actions
+        .chunk_by(|a, b| is_copy(a) && is_copy(b))
+        .try_for_each(|run| {
+            if is_copy(&run[0]) {
+                let throttle = throttle.as_ref();
+                run_copies(target, run, options, throttle, &mut manifest, &mut report)
+            } else {
+                run.iter()
+                    .try_for_each(|action| run_action(target, action, &mut manifest, &mut report))
+            }
+        });
+    manifest.save(target)?;
+    result?;
+    if options.eject {
+        target.eject()?;
+        report.ejected = true;
+    }
+    info!(?report, "sync finished");
+    Ok(report)
+}
+
+fn is_copy(action: &SyncAction) -> bool {
+    matches!(action, SyncAction::Copy(_) | SyncAction::Update(_))
+}
+
+/// Copies `actions` (all copies or updates) on up to
+/// `options.concurrency` threads. After a failure no new copy starts; the
+/// ones already running finish and are recorded, and the first error in
+/// plan order is returned.
+fn run_copies(
+    target: &dyn SyncTarget,
+    actions: &[SyncAction],
+    options: &ExecuteOptions,
+    throttle: Option<&Throttle>,
+    manifest: &mut Manifest,
+    report: &mut SyncReport,
+) -> Result<(), SyncError> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Vec<OnceLock<Result<Transfer, SyncError>>> =
+        actions.iter().map(|_| OnceLock::new()).collect();
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.clamp(1, actions.len()) {
+            scope.spawn(|| {
+                if options.idle_priority {
+                    enter_idle_priority();
+                }
+                while !failed.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(SyncAction::Copy(copy) | SyncAction::Update(copy)) = actions.get(i)
+                    else {
+                        break;
+                    };
+                    let result = write_copy(target, copy, options, throttle);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let _ = results[i].set(result);
+                }
+            });
+        }
+    });
+
+    let mut first_err = None;
+    for (action, result) in actions.iter().zip(results) {
+        match (action, result.into_inner()) {
+            (SyncAction::Copy(copy), Some(Ok(transfer))) => {
+                record_copy(copy, transfer, manifest, report);
+                report.copied += 1;
+            }
+            (SyncAction::Update(copy), Some(Ok(transfer))) => {
+                record_copy(copy, transfer, manifest, report);
+                report.updated += 1;
+            }
+            (_, Some(Err(err))) => {
+                first_err.get_or_insert(err);
+            }
+            _ => {}
+        }
+    }
+    first_err.map_or(Ok(()), Err)
+}
+
+fn write_copy(
+    target: &dyn SyncTarget,
+    copy: &FileCopy,
+    options: &ExecuteOptions,
+    throttle: Option<&Throttle>,
+) -> Result<Transfer, SyncError> {
+    let verify = options.verify && is_book(&copy.dest);
+    let mut source = Paced::new(File::open(&copy.source)?, throttle);
+    target.write(&copy.dest, &mut source, &|written| {
+        if verify {
+            verify_copy(written, &copy.dest)
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// Checks a freshly written book with [`XtcReader::verify`] to catch data
+/// mangled by a flaky USB controller or a failing card.
+fn verify_copy(written: &mut dyn ReadSeek, dest: &Path) -> Result<(), SyncError> {
+    XtcReader::new(written)
+        .and_then(|mut reader| reader.verify())
+        .map_err(|source| SyncError::Verify {
+            path: dest.to_path_buf(),
+            source,
+        })
+}
+
+fn run_action(
+    target: &dyn SyncTarget,
+    action: &SyncAction,
+    manifest: &mut Manifest,
+    report: &mut SyncReport,
+) -> Result<(), SyncError> {
+    match action {
+        SyncAction::Copy(_) | SyncAction::Update(_) => unreachable!("copies run in batches"),
+        SyncAction::Move { from, to } => {
+            target.rename(from, to)?;
+            if let Some(entry) = manifest.remove(from) {
+                manifest.insert(to, entry);
+            }
+            report.moved += 1;
+        }
+        SyncAction::Delete { dest } => {
+            ignore_missing(target.delete(dest))?;
+            manifest.remove(dest);
+            report.deleted += 1;
+        }
+        SyncAction::Trash { dest } => {
+            let trashed = Path::new(TRASH_DIR).join(dest);
+            ignore_missing(target.delete(&trashed))?;
+            ignore_missing(target.rename(dest, &trashed))?;
+            manifest.remove(dest);
+            report.trashed += 1;
+        }
+        SyncAction::Skip { .. } => report.skipped += 1,
+        SyncAction::Keep { .. } => report.kept += 1,
+        SyncAction::Conflict(copy) => {
+            debug!(dest = %copy.dest.display(), "leaving unresolved conflict alone");
+            report.conflicts += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Deleting something already gone is fine: the user may have removed it
+/// by hand, or an earlier interrupted sync got that far.
+fn ignore_missing(result: Result<(), SyncError>) -> Result<(), SyncError> {
+    match result {
+        Err(SyncError::Io(err)) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+fn record_copy(
+    copy: &FileCopy,
+    transfer: Transfer,
+    manifest: &mut Manifest,
+    report: &mut SyncReport,
+) {
+    match transfer {
+        Transfer::Fresh => report.bytes_written += copy.entry.size,
+        Transfer::Resumed { from } => {
+            report.resumed += 1;
+            report.bytes_written += copy.entry.size - from;
+        }
+    }
+    manifest.insert(&copy.dest, copy.entry.clone());
+}
+
+/// Deletes partial files on the target that no copy in `plan` will resume.
+fn remove_stale_partials(target: &dyn SyncTarget, plan: &SyncPlan) -> Result<usize, SyncError> {
+    let wanted: Vec<PathBuf> = plan.copies().map(|copy| partial_path(&copy.dest)).collect();
+    let mut removed = 0;
+    for (path, _) in target.files()? {
+        if is_partial(&path) && !wanted.contains(&path) {
+            debug!(path = %path.display(), "removing stale partial copy");
+            target.delete(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::{DeleteMode, FsTarget, PlanOptions, SyncItem};
+
+    /// Most test books are placeholder bytes rather than real containers.
+    pub(crate) fn unverified() -> ExecuteOptions {
+        ExecuteOptions {
+            verify: false,
+            ..ExecuteOptions::default()
+        }
+    }
+
+    #[test]
+    fn copies_resumes_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        let target = FsTarget::new(&card);
+        let books: Vec<SyncItem> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let source = dir.path().join(format!("{name}.xtc"));
+                fs::write(&source, name.repeat(1000)).unwrap();
+                SyncItem {
+                    source,
+                    dest: PathBuf::from(format!("Books/{name}.xtc")),
+                    version: 1,
+                    priority: 0,
+                }
+            })
+            .collect();
+        fs::create_dir_all(card.join("Books")).unwrap();
+        fs::write(card.join("Books/b.xtc.partial"), "b".repeat(600)).unwrap();
+        fs::write(card.join("Books/gone.xtc.partial"), "x").unwrap();
+
+        let plan = SyncPlan::build(&target, &books, &PlanOptions::default()).unwrap();
+        let report = execute_plan(&target, &plan, &unverified()).unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                copied: 2,
+                resumed: 1,
+                bytes_written: 1000 + 400,
+                stale_partials_removed: 1,
+                ..SyncReport::default()
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(card.join("Books/b.xtc")).unwrap(),
+            "b".repeat(1000)
+        );
+        let mut left: Vec<_> = fs::read_dir(card.join("Books"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["a.xtc", "b.xtc"]);
+
+        let manifest = Manifest::load(&target).unwrap();
+        assert_eq!(manifest.books.len(), 2);
+        assert_eq!(manifest.books["Books/a.xtc"].size, 1000);
+    }
+
+    #[test]
+    fn deletes_deselected_books_and_records_partial_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        let target = FsTarget::new(&card);
+        let source = dir.path().join("a.xtc");
+        fs::write(&source, "aaaa").unwrap();
+        let a = SyncItem {
+            source,
+            dest: "a.xtc".into(),
+            version: 1,
+            priority: 0,
+        };
+        let options = PlanOptions {
+            delete_mode: DeleteMode::Remove,
+            ..PlanOptions::default()
+        };
+        execute_plan(
+            &target,
+            &SyncPlan::build(&target, &[a], &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+
+        let missing = SyncItem {
+            source: dir.path().join("missing.xtc"),
+            dest: "b.xtc".into(),
+            version: 1,
+            priority: 0,
+        };
+        let mut plan = SyncPlan::build(&target, &[], &options).unwrap();
+        plan.actions.push(SyncAction::Copy(FileCopy {
+            source: missing.source,
+            dest: missing.dest,
+            entry: crate::ManifestEntry {
+                sha256: String::new(),
+                size: 1,
+                version: 1,
+            },
+            priority: 0,
+        }));
+        assert!(execute_plan(&target, &plan, &unverified()).is_err());
+        assert!(!card.join("a.xtc").exists());
+        assert!(Manifest::load(&target).unwrap().books.is_empty());
+    }
+
+    #[test]
+    fn concurrent_copies_record_everything_that_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        let target = FsTarget::new(&card);
+        let mut books: Vec<SyncItem> = (0..12)
+            .map(|i| {
+                let source = dir.path().join(format!("{i}.xtc"));
+                fs::write(&source, vec![i as u8; 100 + i]).unwrap();
+                SyncItem {
+                    source,
+                    dest: PathBuf::from(format!("Books/{i}.xtc")),
+                    version: 1,
+                    priority: 0,
+                }
+            })
+            .collect();
+        let options = ExecuteOptions {
+            concurrency: 4,
+            ..unverified()
+        };
+
+        let plan = SyncPlan::build(&target, &books, &PlanOptions::default()).unwrap();
+        let report = execute_plan(&target, &plan, &options).unwrap();
+        assert_eq!(report.copied, 12);
+        assert_eq!(report.bytes_written, (100..112).sum::<u64>());
+        for i in 0..12 {
+            assert_eq!(
+                fs::read(card.join(format!("Books/{i}.xtc"))).unwrap(),
+                vec![i as u8; 100 + i]
+            );
+        }
+
+        // A source vanishing mid-plan fails the sync; copies picked up
+        // before it still land in the manifest.
+        for name in ["new1", "new2", "gone", "new3"] {
+            let source = dir.path().join(format!("{name}.xtc"));
+            fs::write(&source, name).unwrap();
+            books.push(SyncItem {
+                source,
+                dest: PathBuf::from(format!("Books/{name}.xtc")),
+                version: 1,
+                priority: 0,
+            });
+        }
+        let plan = SyncPlan::build(&target, &books, &PlanOptions::default()).unwrap();
+        fs::remove_file(dir.path().join("gone.xtc")).unwrap();
+        assert!(execute_plan(&target, &plan, &options).is_err());
+        let manifest = Manifest::load(&target).unwrap();
+        assert_eq!(manifest.books["Books/new1.xtc"].size, 4);
+        assert_eq!(manifest.books["Books/new2.xtc"].size, 4);
+        assert!(!manifest.books.contains_key("Books/gone.xtc"));
+        assert_eq!(manifest.books["Books/0.xtc"].size, 100);
+    }
+
+    #[test]
+    fn corrupt_copy_is_rejected_before_replacing_the_book() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        let target = FsTarget::new(&card);
+        let source = dir.path().join("a.xtc");
+        xtc::XtcWriter::create(&source)
+            .unwrap()
+            .add_page(&xtc::Page::xtg_from_gray(8, 1, &[0; 8]).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let item = SyncItem {
+            source: source.clone(),
+            dest: "a.xtc".into(),
+            version: 1,
+            priority: 0,
+        };
+        let plan = SyncPlan::build(
+            &target,
+            std::slice::from_ref(&item),
+            &PlanOptions::default(),
+        );
+        execute_plan(&target, &plan.unwrap(), &ExecuteOptions::default()).unwrap();
+        let good = fs::read(card.join("a.xtc")).unwrap();
+
+        // A flipped bit in the page payload, as a failing card might return.
+        let mut bad = good.clone();
+        *bad.last_mut().unwrap() ^= 0x01;
+        fs::write(&source, &bad).unwrap();
+        let plan = SyncPlan::build(&target, &[item], &PlanOptions::default()).unwrap();
+        let err = execute_plan(&target, &plan, &ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(err, SyncError::Verify { .. }), "{err}");
+        assert_eq!(fs::read(card.join("a.xtc")).unwrap(), good);
+        assert!(!card.join("a.xtc.partial").exists());
+        let manifest = Manifest::load(&target).unwrap();
+        assert_eq!(
+            manifest.books["a.xtc"].sha256,
+            crate::manifest::hash_file(&card.join("a.xtc")).unwrap()
+        );
+    }
+}
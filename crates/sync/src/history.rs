@@ -0,0 +1,257 @@
+//! Syncs waiting to run and syncs that already ran, kept on the computer.
+//!
+//! A [`SyncLog`] lives in one JSON file in the app's data folder so the UI
+//! can still say "last synced 2 days ago, 3 books pending" after a
+//! restart. A job stays queued until its outcome is recorded, so a sync cut
+//! short by a crash is still pending next time.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{SyncError, SyncItem, SyncReport};
+
+/// Oldest history entries are dropped beyond this many.
+pub const HISTORY_LIMIT: usize = 500;
+
+/// A sync requested but not yet finished.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncJob {
+    pub id: u64,
+    /// Which reader the job is for, as the caller identifies it (e.g. a
+    /// volume label or MTP serial).
+    pub device: String,
+    pub items: Vec<SyncItem>,
+    /// Seconds since the Unix epoch.
+    pub queued_at: u64,
+}
+
+/// How a sync ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Completed(SyncReport),
+    Failed { error: String },
+}
+
+/// One finished sync.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub job: u64,
+    pub device: String,
+    /// Books the job asked for.
+    pub books: usize,
+    /// Seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub outcome: SyncOutcome,
+}
+
+impl HistoryEntry {
+    pub fn succeeded(&self) -> bool {
+        matches!(self.outcome, SyncOutcome::Completed(_))
+    }
+}
+
+/// The persisted queue and history. Every change is written through to
+/// disk before the method returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncLog {
+    #[serde(skip)]
+    path: PathBuf,
+    next_id: u64,
+    queue: Vec<SyncJob>,
+    history: Vec<HistoryEntry>,
+}
+
+impl SyncLog {
+    /// Loads the log at `path`, starting an empty one if there is none.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, SyncError> {
+        let path = path.into();
+        let mut log = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|source| SyncError::Log {
+                path: path.clone(),
+                source,
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self {
+                path: PathBuf::new(),
+                next_id: 1,
+                queue: Vec::new(),
+                history: Vec::new(),
+            },
+            Err(err) => return Err(err.into()),
+        };
+        log.path = path;
+        Ok(log)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues a sync of `items` to `device` and returns its id.
+    pub fn enqueue(&mut self, device: &str, items: Vec<SyncItem>) -> Result<u64, SyncError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push(SyncJob {
+            id,
+            device: device.to_owned(),
+            items,
+            queued_at: unix_now(),
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Takes job `id` off the queue and logs how it ended. Returns `false`
+    /// if no such job was queued.
+    pub fn record(
+        &mut self,
+        id: u64,
+        result: &Result<SyncReport, SyncError>,
+    ) -> Result<bool, SyncError> {
+        let Some(index) = self.queue.iter().position(|job| job.id == id) else {
+            return Ok(false);
+        };
+        let job = self.queue.remove(index);
+        let outcome = match result {
+            Ok(report) => SyncOutcome::Completed(report.clone()),
+            Err(err) => SyncOutcome::Failed {
+                error: err.to_string(),
+            },
+        };
+        self.history.push(HistoryEntry {
+            job: job.id,
+            device: job.device,
+            books: job.items.len(),
+            finished_at: unix_now(),
+            outcome,
+        });
+        let excess = self.history.len().saturating_sub(HISTORY_LIMIT);
+        self.history.drain(..excess);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Drops job `id` without running it.
+    pub fn cancel(&mut self, id: u64) -> Result<Option<SyncJob>, SyncError> {
+        let Some(index) = self.queue.iter().position(|job| job.id == id) else {
+            return Ok(None);
+        };
+        let job = self.queue.remove(index);
+        self.save()?;
+        Ok(Some(job))
+    }
+
+    /// Queued jobs, oldest first.
+    pub fn pending(&self) -> &[SyncJob] {
+        &self.queue
+    }
+
+    /// Books waiting in queued jobs for `device`.
+    pub fn pending_books(&self, device: &str) -> usize {
+        self.queue
+            .iter()
+            .filter(|job| job.device == device)
+            .map(|job| job.items.len())
+            .sum()
+    }
+
+    /// Finished syncs, oldest first.
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// When a sync to `device` last completed.
+    pub fn last_synced(&self, device: &str) -> Option<SystemTime> {
+        self.history
+            .iter()
+            .rev()
+            .find(|entry| entry.device == device && entry.succeeded())
+            .map(|entry| UNIX_EPOCH + Duration::from_secs(entry.finished_at))
+    }
+
+    /// Writes to a temporary file and renames it over the log, so a crash
+    /// mid-save keeps the previous log.
+    fn save(&self) -> Result<(), SyncError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str) -> SyncItem {
+        SyncItem {
+            source: PathBuf::from(format!("library/{name}.xtc")),
+            dest: PathBuf::from(format!("Books/{name}.xtc")),
+            version: 1,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn queue_and_history_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/sync-log.json");
+        let mut log = SyncLog::open(&path).unwrap();
+        let done = log.enqueue("X4", vec![item("a")]).unwrap();
+        let interrupted = log.enqueue("X4", vec![item("b"), item("c")]).unwrap();
+        let report = SyncReport {
+            copied: 1,
+            ..SyncReport::default()
+        };
+        assert!(log.record(done, &Ok(report.clone())).unwrap());
+        assert!(!log.record(done, &Ok(report.clone())).unwrap());
+
+        let log = SyncLog::open(&path).unwrap();
+        assert_eq!(log.pending().len(), 1);
+        assert_eq!(log.pending()[0].id, interrupted);
+        assert_eq!(log.pending_books("X4"), 2);
+        assert_eq!(log.pending_books("X3"), 0);
+        assert_eq!(log.history()[0].outcome, SyncOutcome::Completed(report));
+        assert!(log.last_synced("X4").is_some());
+        assert!(log.last_synced("X3").is_none());
+    }
+
+    #[test]
+    fn failures_are_logged_but_do_not_count_as_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = SyncLog::open(dir.path().join("log.json")).unwrap();
+        let id = log.enqueue("X4", vec![item("a")]).unwrap();
+        let failed = Err(SyncError::InsufficientSpace {
+            needed: 10,
+            available: 5,
+        });
+        log.record(id, &failed).unwrap();
+        assert!(!log.history()[0].succeeded());
+        assert!(log.last_synced("X4").is_none());
+
+        let id = log.enqueue("X4", vec![item("a")]).unwrap();
+        assert_eq!(log.cancel(id).unwrap().unwrap().items.len(), 1);
+        assert!(log.pending().is_empty());
+
+        fs::write(log.path(), "{").unwrap();
+        assert!(matches!(
+            SyncLog::open(log.path()),
+            Err(SyncError::Log { .. })
+        ));
+    }
+}
@@ -0,0 +1,387 @@
+//! Sharing reading positions with KOReader through a KOSync server.
+//!
+//! KOSync (`koreader-sync-server`, or the public `sync.koreader.rocks`)
+//! stores one position per user and document. Documents are identified by
+//! [`document_id`], KOReader's partial MD5 of the file, so the same book
+//! matches on every device without uploading it. Positions are opaque
+//! strings (an XPointer or page number) plus a percentage.
+//!
+//! [`KoSyncClient::sync`] reconciles one book against a [`ProgressStore`],
+//! the library's record of where the user is, with the newer side winning.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::debug;
+
+use crate::SyncError;
+
+const ACCEPT: &str = "application/vnd.koreader.v1+json";
+
+/// KOReader's "binary" document id: the MD5 of 1 KiB samples at offsets 0,
+/// 1 KiB, 4 KiB, 16 KiB, ... up to 1 GiB, stopping at the end of the file.
+pub fn document_id(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut md5 = Md5::new();
+    let mut sample = Vec::with_capacity(1024);
+    for i in -1..=10i32 {
+        let offset = if i < 0 { 0 } else { 1024u64 << (2 * i) };
+        if offset >= len {
+            break;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        sample.clear();
+        (&mut file).take(1024).read_to_end(&mut sample)?;
+        md5.update(&sample);
+    }
+    Ok(hex(&md5.finalize()))
+}
+
+/// A reading position as the library records it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalProgress {
+    /// Where the reader is, in KOReader's terms (an XPointer for reflowable
+    /// books, a page number for fixed layout).
+    pub progress: String,
+    /// 0.0 to 1.0.
+    pub percentage: f64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A reading position stored on the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteProgress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f64,
+    /// Name of the device that pushed it.
+    pub device: String,
+    pub device_id: String,
+    /// Seconds since the Unix epoch, set by the server.
+    pub timestamp: u64,
+}
+
+/// Where the library keeps reading positions, keyed by [`document_id`].
+pub trait ProgressStore {
+    fn progress(&self, document: &str) -> Result<Option<LocalProgress>, SyncError>;
+    fn set_progress(&mut self, document: &str, progress: LocalProgress) -> Result<(), SyncError>;
+}
+
+/// What [`KoSyncClient::sync`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressSync {
+    /// The local position was newer and was uploaded.
+    Pushed,
+    /// Another device's position was newer and was stored locally.
+    Pulled,
+    /// Both sides agree, or neither has a position.
+    UpToDate,
+}
+
+/// A KOSync account on one server.
+pub struct KoSyncClient {
+    agent: ureq::Agent,
+    server: String,
+    username: String,
+    /// KOSync authenticates with the MD5 of the password, never the
+    /// password itself.
+    key: String,
+    device: String,
+    device_id: String,
+}
+
+impl std::fmt::Debug for KoSyncClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KoSyncClient")
+            .field("server", &self.server)
+            .field("username", &self.username)
+            .field("device", &self.device)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KoSyncClient {
+    /// `server` is the base URL, e.g. `https://sync.koreader.rocks`.
+    /// `device` names this app in other devices' sync prompts and
+    /// `device_id` tells its pushes apart from theirs.
+    pub fn new(
+        server: &str,
+        username: &str,
+        password: &str,
+        device: &str,
+        device_id: &str,
+    ) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(15))
+                .build(),
+            server: server.trim_end_matches('/').to_owned(),
+            username: username.to_owned(),
+            key: hex(&Md5::digest(password.as_bytes())),
+            device: device.to_owned(),
+            device_id: device_id.to_owned(),
+        }
+    }
+
+    /// Creates the account on the server.
+    pub fn register(&self) -> Result<(), SyncError> {
+        let body = json!({ "username": self.username, "password": self.key });
+        self.request("POST", "/users/create")
+            .send_json(body)
+            .map_err(rejected)?;
+        Ok(())
+    }
+
+    /// Checks the credentials.
+    pub fn authorize(&self) -> Result<(), SyncError> {
+        self.request("GET", "/users/auth")
+            .call()
+            .map_err(rejected)?;
+        Ok(())
+    }
+
+    /// The server's position for `document`, if any device pushed one.
+    pub fn pull(&self, document: &str) -> Result<Option<RemoteProgress>, SyncError> {
+        let response = self
+            .request("GET", &format!("/syncs/progress/{document}"))
+            .call()
+            .map_err(rejected)?;
+        // An unknown document comes back as `{}`.
+        let body: serde_json::Value = response.into_json()?;
+        if body.get("progress").is_none() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(body)?))
+    }
+
+    /// Uploads this device's position for `document`. Returns the server's
+    /// timestamp for it.
+    pub fn push(&self, document: &str, progress: &LocalProgress) -> Result<u64, SyncError> {
+        #[derive(Deserialize)]
+        struct Pushed {
+            timestamp: u64,
+        }
+        let body = json!({
+            "document": document,
+            "progress": progress.progress,
+            "percentage": progress.percentage,
+            "device": self.device,
+            "device_id": self.device_id,
+        });
+        let pushed: Pushed = self
+            .request("PUT", "/syncs/progress")
+            .send_json(body)
+            .map_err(rejected)?
+            .into_json()?;
+        Ok(pushed.timestamp)
+    }
+
+    /// Brings `store` and the server to the newer of their positions for
+    /// `document`. Our own earlier pushes never count as newer.
+    pub fn sync(
+        &self,
+        store: &mut dyn ProgressStore,
+        document: &str,
+    ) -> Result<ProgressSync, SyncError> {
+        let local = store.progress(document)?;
+        let remote = self.pull(document)?;
+        let result = match (local, remote) {
+            (None, None) => ProgressSync::UpToDate,
+            (Some(local), None) => {
+                self.push(document, &local)?;
+                ProgressSync::Pushed
+            }
+            (local, Some(remote))
+                if remote.device_id != self.device_id
+                    && local
+                        .as_ref()
+                        .is_none_or(|l| remote.timestamp > l.timestamp) =>
+            {
+                store.set_progress(
+                    document,
+                    LocalProgress {
+                        progress: remote.progress,
+                        percentage: remote.percentage,
+                        timestamp: remote.timestamp,
+                    },
+                )?;
+                ProgressSync::Pulled
+            }
+            (Some(local), Some(remote)) if local.progress != remote.progress => {
+                self.push(document, &local)?;
+                ProgressSync::Pushed
+            }
+            _ => ProgressSync::UpToDate,
+        };
+        debug!(document, ?result, "progress synced");
+        Ok(result)
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.agent
+            .request(method, &format!("{}{path}", self.server))
+            .set("Accept", ACCEPT)
+            .set("x-auth-user", &self.username)
+            .set("x-auth-key", &self.key)
+    }
+}
+
+/// Turns an HTTP error status into the server's message.
+fn rejected(err: ureq::Error) -> SyncError {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let message = response
+                .into_json::<serde_json::Value>()
+                .ok()
+                .and_then(|body| body.get("message")?.as_str().map(str::to_owned))
+                .unwrap_or_default();
+            SyncError::KoSync { status, message }
+        }
+        ureq::Error::Transport(transport) => SyncError::Io(io::Error::other(transport)),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tiny_http::{Method, Response, Server};
+
+    use super::*;
+
+    /// Just enough of koreader-sync-server for one user.
+    fn fake_server() -> (String, Arc<Server>) {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let stored: Mutex<HashMap<String, serde_json::Value>> = Mutex::default();
+        let clock = Mutex::new(1_000u64);
+        thread::spawn({
+            let server = Arc::clone(&server);
+            move || {
+                for mut request in server.incoming_requests() {
+                    let authorized = request.headers().iter().any(|h| {
+                        h.field.equiv("x-auth-key") && h.value == hex(&Md5::digest(b"secret"))
+                    });
+                    let url = request.url().to_owned();
+                    let reply = match (request.method(), url.as_str()) {
+                        _ if !authorized => (401, json!({ "message": "Unauthorized" })),
+                        (Method::Get, "/users/auth") => (200, json!({ "authorized": "OK" })),
+                        (Method::Put, "/syncs/progress") => {
+                            let mut body: serde_json::Value =
+                                serde_json::from_reader(request.as_reader()).unwrap();
+                            let mut now = clock.lock().unwrap();
+                            *now += 1;
+                            body["timestamp"] = json!(*now);
+                            let document = body["document"].as_str().unwrap().to_owned();
+                            stored.lock().unwrap().insert(document.clone(), body);
+                            (200, json!({ "document": document, "timestamp": *now }))
+                        }
+                        (Method::Get, path) => {
+                            let document = path.trim_start_matches("/syncs/progress/");
+                            let found = stored.lock().unwrap().get(document).cloned();
+                            (200, found.unwrap_or_else(|| json!({})))
+                        }
+                        _ => (404, json!({})),
+                    };
+                    let response =
+                        Response::from_string(reply.1.to_string()).with_status_code(reply.0);
+                    let _ = request.respond(response);
+                }
+            }
+        });
+        (format!("http://{addr}/"), server)
+    }
+
+    #[derive(Default)]
+    struct Memory(HashMap<String, LocalProgress>);
+
+    impl ProgressStore for Memory {
+        fn progress(&self, document: &str) -> Result<Option<LocalProgress>, SyncError> {
+            Ok(self.0.get(document).cloned())
+        }
+
+        fn set_progress(
+            &mut self,
+            document: &str,
+            progress: LocalProgress,
+        ) -> Result<(), SyncError> {
+            self.0.insert(document.to_owned(), progress);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn syncs_positions_between_devices() {
+        let (url, server) = fake_server();
+        let laptop = KoSyncClient::new(&url, "reader", "secret", "e-inky", "laptop");
+        let kobo = KoSyncClient::new(&url, "reader", "secret", "KOReader", "kobo");
+        laptop.authorize().unwrap();
+        let wrong = KoSyncClient::new(&url, "reader", "guess", "e-inky", "laptop");
+        assert!(matches!(
+            wrong.authorize(),
+            Err(SyncError::KoSync { status: 401, message }) if message == "Unauthorized"
+        ));
+
+        let mut library = Memory::default();
+        assert_eq!(
+            laptop.sync(&mut library, "doc").unwrap(),
+            ProgressSync::UpToDate
+        );
+        let at = |progress: &str, timestamp| LocalProgress {
+            progress: progress.into(),
+            percentage: 0.5,
+            timestamp,
+        };
+        library.set_progress("doc", at("/body/p[10]", 900)).unwrap();
+        assert_eq!(
+            laptop.sync(&mut library, "doc").unwrap(),
+            ProgressSync::Pushed
+        );
+        assert_eq!(
+            laptop.sync(&mut library, "doc").unwrap(),
+            ProgressSync::UpToDate
+        );
+
+        kobo.push("doc", &at("/body/p[42]", 0)).unwrap();
+        assert_eq!(
+            laptop.sync(&mut library, "doc").unwrap(),
+            ProgressSync::Pulled
+        );
+        assert_eq!(library.0["doc"].progress, "/body/p[42]");
+        assert_eq!(laptop.pull("doc").unwrap().unwrap().device, "KOReader");
+        server.unblock();
+    }
+
+    #[test]
+    fn document_ids_sample_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.epub");
+        std::fs::write(&path, b"").unwrap();
+        // Nothing sampled: the MD5 of no input.
+        assert_eq!(
+            document_id(&path).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+
+        let bytes: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        let mut expected = Md5::new();
+        for offset in [0, 1024, 4096, 16_384] {
+            expected.update(&bytes[offset..(offset + 1024).min(bytes.len())]);
+        }
+        assert_eq!(document_id(&path).unwrap(), hex(&expected.finalize()));
+    }
+}
@@ -0,0 +1,90 @@
+//! Getting the library onto the reader's microSD card.
+//!
+//! The X4 shows up as an ordinary removable FAT/exFAT volume. This crate
+//! finds it and copies books onto it. Planning and copying go through a
+//! [`SyncTarget`], so the same sync also works over MTP and in memory.
+
+mod detect;
+mod eject;
+mod execute;
+mod history;
+#[cfg(feature = "kosync")]
+mod kosync;
+mod manifest;
+#[cfg(feature = "mtp")]
+mod mtp;
+mod pace;
+mod plan;
+#[cfg(feature = "server")]
+mod server;
+mod target;
+mod transfer;
+mod walk;
+
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub use detect::{detect_devices, removable_volumes, Device};
+pub use eject::eject;
+pub use execute::{execute_plan, ExecuteOptions, SyncReport};
+pub use history::{HistoryEntry, SyncJob, SyncLog, SyncOutcome, HISTORY_LIMIT};
+#[cfg(feature = "kosync")]
+pub use kosync::{
+    document_id, KoSyncClient, LocalProgress, ProgressStore, ProgressSync, RemoteProgress,
+};
+pub use manifest::{Manifest, ManifestEntry, MANIFEST_PATH, MANIFEST_VERSION, TRASH_DIR};
+#[cfg(feature = "mtp")]
+pub use mtp::{detect_mtp_devices, MtpDevice};
+pub use plan::{
+    ConflictPolicy, DeleteMode, FileCopy, FileTally, PlanOptions, PlanPreview, Resolution,
+    SyncAction, SyncItem, SyncPlan,
+};
+#[cfg(feature = "server")]
+pub use server::{SyncServer, BOOKS_URL, MANIFEST_URL};
+pub use target::{FsTarget, MemoryTarget, ReadSeek, SyncTarget, Transfer, WriteCheck};
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("device manifest is unreadable: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("device manifest version {0} is newer than this app supports")]
+    UnsupportedManifest(u32),
+    #[error("sync needs {needed} bytes but the device has {available} free")]
+    InsufficientSpace { needed: u64, available: u64 },
+    #[error("destination {} is not a file path inside the device", .0.display())]
+    InvalidDestination(PathBuf),
+    #[error("{} and {} would both go to {}", first.display(), second.display(), dest.display())]
+    DuplicateDestination {
+        dest: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    #[error("could not eject {}: {reason}", path.display())]
+    Eject { path: PathBuf, reason: String },
+    #[error("sync log {} is unreadable: {source}", path.display())]
+    Log {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{} failed verification after copying: {source}", path.display())]
+    Verify {
+        path: PathBuf,
+        source: xtc::XtcError,
+    },
+    #[cfg(feature = "mtp")]
+    #[error(transparent)]
+    Usb(#[from] rusb::Error),
+    #[cfg(feature = "mtp")]
+    #[error("MTP device refused the operation with code {0:#06x}")]
+    MtpResponse(u16),
+    #[cfg(feature = "mtp")]
+    #[error("malformed MTP exchange: {0}")]
+    MtpProtocol(&'static str),
+    #[cfg(feature = "kosync")]
+    #[error("progress server answered {status}: {message}")]
+    KoSync { status: u16, message: String },
+}
@@ -0,0 +1,180 @@
+//! The record of what a previous sync put on the device.
+//!
+//! Stored as JSON at [`MANIFEST_PATH`] on the target. Targets that write
+//! atomically (see [`FsTarget`](crate::FsTarget)) keep the previous manifest
+//! intact when a sync is cut short.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{SyncError, SyncTarget};
+
+/// Location of the manifest, relative to the device root.
+pub const MANIFEST_PATH: &str = ".e-inky/manifest.json";
+
+/// Where [`DeleteMode::Trash`](crate::DeleteMode::Trash) moves books,
+/// relative to the device root.
+pub const TRASH_DIR: &str = ".e-inky/trash";
+
+/// Bumped when the manifest layout changes incompatibly.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// One synced book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Lowercase hex SHA-256 of the file as copied.
+    pub sha256: String,
+    pub size: u64,
+    /// The library's version of the book (e.g. its conversion revision).
+    pub version: u32,
+}
+
+/// Synced books keyed by their `/`-separated path on the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub books: BTreeMap<String, ManifestEntry>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            books: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Reads the manifest from `target`; a device never synced has none
+    /// and gets an empty one.
+    pub fn load(target: &dyn SyncTarget) -> Result<Self, SyncError> {
+        let bytes = match target.read(Path::new(MANIFEST_PATH)) {
+            Ok(bytes) => bytes,
+            Err(SyncError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(err) => return Err(err),
+        };
+        let manifest: Self = serde_json::from_slice(&bytes)?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(SyncError::UnsupportedManifest(manifest.version));
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, target: &dyn SyncTarget) -> Result<(), SyncError> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        target.write(
+            Path::new(MANIFEST_PATH),
+            &mut Cursor::new(json),
+            &|_| Ok(()),
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, dest: &Path) -> Option<&ManifestEntry> {
+        self.books.get(&key(dest))
+    }
+
+    pub fn insert(&mut self, dest: &Path, entry: ManifestEntry) {
+        self.books.insert(key(dest), entry);
+    }
+
+    pub fn remove(&mut self, dest: &Path) -> Option<ManifestEntry> {
+        self.books.remove(&key(dest))
+    }
+}
+
+/// Manifest key for a device-relative path: components joined with `/` so
+/// the manifest reads the same from every OS.
+pub(crate) fn key(dest: &Path) -> String {
+    dest.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Lowercase hex SHA-256 of the file at `path`.
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub(crate) fn hash_bytes(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::{FsTarget, MemoryTarget};
+
+    #[test]
+    fn round_trips_through_the_device() {
+        let card = tempfile::tempdir().unwrap();
+        let target = FsTarget::new(card.path());
+        assert_eq!(Manifest::load(&target).unwrap(), Manifest::default());
+
+        let mut manifest = Manifest::default();
+        manifest.insert(
+            Path::new("Books").join("a.xtc").as_path(),
+            ManifestEntry {
+                sha256: "00".repeat(32),
+                size: 12,
+                version: 3,
+            },
+        );
+        manifest.save(&target).unwrap();
+        let loaded = Manifest::load(&target).unwrap();
+        assert_eq!(loaded, manifest);
+        assert!(loaded.books.contains_key("Books/a.xtc"));
+        assert!(!card.path().join(".e-inky/manifest.json.partial").exists());
+    }
+
+    #[test]
+    fn rejects_newer_manifest() {
+        let target = MemoryTarget::new();
+        let json = r#"{"version": 99, "books": {}}"#;
+        target
+            .write(
+                Path::new(MANIFEST_PATH),
+                &mut Cursor::new(json),
+                &|_| Ok(()),
+            )
+            .unwrap();
+        assert!(matches!(
+            Manifest::load(&target),
+            Err(SyncError::UnsupportedManifest(99))
+        ));
+    }
+
+    #[test]
+    fn hashes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc");
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,967 @@
+//! Readers that expose their storage over MTP instead of as a mass-storage
+//! volume.
+//!
+//! MTP is PTP over USB bulk endpoints: every operation is a command
+//! container, an optional data phase in either direction, and a response
+//! container. Only the operations a sync needs are spoken here. Objects are
+//! addressed by handle rather than path, so paths are resolved by walking
+//! folders by name from the storage root; folder listings are cached for
+//! the life of the session.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+use tracing::debug;
+
+use crate::target::not_found;
+use crate::{ReadSeek, SyncError, SyncTarget, Transfer, WriteCheck};
+
+const OPEN_SESSION: u16 = 0x1002;
+const GET_STORAGE_IDS: u16 = 0x1004;
+const GET_STORAGE_INFO: u16 = 0x1005;
+const GET_OBJECT_HANDLES: u16 = 0x1007;
+const GET_OBJECT_INFO: u16 = 0x1008;
+const GET_OBJECT: u16 = 0x1009;
+const DELETE_OBJECT: u16 = 0x100B;
+const SEND_OBJECT_INFO: u16 = 0x100C;
+const SEND_OBJECT: u16 = 0x100D;
+const SET_OBJECT_PROP_VALUE: u16 = 0x9804;
+
+const OBJECT_FILE_NAME: u32 = 0xDC07;
+
+const RESPONSE_OK: u16 = 0x2001;
+const SESSION_ALREADY_OPEN: u16 = 0x201E;
+
+const FORMAT_UNDEFINED: u16 = 0x3000;
+const FORMAT_ASSOCIATION: u16 = 0x3001;
+const ASSOCIATION_GENERIC_FOLDER: u16 = 0x0001;
+
+/// Parent handle meaning "the top level of the storage".
+const ROOT: u32 = 0xFFFF_FFFF;
+
+const COMMAND: u16 = 1;
+const DATA: u16 = 2;
+const RESPONSE: u16 = 3;
+
+/// Container length, type, code and transaction ID.
+const CONTAINER_HEADER_LEN: usize = 12;
+
+/// Fixed-size part of an ObjectInfo dataset, before the file name.
+const OBJECT_INFO_FIXED_LEN: usize = 52;
+
+/// Generous, since a whole book goes out in one bulk transfer.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Name a replacement file is sent under until it has been checked. It
+/// ends in `.partial`, so the device and a later sync ignore a leftover.
+const UPLOAD_NAME: &str = ".e-inky-upload.partial";
+
+/// One PTP container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Container {
+    kind: u16,
+    code: u16,
+    transaction: u32,
+    payload: Vec<u8>,
+}
+
+impl Container {
+    fn encode(&self) -> Vec<u8> {
+        let len = (CONTAINER_HEADER_LEN + self.payload.len()) as u32;
+        let mut bytes = Vec::with_capacity(len as usize);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        bytes.extend_from_slice(&self.code.to_le_bytes());
+        bytes.extend_from_slice(&self.transaction.to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SyncError> {
+        let mut fields = Fields::new(bytes);
+        let len = fields.u32()? as usize;
+        if len != bytes.len() {
+            return Err(SyncError::MtpProtocol("container length mismatch"));
+        }
+        Ok(Self {
+            kind: fields.u16()?,
+            code: fields.u16()?,
+            transaction: fields.u32()?,
+            payload: bytes[CONTAINER_HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// The payload read as the u32 parameters of a command or response.
+    fn params(&self) -> Vec<u32> {
+        self.payload
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes(p.try_into().expect("chunk of 4")))
+            .collect()
+    }
+}
+
+/// Little-endian reader over a PTP dataset.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SyncError> {
+        if self.bytes.len() < len {
+            return Err(SyncError::MtpProtocol("truncated dataset"));
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, SyncError> {
+        Ok(u16::from_le_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, SyncError> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, SyncError> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    /// A u32 count followed by that many u32s.
+    fn u32_array(&mut self) -> Result<Vec<u32>, SyncError> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.u32()).collect()
+    }
+
+    /// A u8 count of UTF-16 units, including the terminating NUL, then the
+    /// units.
+    fn string(&mut self) -> Result<String, SyncError> {
+        let count = self.take(1)?[0] as usize;
+        let units: Vec<u16> = self
+            .take(count * 2)?
+            .chunks_exact(2)
+            .map(|u| u16::from_le_bytes([u[0], u[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        String::from_utf16(&units).map_err(|_| SyncError::MtpProtocol("invalid UTF-16 string"))
+    }
+}
+
+/// Fails on strings of more than 254 UTF-16 units, which with the NUL
+/// don't fit the count byte. Names are never shortened here: the file
+/// would then not be found under the path it was written to.
+fn put_string(out: &mut Vec<u8>, s: &str) -> Result<(), SyncError> {
+    if s.is_empty() {
+        out.push(0);
+        return Ok(());
+    }
+    let units: Vec<u16> = s.encode_utf16().chain([0]).collect();
+    let count = u8::try_from(units.len())
+        .map_err(|_| SyncError::MtpProtocol("name is longer than MTP allows"))?;
+    out.push(count);
+    for unit in units {
+        out.extend_from_slice(&unit.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// The ObjectInfo fields a sync cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ObjectInfo {
+    name: String,
+    folder: bool,
+    size: u32,
+    parent: u32,
+}
+
+impl ObjectInfo {
+    fn encode(&self, storage: u32) -> Result<Vec<u8>, SyncError> {
+        let (format, association) = if self.folder {
+            (FORMAT_ASSOCIATION, ASSOCIATION_GENERIC_FOLDER)
+        } else {
+            (FORMAT_UNDEFINED, 0)
+        };
+        let mut out = Vec::with_capacity(OBJECT_INFO_FIXED_LEN + 2 * self.name.len() + 8);
+        out.extend_from_slice(&storage.to_le_bytes());
+        out.extend_from_slice(&format.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // protection status
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // thumb format
+        out.extend_from_slice(&[0; 24]); // thumb size and pixel dimensions, image dimensions, bit depth
+        out.extend_from_slice(&self.parent.to_le_bytes());
+        out.extend_from_slice(&association.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes()); // association description
+        out.extend_from_slice(&0u32.to_le_bytes()); // sequence number
+        put_string(&mut out, &self.name)?;
+        for _ in ["capture date", "modification date", "keywords"] {
+            put_string(&mut out, "")?;
+        }
+        Ok(out)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SyncError> {
+        let mut fields = Fields::new(bytes);
+        fields.u32()?; // storage
+        let format = fields.u16()?;
+        fields.u16()?; // protection status
+        let size = fields.u32()?;
+        fields.take(26)?; // thumb format through image bit depth
+        let parent = fields.u32()?;
+        fields.take(10)?; // association type and description, sequence number
+        Ok(Self {
+            name: fields.string()?,
+            folder: format == FORMAT_ASSOCIATION,
+            size,
+            parent,
+        })
+    }
+}
+
+/// Moves whole containers to and from the device.
+trait Transport {
+    fn send(&mut self, container: &[u8]) -> Result<(), SyncError>;
+    fn receive(&mut self) -> Result<Vec<u8>, SyncError>;
+}
+
+/// An open PTP session: numbers transactions and checks responses.
+struct Session<T> {
+    transport: T,
+    next_transaction: u32,
+}
+
+impl<T: Transport> Session<T> {
+    fn open(transport: T) -> Result<Self, SyncError> {
+        let mut session = Self {
+            transport,
+            next_transaction: 0,
+        };
+        match session.call(OPEN_SESSION, &[1], None) {
+            Ok(_) | Err(SyncError::MtpResponse(SESSION_ALREADY_OPEN)) => Ok(session),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Runs one operation, sending `data` as its data phase. Returns the
+    /// data the device sent back (empty if none) and the response
+    /// parameters.
+    fn call(
+        &mut self,
+        code: u16,
+        params: &[u32],
+        data: Option<&[u8]>,
+    ) -> Result<(Vec<u8>, Vec<u32>), SyncError> {
+        let transaction = self.next_transaction;
+        self.next_transaction = self.next_transaction.wrapping_add(1);
+        let command = Container {
+            kind: COMMAND,
+            code,
+            transaction,
+            payload: params.iter().flat_map(|p| p.to_le_bytes()).collect(),
+        };
+        self.transport.send(&command.encode())?;
+        if let Some(data) = data {
+            let data = Container {
+                kind: DATA,
+                code,
+                transaction,
+                payload: data.to_vec(),
+            };
+            self.transport.send(&data.encode())?;
+        }
+
+        let mut incoming = Vec::new();
+        loop {
+            let container = Container::decode(&self.transport.receive()?)?;
+            if container.transaction != transaction {
+                return Err(SyncError::MtpProtocol("reply to another transaction"));
+            }
+            match container.kind {
+                DATA => incoming = container.payload,
+                RESPONSE if container.code == RESPONSE_OK => {
+                    return Ok((incoming, container.params()))
+                }
+                RESPONSE => return Err(SyncError::MtpResponse(container.code)),
+                _ => return Err(SyncError::MtpProtocol("unexpected container type")),
+            }
+        }
+    }
+}
+
+/// One cached directory entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Object {
+    handle: u32,
+    info: ObjectInfo,
+}
+
+/// File operations on one storage of a device, by path.
+struct Storage<T> {
+    session: Session<T>,
+    id: u32,
+    /// Children of each folder handle listed so far.
+    listings: HashMap<u32, Vec<Object>>,
+}
+
+impl<T: Transport> Storage<T> {
+    /// Opens the first storage the device reports; the X4 has only its
+    /// card.
+    fn open(transport: T) -> Result<Self, SyncError> {
+        let mut session = Session::open(transport)?;
+        let (ids, _) = session.call(GET_STORAGE_IDS, &[], None)?;
+        let id = *Fields::new(&ids)
+            .u32_array()?
+            .first()
+            .ok_or(SyncError::MtpProtocol("device has no storage"))?;
+        Ok(Self {
+            session,
+            id,
+            listings: HashMap::new(),
+        })
+    }
+
+    fn children(&mut self, parent: u32) -> Result<&mut Vec<Object>, SyncError> {
+        if !self.listings.contains_key(&parent) {
+            let (data, _) = self
+                .session
+                .call(GET_OBJECT_HANDLES, &[self.id, 0, parent], None)?;
+            let mut objects = Vec::new();
+            for handle in Fields::new(&data).u32_array()? {
+                let (info, _) = self.session.call(GET_OBJECT_INFO, &[handle], None)?;
+                objects.push(Object {
+                    handle,
+                    info: ObjectInfo::decode(&info)?,
+                });
+            }
+            self.listings.insert(parent, objects);
+        }
+        Ok(self
+            .listings
+            .get_mut(&parent)
+            .expect("listing was just cached"))
+    }
+
+    /// The handle of the folder at `path`, creating missing folders when
+    /// `create` is set.
+    fn folder(&mut self, path: &Path, create: bool) -> Result<Option<u32>, SyncError> {
+        let mut parent = ROOT;
+        for name in names(path)? {
+            let existing = self
+                .children(parent)?
+                .iter()
+                .find(|o| o.info.folder && o.info.name == name)
+                .map(|o| o.handle);
+            parent = match existing {
+                Some(handle) => handle,
+                None if create => self.send(parent, name, None)?,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(parent))
+    }
+
+    /// The file at `path` and the handle of its folder.
+    fn lookup(&mut self, path: &Path) -> Result<Option<(u32, Object)>, SyncError> {
+        let (dir, name) = split(path)?;
+        let Some(parent) = self.folder(dir, false)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .children(parent)?
+            .iter()
+            .find(|o| !o.info.folder && o.info.name == name)
+            .map(|o| (parent, o.clone())))
+    }
+
+    /// Creates `name` under `parent`: a folder when `data` is `None`.
+    fn send(&mut self, parent: u32, name: &str, data: Option<&[u8]>) -> Result<u32, SyncError> {
+        let size = data
+            .map_or(Ok(0), |d| u32::try_from(d.len()))
+            .map_err(|_| SyncError::MtpProtocol("file is too large for MTP"))?;
+        // List the folder first so the new object is not fetched twice.
+        self.children(parent)?;
+        let info = ObjectInfo {
+            name: name.to_owned(),
+            folder: data.is_none(),
+            size,
+            parent,
+        };
+        let (_, params) = self.session.call(
+            SEND_OBJECT_INFO,
+            &[self.id, parent],
+            Some(&info.encode(self.id)?),
+        )?;
+        let handle = *params
+            .get(2)
+            .ok_or(SyncError::MtpProtocol("SendObjectInfo returned no handle"))?;
+        if let Some(data) = data {
+            self.session.call(SEND_OBJECT, &[], Some(data))?;
+        }
+        self.children(parent)?.push(Object { handle, info });
+        Ok(handle)
+    }
+
+    fn files(&mut self) -> Result<Vec<(PathBuf, u64)>, SyncError> {
+        let mut files = Vec::new();
+        let mut pending = vec![(ROOT, PathBuf::new())];
+        while let Some((handle, dir)) = pending.pop() {
+            for object in self.children(handle)?.clone() {
+                let path = dir.join(&object.info.name);
+                if object.info.folder {
+                    if !object.info.name.starts_with('.') {
+                        pending.push((object.handle, path));
+                    }
+                } else {
+                    files.push((path, u64::from(object.info.size)));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<Vec<u8>, SyncError> {
+        let (_, object) = self.lookup(path)?.ok_or_else(|| not_found(path))?;
+        Ok(self.session.call(GET_OBJECT, &[object.handle], None)?.0)
+    }
+
+    fn write(&mut self, path: &Path, data: &[u8]) -> Result<(), SyncError> {
+        if self.lookup(path)?.is_some() {
+            self.delete(path)?;
+        }
+        let (dir, name) = split(path)?;
+        let parent = self.folder(dir, true)?.expect("folders are created");
+        self.send(parent, name, Some(data))?;
+        Ok(())
+    }
+
+    /// Renames the file at `path` within its folder.
+    fn rename_file(&mut self, path: &Path, name: &str) -> Result<(), SyncError> {
+        let (parent, object) = self.lookup(path)?.ok_or_else(|| not_found(path))?;
+        let mut value = Vec::new();
+        put_string(&mut value, name)?;
+        self.session.call(
+            SET_OBJECT_PROP_VALUE,
+            &[object.handle, OBJECT_FILE_NAME],
+            Some(&value),
+        )?;
+        for o in self.children(parent)? {
+            if o.handle == object.handle {
+                o.info.name = name.to_owned();
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<(), SyncError> {
+        let (parent, object) = self.lookup(path)?.ok_or_else(|| not_found(path))?;
+        self.session
+            .call(DELETE_OBJECT, &[object.handle, 0], None)?;
+        self.children(parent)?.retain(|o| o.handle != object.handle);
+        Ok(())
+    }
+
+    fn free_space(&mut self) -> Result<u64, SyncError> {
+        let (info, _) = self.session.call(GET_STORAGE_INFO, &[self.id], None)?;
+        let mut fields = Fields::new(&info);
+        fields.take(6)?; // storage type, filesystem type, access capability
+        fields.u64()?; // max capacity
+        fields.u64()
+    }
+}
+
+/// The `/`-free names making up a relative path.
+fn names(path: &Path) -> Result<Vec<&str>, SyncError> {
+    path.components()
+        .map(|c| match c {
+            Component::Normal(name) => name
+                .to_str()
+                .ok_or_else(|| SyncError::InvalidDestination(path.to_path_buf())),
+            _ => Err(SyncError::InvalidDestination(path.to_path_buf())),
+        })
+        .collect()
+}
+
+fn split(path: &Path) -> Result<(&Path, &str), SyncError> {
+    let invalid = || SyncError::InvalidDestination(path.to_path_buf());
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(invalid)?;
+    Ok((path.parent().ok_or_else(invalid)?, name))
+}
+
+/// Bulk endpoints of a claimed MTP interface.
+struct UsbTransport {
+    handle: DeviceHandle<Context>,
+    bulk_in: u8,
+    bulk_out: u8,
+    max_packet: usize,
+}
+
+impl Transport for UsbTransport {
+    fn send(&mut self, container: &[u8]) -> Result<(), SyncError> {
+        self.handle.write_bulk(self.bulk_out, container, TIMEOUT)?;
+        // A transfer that fills its last packet exactly needs a zero-length
+        // packet to end it.
+        if container.len().is_multiple_of(self.max_packet) {
+            self.handle.write_bulk(self.bulk_out, &[], TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, SyncError> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut container = Vec::new();
+        loop {
+            let n = self.handle.read_bulk(self.bulk_in, &mut buf, TIMEOUT)?;
+            container.extend_from_slice(&buf[..n]);
+            if container.len() >= CONTAINER_HEADER_LEN {
+                let len = u32::from_le_bytes(container[..4].try_into().expect("4 bytes"));
+                if container.len() >= len as usize {
+                    container.truncate(len as usize);
+                    return Ok(container);
+                }
+            }
+            // Zero-length packets ending the previous transfer are skipped.
+        }
+    }
+}
+
+/// A reader connected over MTP.
+///
+/// A session serves one operation at a time, so concurrent copies take
+/// turns.
+pub struct MtpDevice {
+    name: String,
+    storage: Mutex<Storage<UsbTransport>>,
+}
+
+impl std::fmt::Debug for MtpDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MtpDevice")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MtpDevice {
+    /// The product name the device reports over USB.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn storage(&self) -> MutexGuard<'_, Storage<UsbTransport>> {
+        self.storage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SyncTarget for MtpDevice {
+    fn files(&self) -> Result<Vec<(PathBuf, u64)>, SyncError> {
+        self.storage().files()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, SyncError> {
+        self.storage().read(path)
+    }
+
+    /// MTP cannot replace an object in place. A replacement is sent under
+    /// a temporary name and checked on a fresh read first; only then is
+    /// the old book deleted and the new one renamed into place, so a
+    /// failed check leaves the old book untouched.
+    fn write(
+        &self,
+        path: &Path,
+        data: &mut dyn ReadSeek,
+        check: &WriteCheck<'_>,
+    ) -> Result<Transfer, SyncError> {
+        let mut storage = self.storage();
+        check_written(&mut *storage, path, data, check)
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), SyncError> {
+        self.storage().delete(path)
+    }
+
+    fn free_space(&self) -> Result<u64, SyncError> {
+        self.storage().free_space()
+    }
+}
+
+fn check_written<T: Transport>(
+    storage: &mut Storage<T>,
+    path: &Path,
+    data: &mut dyn ReadSeek,
+    check: &WriteCheck<'_>,
+) -> Result<Transfer, SyncError> {
+    let mut bytes = Vec::new();
+    data.read_to_end(&mut bytes)?;
+    let (dir, name) = split(path)?;
+    let replacing = storage.lookup(path)?.is_some();
+    let upload = match replacing {
+        true => dir.join(UPLOAD_NAME),
+        false => path.to_path_buf(),
+    };
+    storage.write(&upload, &bytes)?;
+    if let Err(err) = check(&mut Cursor::new(storage.read(&upload)?)) {
+        storage.delete(&upload)?;
+        return Err(err);
+    }
+    if replacing {
+        storage.delete(path)?;
+        if let Err(err) = storage.rename_file(&upload, name) {
+            debug!(%err, "device cannot rename; sending the book again");
+            storage.write(path, &bytes)?;
+            storage.delete(&upload)?;
+        }
+    }
+    Ok(Transfer::Fresh)
+}
+
+/// Connects to every USB device offering an MTP interface. Devices that
+/// cannot be opened, e.g. because another program holds them, are skipped.
+pub fn detect_mtp_devices() -> Result<Vec<MtpDevice>, SyncError> {
+    let context = Context::new()?;
+    let mut found = Vec::new();
+    for device in context.devices()?.iter() {
+        match open_device(&device) {
+            Ok(Some(mtp)) => found.push(mtp),
+            Ok(None) => {}
+            Err(err) => debug!(
+                bus = device.bus_number(),
+                address = device.address(),
+                %err,
+                "skipping USB device"
+            ),
+        }
+    }
+    Ok(found)
+}
+
+fn open_device(device: &Device<Context>) -> Result<Option<MtpDevice>, SyncError> {
+    let descriptor = device.device_descriptor()?;
+    let config = device.active_config_descriptor()?;
+    for interface in config.interfaces() {
+        for setting in interface.descriptors() {
+            let still_image = (setting.class_code(), setting.sub_class_code()) == (6, 1);
+            let vendor = setting.class_code() == 0xFF && setting.num_endpoints() == 3;
+            if !still_image && !vendor {
+                continue;
+            }
+            let handle = device.open()?;
+            // Android and others use a vendor class and name the interface.
+            let label = setting
+                .description_string_index()
+                .and_then(|index| handle.read_string_descriptor_ascii(index).ok());
+            if !still_image && label.as_deref() != Some("MTP") {
+                continue;
+            }
+            let endpoint = |dir| {
+                setting
+                    .endpoint_descriptors()
+                    .find(|e| e.transfer_type() == TransferType::Bulk && e.direction() == dir)
+            };
+            let (Some(bulk_in), Some(bulk_out)) =
+                (endpoint(Direction::In), endpoint(Direction::Out))
+            else {
+                continue;
+            };
+            let _ = handle.set_auto_detach_kernel_driver(true);
+            handle.claim_interface(setting.interface_number())?;
+            let name = handle
+                .read_product_string_ascii(&descriptor)
+                .unwrap_or_else(|_| "MTP device".to_owned());
+            let transport = UsbTransport {
+                handle,
+                bulk_in: bulk_in.address(),
+                bulk_out: bulk_out.address(),
+                max_packet: usize::from(bulk_out.max_packet_size()).max(1),
+            };
+            return Ok(Some(MtpDevice {
+                name,
+                storage: Mutex::new(Storage::open(transport)?),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers operations from an in-memory object store, the way a device
+    /// would.
+    #[derive(Default)]
+    struct FakeDevice {
+        objects: HashMap<u32, (ObjectInfo, Vec<u8>)>,
+        next_handle: u32,
+        /// Handle of the last SendObjectInfo, awaiting SendObject.
+        pending: Option<u32>,
+        /// Answer SetObjectPropValue with "operation not supported".
+        no_rename: bool,
+        command: Option<Container>,
+        replies: Vec<Vec<u8>>,
+    }
+
+    impl FakeDevice {
+        fn answer(&mut self, command: &Container, data: Option<Vec<u8>>) {
+            let p = command.params();
+            let array = |items: Vec<u32>| {
+                let mut out = (items.len() as u32).to_le_bytes().to_vec();
+                out.extend(items.iter().flat_map(|i| i.to_le_bytes()));
+                out
+            };
+            let mut response = Vec::new();
+            let mut code = RESPONSE_OK;
+            let outgoing = match command.code {
+                OPEN_SESSION => None,
+                GET_STORAGE_IDS => Some(array(vec![0x0001_0001])),
+                GET_STORAGE_INFO => {
+                    let mut info = vec![0; 6];
+                    info.extend(1000u64.to_le_bytes());
+                    info.extend(400u64.to_le_bytes());
+                    Some(info)
+                }
+                GET_OBJECT_HANDLES => {
+                    let mut children: Vec<u32> = self
+                        .objects
+                        .iter()
+                        .filter(|(_, (info, _))| info.parent == p[2])
+                        .map(|(&h, _)| h)
+                        .collect();
+                    children.sort();
+                    Some(array(children))
+                }
+                GET_OBJECT_INFO => Some(self.objects[&p[0]].0.encode(p[0]).unwrap()),
+                GET_OBJECT => Some(self.objects[&p[0]].1.clone()),
+                DELETE_OBJECT => {
+                    self.objects.remove(&p[0]).unwrap();
+                    None
+                }
+                SEND_OBJECT_INFO => {
+                    let info = ObjectInfo::decode(&data.unwrap()).unwrap();
+                    self.next_handle += 1;
+                    self.objects.insert(self.next_handle, (info, Vec::new()));
+                    self.pending = Some(self.next_handle);
+                    response = vec![p[0], p[1], self.next_handle];
+                    None
+                }
+                SEND_OBJECT => {
+                    let handle = self.pending.take().unwrap();
+                    self.objects.get_mut(&handle).unwrap().1 = data.unwrap();
+                    None
+                }
+                SET_OBJECT_PROP_VALUE if self.no_rename => {
+                    code = 0x2005;
+                    None
+                }
+                SET_OBJECT_PROP_VALUE => {
+                    assert_eq!(p[1], OBJECT_FILE_NAME);
+                    let name = Fields::new(&data.unwrap()).string().unwrap();
+                    self.objects.get_mut(&p[0]).unwrap().0.name = name;
+                    None
+                }
+                code => panic!("unexpected operation {code:#x}"),
+            };
+            let mut reply = |kind, code, payload| {
+                let container = Container {
+                    kind,
+                    code,
+                    transaction: command.transaction,
+                    payload,
+                };
+                self.replies.push(container.encode());
+            };
+            if let Some(payload) = outgoing {
+                reply(DATA, command.code, payload);
+            }
+            let params = response.iter().flat_map(|v| v.to_le_bytes()).collect();
+            reply(RESPONSE, code, params);
+        }
+    }
+
+    impl Transport for FakeDevice {
+        fn send(&mut self, bytes: &[u8]) -> Result<(), SyncError> {
+            let container = Container::decode(bytes)?;
+            match container.kind {
+                COMMAND
+                    if [SEND_OBJECT_INFO, SEND_OBJECT, SET_OBJECT_PROP_VALUE]
+                        .contains(&container.code) =>
+                {
+                    self.command = Some(container)
+                }
+                COMMAND => self.answer(&container, None),
+                DATA => {
+                    let command = self.command.take().unwrap();
+                    self.answer(&command, Some(container.payload));
+                }
+                kind => panic!("host sent container type {kind}"),
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, SyncError> {
+            Ok(self.replies.remove(0))
+        }
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let mut out = Vec::new();
+        put_string(&mut out, "Dune – Part 1.xtc").unwrap();
+        put_string(&mut out, "").unwrap();
+        let longest = "a".repeat(254);
+        put_string(&mut out, &longest).unwrap();
+        let mut fields = Fields::new(&out);
+        assert_eq!(fields.string().unwrap(), "Dune – Part 1.xtc");
+        assert_eq!(fields.string().unwrap(), "");
+        assert_eq!(fields.string().unwrap(), longest);
+        assert!(fields.bytes.is_empty());
+    }
+
+    #[test]
+    fn names_too_long_for_a_count_byte_are_refused() {
+        // What the default FileNaming allows: 255 characters, plus NUL.
+        let info = ObjectInfo {
+            name: format!("{}.xtc", "a".repeat(251)),
+            folder: false,
+            size: 1,
+            parent: ROOT,
+        };
+        assert!(matches!(info.encode(1), Err(SyncError::MtpProtocol(_))));
+        let mut storage = Storage::open(FakeDevice::default()).unwrap();
+        let path = PathBuf::from("Books").join(&info.name);
+        assert!(storage.write(&path, b"x").is_err());
+        assert!(storage.lookup(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn object_info_round_trips() {
+        let info = ObjectInfo {
+            name: "book.xtc".into(),
+            folder: false,
+            size: 1234,
+            parent: 7,
+        };
+        let bytes = info.encode(1).unwrap();
+        assert_eq!(&bytes[..4], &1u32.to_le_bytes());
+        assert_eq!(bytes.len(), OBJECT_INFO_FIXED_LEN + 1 + 2 * 9 + 3);
+        assert_eq!(ObjectInfo::decode(&bytes).unwrap(), info);
+    }
+
+    #[test]
+    fn error_responses_surface_their_code() {
+        struct Refuses;
+        impl Transport for Refuses {
+            fn send(&mut self, _: &[u8]) -> Result<(), SyncError> {
+                Ok(())
+            }
+            fn receive(&mut self) -> Result<Vec<u8>, SyncError> {
+                Ok(Container {
+                    kind: RESPONSE,
+                    code: 0x2019, // device busy
+                    transaction: 0,
+                    payload: Vec::new(),
+                }
+                .encode())
+            }
+        }
+        assert!(matches!(
+            Session::open(Refuses),
+            Err(SyncError::MtpResponse(0x2019))
+        ));
+    }
+
+    #[test]
+    fn writes_reads_lists_and_deletes_by_path() {
+        let mut storage = Storage::open(FakeDevice::default()).unwrap();
+        let book = Path::new("Books/SF/dune.xtc");
+        storage.write(book, b"first").unwrap();
+        storage.write(book, b"second").unwrap();
+        storage.write(Path::new("top.xtc"), b"x").unwrap();
+        assert_eq!(storage.read(book).unwrap(), b"second");
+        assert_eq!(
+            storage.files().unwrap(),
+            [
+                (PathBuf::from("Books/SF/dune.xtc"), 6),
+                (PathBuf::from("top.xtc"), 1)
+            ]
+        );
+        // Folders are created once and reused.
+        let folders = storage.session.transport.objects.values();
+        assert_eq!(folders.filter(|(info, _)| info.folder).count(), 2);
+
+        storage.delete(book).unwrap();
+        assert!(storage.read(book).is_err());
+        assert_eq!(storage.free_space().unwrap(), 400);
+
+        // A write that fails its check is taken back off the device.
+        let rejected = check_written(&mut storage, book, &mut Cursor::new(b"bad"), &|written| {
+            let mut bytes = Vec::new();
+            written.read_to_end(&mut bytes)?;
+            assert_eq!(bytes, b"bad");
+            Err(SyncError::MtpProtocol("rejected"))
+        });
+        assert!(matches!(rejected, Err(SyncError::MtpProtocol("rejected"))));
+        assert!(storage.read(book).is_err());
+
+        // A fresh session sees what the device holds.
+        let device = storage.session.transport;
+        let mut reopened = Storage::open(device).unwrap();
+        assert_eq!(reopened.files().unwrap(), [(PathBuf::from("top.xtc"), 1)]);
+    }
+
+    fn replace(storage: &mut Storage<FakeDevice>, path: &Path, data: &[u8], ok: bool) {
+        let result = check_written(
+            storage,
+            path,
+            &mut Cursor::new(data.to_vec()),
+            &|_| match ok {
+                true => Ok(()),
+                false => Err(SyncError::MtpProtocol("rejected")),
+            },
+        );
+        assert_eq!(result.is_ok(), ok);
+    }
+
+    #[test]
+    fn replacing_keeps_the_old_book_until_the_new_one_checks_out() {
+        for no_rename in [false, true] {
+            let device = FakeDevice {
+                no_rename,
+                ..FakeDevice::default()
+            };
+            let mut storage = Storage::open(device).unwrap();
+            let book = Path::new("Books/dune.xtc");
+            replace(&mut storage, book, b"old", true);
+
+            replace(&mut storage, book, b"bad", false);
+            assert_eq!(storage.read(book).unwrap(), b"old");
+
+            replace(&mut storage, book, b"new", true);
+            assert_eq!(storage.read(book).unwrap(), b"new");
+            let device = storage.session.transport;
+            let mut reopened = Storage::open(device).unwrap();
+            assert_eq!(
+                reopened.files().unwrap(),
+                [(PathBuf::from("Books/dune.xtc"), 3)]
+            );
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Keeping a long sync from getting in the user's way.
+//!
+//! A [`Throttle`] caps the bytes read from the library per second across
+//! every copy thread, and [`enter_idle_priority`] asks the OS to serve the
+//! calling thread's disk I/O only when nothing else wants it.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+/// A shared byte budget refilled at a fixed rate.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    bytes_per_sec: u64,
+    /// When the bytes handed out so far will have been paid for.
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Sleeps until `bytes` more fit in the budget. Idle time is not saved
+    /// up, so a pause is never followed by a burst.
+    pub fn consume(&self, bytes: u64) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let due = {
+            let mut next = self.next.lock().unwrap_or_else(|p| p.into_inner());
+            *next = (*next).max(Instant::now()) + cost;
+            *next
+        };
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// A reader that charges every read to a [`Throttle`].
+pub(crate) struct Paced<'a, R> {
+    inner: R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R> Paced<'a, R> {
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for Paced<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(throttle) = self.throttle {
+            throttle.consume(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Paced<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Drops the calling thread to idle I/O and lowest CPU priority for the
+/// rest of its life. Best effort: failures are logged and ignored.
+pub(crate) fn enter_idle_priority() {
+    if let Err(err) = platform::enter_idle_priority() {
+        debug!(%err, "could not lower sync thread priority");
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::io;
+
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+    /// The idle I/O class and nice 19. With `who == 0` both calls apply to
+    /// the calling thread only.
+    pub fn enter_idle_priority() -> io::Result<()> {
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        // SAFETY: plain syscalls with integer arguments.
+        unsafe {
+            if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setpriority(libc::PRIO_PROCESS, 0, 19) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+
+    /// The background QoS class, which throttles disk I/O as well as CPU.
+    pub fn enter_idle_priority() -> io::Result<()> {
+        // SAFETY: affects only the calling thread.
+        let err = unsafe {
+            libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0)
+        };
+        match err {
+            0 => Ok(()),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+    };
+
+    /// Background mode lowers the thread's I/O and memory priority too.
+    pub fn enter_idle_priority() -> io::Result<()> {
+        // SAFETY: the pseudo-handle always refers to the calling thread.
+        if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use std::io;
+
+    pub fn enter_idle_priority() -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_paces_reads_across_threads() {
+        let throttle = Throttle::new(100_000);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    let mut reader = Paced::new(io::repeat(7).take(10_000), Some(&throttle));
+                    io::copy(&mut reader, &mut io::sink()).unwrap();
+                });
+            }
+        });
+        // 20 kB at 100 kB/s.
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn idle_priority_is_best_effort() {
+        thread::spawn(enter_idle_priority).join().unwrap();
+    }
+}
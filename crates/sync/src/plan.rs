@@ -0,0 +1,826 @@
+//! What a sync will do, worked out before anything is written.
+//!
+//! Plans diff the books selected for the device against the device's
+//! [`Manifest`], so only new and changed books are copied.
+//!
+//! Book files on the device that no manifest tracks, such as books copied
+//! over by hand, are *orphans*. They are reported and left alone unless
+//! [`PlanOptions::remove_orphans`] is set.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use device::{BookInfo, DeviceProfile};
+use serde::{Deserialize, Serialize};
+
+use crate::detect::is_book;
+use crate::manifest::{hash_bytes, hash_file, key};
+use crate::{Manifest, ManifestEntry, SyncError, SyncTarget};
+
+/// A library file to put on the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncItem {
+    pub source: PathBuf,
+    /// Where it goes, relative to the device root.
+    pub dest: PathBuf,
+    /// The library's version of the book; a change forces a re-copy even
+    /// when the bytes happen to match.
+    pub version: u32,
+    /// Higher is more important; [`SyncPlan::suggest_drops`] gives up the
+    /// lowest first when the device is full.
+    pub priority: i32,
+}
+
+impl SyncItem {
+    /// An item for `source`, placed and named by `profile`'s layout rules.
+    /// Changing the layout later moves synced books rather than copying
+    /// them again; see [`SyncAction::Move`].
+    pub fn for_device(
+        profile: &DeviceProfile,
+        source: PathBuf,
+        book: &BookInfo,
+        version: u32,
+    ) -> Self {
+        let extension = source
+            .extension()
+            .map_or("xtc".into(), |ext| ext.to_string_lossy().to_lowercase());
+        Self {
+            dest: profile.book_path(book, &extension),
+            source,
+            version,
+            priority: 0,
+        }
+    }
+
+    /// What the manifest records for this item once synced. Fails if the
+    /// destination would leave the device root.
+    pub(crate) fn entry(&self) -> Result<ManifestEntry, SyncError> {
+        check_dest(&self.dest)?;
+        Ok(ManifestEntry {
+            sha256: hash_file(&self.source)?,
+            size: fs::metadata(&self.source)?.len(),
+            version: self.version,
+        })
+    }
+}
+
+/// How books leave the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeleteMode {
+    /// Move them under [`TRASH_DIR`](crate::TRASH_DIR), where a later sync
+    /// or the user can still get them back.
+    #[default]
+    Trash,
+    Remove,
+}
+
+/// What to do with a synced book whose file on the device no longer
+/// matches the manifest, e.g. because it was replaced by hand. A changed
+/// size always counts; a same-size edit is only caught for books the
+/// library is about to overwrite, whose device copy is hashed first.
+///
+/// A file the manifest doesn't know, sitting where a book is about to be
+/// copied, is treated the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Leave the device's file in place.
+    PreferDevice,
+    /// Overwrite it with the library's book.
+    PreferLibrary,
+    /// Plan a [`SyncAction::Conflict`] for the user to settle with
+    /// [`SyncPlan::resolve`]; unresolved conflicts are left alone.
+    #[default]
+    Ask,
+}
+
+/// How the user settled a [`SyncAction::Conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    KeepDevice,
+    UseLibrary,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanOptions {
+    pub delete_mode: DeleteMode,
+    /// Also take orphaned books off the device.
+    pub remove_orphans: bool,
+    pub conflicts: ConflictPolicy,
+}
+
+/// Files and bytes in one group of a [`PlanPreview`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FileTally {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl FileTally {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Summary of a plan for the user to confirm before anything is written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PlanPreview {
+    /// New books.
+    pub copy: FileTally,
+    /// Books replaced with a newer version.
+    pub update: FileTally,
+    /// Books renamed or moved to another folder on the device.
+    pub moved: usize,
+    /// Books removed from the device, sized as recorded in the manifest
+    /// (0 for orphans, which it does not track).
+    pub delete: FileTally,
+    /// Books moved to the device trash.
+    pub trash: FileTally,
+    /// Books already up to date, or kept as they are on the device.
+    pub unchanged: usize,
+    /// Conflicts awaiting the user's decision.
+    pub conflicts: usize,
+    /// Untracked books found, whether or not the plan removes them.
+    pub orphans: usize,
+}
+
+impl PlanPreview {
+    /// Bytes the sync will write.
+    pub fn bytes_to_write(&self) -> u64 {
+        self.copy.bytes + self.update.bytes
+    }
+
+    /// Whether running the plan would change nothing.
+    pub fn is_noop(&self) -> bool {
+        self.copy.files + self.update.files + self.moved + self.delete.files + self.trash.files == 0
+    }
+}
+
+/// A file to write onto the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCopy {
+    pub source: PathBuf,
+    /// Relative to the device root.
+    pub dest: PathBuf,
+    /// What the manifest records once the copy is done.
+    pub entry: ManifestEntry,
+    pub priority: i32,
+}
+
+/// One step of a [`SyncPlan`]. Destinations are relative to the device
+/// root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    /// A book not yet on the device.
+    Copy(FileCopy),
+    /// A book whose content, version or on-device file changed.
+    Update(FileCopy),
+    /// A synced book whose destination changed, e.g. after switching the
+    /// [`Layout`](device::Layout). Renamed on the device instead of copied
+    /// again.
+    Move { from: PathBuf, to: PathBuf },
+    /// A book to take off the device: synced but no longer selected, or an
+    /// orphan when [`PlanOptions::remove_orphans`] is set.
+    Delete { dest: PathBuf },
+    /// Like `Delete`, but moved to the device trash.
+    Trash { dest: PathBuf },
+    /// Already up to date.
+    Skip { dest: PathBuf },
+    /// A book changed on the device, or a file copied there by hand in a
+    /// book's place, kept there by the [`ConflictPolicy`]. The manifest
+    /// keeps its old record, if any, so later syncs still see the conflict.
+    Keep { dest: PathBuf },
+    /// A book changed on the device that the user has yet to decide on;
+    /// executing the plan leaves it alone. Holds the copy that would
+    /// replace it.
+    Conflict(FileCopy),
+}
+
+/// The actions needed to bring a device up to date.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    /// The manifest the plan was built against.
+    pub manifest: Manifest,
+    /// Untracked book files found on the device, relative to its root.
+    pub orphans: Vec<PathBuf>,
+}
+
+impl SyncPlan {
+    /// Plans syncing exactly `items` to `target`, against the manifest
+    /// stored there.
+    pub fn build(
+        target: &dyn SyncTarget,
+        items: &[SyncItem],
+        options: &PlanOptions,
+    ) -> Result<Self, SyncError> {
+        Self::build_with_manifest(target, items, Manifest::load(target)?, options)
+    }
+
+    /// [`SyncPlan::build`] against an already loaded manifest.
+    pub fn build_with_manifest(
+        target: &dyn SyncTarget,
+        items: &[SyncItem],
+        manifest: Manifest,
+        options: &PlanOptions,
+    ) -> Result<Self, SyncError> {
+        let on_device: HashMap<String, u64> = target
+            .files()?
+            .into_iter()
+            .map(|(path, size)| (key(&path), size))
+            .collect();
+        check_unique(items)?;
+        let selected: HashSet<String> = items.iter().map(|item| key(&item.dest)).collect();
+        let mut moved = HashSet::new();
+        let mut actions = Vec::with_capacity(items.len());
+        let conflict = |copy: FileCopy| match options.conflicts {
+            ConflictPolicy::PreferLibrary => SyncAction::Update(copy),
+            ConflictPolicy::PreferDevice => SyncAction::Keep { dest: copy.dest },
+            ConflictPolicy::Ask => SyncAction::Conflict(copy),
+        };
+        for item in items {
+            let copy = FileCopy {
+                source: item.source.clone(),
+                dest: item.dest.clone(),
+                entry: item.entry()?,
+                priority: item.priority,
+            };
+            let Some(synced) = manifest.get(&item.dest) else {
+                // Something the manifest doesn't know, e.g. a book copied
+                // over by hand, is in the way.
+                if on_device.contains_key(&key(&copy.dest)) {
+                    actions.push(conflict(copy));
+                    continue;
+                }
+                // The same book, still intact under a path no longer
+                // selected, was moved rather than replaced.
+                let from = manifest.books.iter().find(|(old, synced)| {
+                    **synced == copy.entry
+                        && !selected.contains(*old)
+                        && !moved.contains(*old)
+                        && on_device.get(*old) == Some(&synced.size)
+                });
+                actions.push(match from {
+                    Some((from, _)) => {
+                        moved.insert(from.clone());
+                        SyncAction::Move {
+                            from: PathBuf::from(from),
+                            to: copy.dest,
+                        }
+                    }
+                    None => SyncAction::Copy(copy),
+                });
+                continue;
+            };
+            // Sizes catch books removed or replaced by hand. A book the
+            // library is about to overwrite is also hashed, so an edit
+            // that kept the size isn't lost.
+            let device_size = on_device.get(&key(&copy.dest));
+            let up_to_date = device_size.is_some() && *synced == copy.entry;
+            let changed_on_device = match device_size {
+                Some(size) if *size != synced.size => true,
+                Some(_) if !up_to_date => hash_bytes(&target.read(&copy.dest)?) != synced.sha256,
+                _ => false,
+            };
+            actions.push(if changed_on_device {
+                conflict(copy)
+            } else if up_to_date {
+                SyncAction::Skip { dest: copy.dest }
+            } else {
+                SyncAction::Update(copy)
+            });
+        }
+        let remove = |dest| match options.delete_mode {
+            DeleteMode::Trash => SyncAction::Trash { dest },
+            DeleteMode::Remove => SyncAction::Delete { dest },
+        };
+        for dest in manifest.books.keys() {
+            if !selected.contains(dest) && !moved.contains(dest) {
+                actions.push(remove(PathBuf::from(dest)));
+            }
+        }
+        let orphans = orphans(&on_device, &manifest, &selected);
+        if options.remove_orphans {
+            actions.extend(orphans.iter().cloned().map(remove));
+        }
+        Ok(Self {
+            actions,
+            manifest,
+            orphans,
+        })
+    }
+
+    /// What the plan will do, in counts and bytes, without touching the
+    /// device.
+    pub fn preview(&self) -> PlanPreview {
+        let mut preview = PlanPreview {
+            orphans: self.orphans.len(),
+            ..PlanPreview::default()
+        };
+        let recorded = |dest: &Path| self.manifest.get(dest).map_or(0, |entry| entry.size);
+        for action in &self.actions {
+            match action {
+                SyncAction::Copy(copy) => preview.copy.add(copy.entry.size),
+                SyncAction::Update(copy) => preview.update.add(copy.entry.size),
+                SyncAction::Move { .. } => preview.moved += 1,
+                SyncAction::Delete { dest } => preview.delete.add(recorded(dest)),
+                SyncAction::Trash { dest } => preview.trash.add(recorded(dest)),
+                SyncAction::Skip { .. } | SyncAction::Keep { .. } => preview.unchanged += 1,
+                SyncAction::Conflict(_) => preview.conflicts += 1,
+            }
+        }
+        preview
+    }
+
+    /// Fails with [`SyncError::InsufficientSpace`] unless `available` bytes
+    /// cover everything the plan writes.
+    ///
+    /// Space freed by deletions is not counted: they run after the copies,
+    /// and each copy needs room for the new file before the old one goes.
+    pub fn check_space(&self, available: u64) -> Result<(), SyncError> {
+        let needed = self.total_bytes();
+        if needed > available {
+            return Err(SyncError::InsufficientSpace { needed, available });
+        }
+        Ok(())
+    }
+
+    /// The lowest-priority copies to leave out so the rest fit in
+    /// `available` bytes, largest first within a priority. Empty when the
+    /// plan already fits.
+    pub fn suggest_drops(&self, available: u64) -> Vec<PathBuf> {
+        let mut needed = self.total_bytes();
+        let mut candidates: Vec<&FileCopy> = self.copies().collect();
+        candidates.sort_by_key(|copy| (copy.priority, std::cmp::Reverse(copy.entry.size)));
+        let mut drops = Vec::new();
+        for copy in candidates {
+            if needed <= available {
+                break;
+            }
+            needed -= copy.entry.size;
+            drops.push(copy.dest.clone());
+        }
+        drops
+    }
+
+    /// Bytes the plan will write.
+    pub fn total_bytes(&self) -> u64 {
+        self.copies().map(|copy| copy.entry.size).sum()
+    }
+
+    /// Every copy and update, in plan order.
+    pub fn copies(&self) -> impl Iterator<Item = &FileCopy> {
+        self.actions.iter().filter_map(|action| match action {
+            SyncAction::Copy(copy) | SyncAction::Update(copy) => Some(copy),
+            _ => None,
+        })
+    }
+
+    /// Books awaiting a decision, in plan order.
+    pub fn conflicts(&self) -> impl Iterator<Item = &FileCopy> {
+        self.actions.iter().filter_map(|action| match action {
+            SyncAction::Conflict(copy) => Some(copy),
+            _ => None,
+        })
+    }
+
+    /// Settles the conflict at `dest`. Returns `false` if there is none.
+    pub fn resolve(&mut self, dest: &Path, resolution: Resolution) -> bool {
+        let conflict = self
+            .actions
+            .iter_mut()
+            .find(|action| matches!(action, SyncAction::Conflict(copy) if copy.dest == dest));
+        let Some(action) = conflict else {
+            return false;
+        };
+        let SyncAction::Conflict(copy) = action.clone() else {
+            unreachable!("matched a conflict");
+        };
+        *action = match resolution {
+            Resolution::KeepDevice => SyncAction::Keep { dest: copy.dest },
+            Resolution::UseLibrary => SyncAction::Update(copy),
+        };
+        true
+    }
+}
+
+/// Book files on the device that neither the manifest nor the selection
+/// accounts for.
+fn orphans(
+    on_device: &HashMap<String, u64>,
+    manifest: &Manifest,
+    selected: &HashSet<String>,
+) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = on_device
+        .keys()
+        .filter(|key| {
+            is_book(Path::new(key))
+                && !manifest.books.contains_key(*key)
+                && !selected.contains(*key)
+        })
+        .map(PathBuf::from)
+        .collect();
+    found.sort();
+    found
+}
+
+/// Destinations must stay inside the device root.
+fn check_dest(dest: &Path) -> Result<(), SyncError> {
+    let inside = dest.components().all(|c| matches!(c, Component::Normal(_)));
+    if inside && dest.file_name().is_some() {
+        Ok(())
+    } else {
+        Err(SyncError::InvalidDestination(dest.to_path_buf()))
+    }
+}
+
+/// No two items may share a destination, or one would silently replace
+/// the other. Cards are FAT or exFAT, which ignore case.
+fn check_unique(items: &[SyncItem]) -> Result<(), SyncError> {
+    let mut claimed = HashMap::with_capacity(items.len());
+    for item in items {
+        if let Some(first) = claimed.insert(key(&item.dest).to_lowercase(), &item.source) {
+            return Err(SyncError::DuplicateDestination {
+                dest: item.dest.clone(),
+                first: first.clone(),
+                second: item.source.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::tests::unverified;
+    use crate::{execute_plan, MemoryTarget};
+
+    fn item(dir: &Path, name: &str, body: &str) -> SyncItem {
+        let source = dir.join(format!("{name}.xtc"));
+        fs::write(&source, body).unwrap();
+        SyncItem {
+            source,
+            dest: PathBuf::from(format!("Books/{name}.xtc")),
+            version: 1,
+            priority: 0,
+        }
+    }
+
+    fn kinds(plan: &SyncPlan) -> Vec<(&'static str, String)> {
+        plan.actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Copy(c) => ("copy", key(&c.dest)),
+                SyncAction::Update(c) => ("update", key(&c.dest)),
+                SyncAction::Move { to, .. } => ("move", key(to)),
+                SyncAction::Delete { dest } => ("delete", key(dest)),
+                SyncAction::Trash { dest } => ("trash", key(dest)),
+                SyncAction::Skip { dest } => ("skip", key(dest)),
+                SyncAction::Keep { dest } => ("keep", key(dest)),
+                SyncAction::Conflict(c) => ("conflict", key(&c.dest)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn device_items_follow_the_profile_layout() {
+        let profile = DeviceProfile {
+            books_dir: "Books".into(),
+            ..DeviceProfile::x3()
+        };
+        let book = BookInfo::new("Dune: Messiah");
+        let item = SyncItem::for_device(&profile, "lib/42.XTCH".into(), &book, 3);
+        assert_eq!(item.dest, PathBuf::from("Books/Dune_ Messiah.xtch"));
+        assert_eq!(item.version, 3);
+    }
+
+    #[test]
+    fn refuses_books_that_share_a_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let profile = DeviceProfile::x3();
+        assert_eq!(profile.layout, device::Layout::Flat);
+        let place = |name: &str, title: &str| {
+            let source = item(dir.path(), name, name).source;
+            SyncItem::for_device(&profile, source, &BookInfo::new(title), 1)
+        };
+        let options = PlanOptions::default();
+
+        for (first, second) in [("Dune", "Dune"), ("Dune", "dune")] {
+            let items = [place("a", first), place("b", second)];
+            assert!(matches!(
+                SyncPlan::build(&target, &items, &options),
+                Err(SyncError::DuplicateDestination { dest, .. }) if key(&dest).eq_ignore_ascii_case("dune.xtc")
+            ));
+        }
+        let items = [place("a", "Dune"), place("b", "Dune Messiah")];
+        assert!(SyncPlan::build(&target, &items, &options).is_ok());
+    }
+
+    #[test]
+    fn reorganizing_moves_books_instead_of_recopying() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let (a, b) = (item(dir.path(), "a", "aaaa"), item(dir.path(), "b", "aaaa"));
+        let options = PlanOptions::default();
+        let first = SyncPlan::build(&target, &[a.clone(), b.clone()], &options).unwrap();
+        execute_plan(&target, &first, &unverified()).unwrap();
+
+        // Both books have the same bytes; each old path is claimed once.
+        let relocated = |item: &SyncItem, folder: &str| SyncItem {
+            dest: Path::new(folder).join(item.dest.file_name().unwrap()),
+            ..item.clone()
+        };
+        let items = [relocated(&a, "Herbert"), relocated(&b, "Asimov")];
+        let plan = SyncPlan::build(&target, &items, &options).unwrap();
+        assert_eq!(
+            kinds(&plan),
+            [
+                ("move", "Herbert/a.xtc".into()),
+                ("move", "Asimov/b.xtc".into())
+            ]
+        );
+        assert_eq!(plan.total_bytes(), 0);
+        assert_eq!(plan.preview().moved, 2);
+
+        let report = execute_plan(&target, &plan, &unverified()).unwrap();
+        assert_eq!(report.moved, 2);
+        assert!(target.get(Path::new("Books/a.xtc")).is_none());
+        assert_eq!(target.get(Path::new("Asimov/b.xtc")).unwrap(), b"aaaa");
+        let plan = SyncPlan::build(&target, &items, &options).unwrap();
+        assert!(plan.preview().is_noop());
+    }
+
+    #[test]
+    fn diffs_against_the_device_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let (a, b, c) = (
+            item(dir.path(), "a", "aaaa"),
+            item(dir.path(), "b", "bbbb"),
+            item(dir.path(), "c", "cccc"),
+        );
+        let options = PlanOptions::default();
+        let first = SyncPlan::build(&target, &[a.clone(), b.clone(), c.clone()], &options).unwrap();
+        assert_eq!(first.total_bytes(), 12);
+        execute_plan(&target, &first, &unverified()).unwrap();
+
+        // a unchanged, b rewritten, c dropped from the selection, d new.
+        fs::write(&b.source, "bbbbbb").unwrap();
+        let d = item(dir.path(), "d", "dd");
+        let plan = SyncPlan::build(&target, &[a.clone(), b, d], &options).unwrap();
+        assert_eq!(
+            kinds(&plan),
+            [
+                ("skip", "Books/a.xtc".into()),
+                ("update", "Books/b.xtc".into()),
+                ("copy", "Books/d.xtc".into()),
+                ("trash", "Books/c.xtc".into()),
+            ]
+        );
+        assert_eq!(plan.total_bytes(), 8);
+        let tally = |files, bytes| FileTally { files, bytes };
+        assert_eq!(
+            plan.preview(),
+            PlanPreview {
+                copy: tally(1, 2),
+                update: tally(1, 6),
+                delete: tally(0, 0),
+                trash: tally(1, 4),
+                moved: 0,
+                unchanged: 1,
+                conflicts: 0,
+                orphans: 0,
+            }
+        );
+        assert_eq!(plan.preview().bytes_to_write(), 8);
+        assert!(!plan.preview().is_noop());
+
+        let bumped = SyncItem { version: 2, ..a };
+        let plan = SyncPlan::build(&target, &[bumped], &options).unwrap();
+        assert_eq!(kinds(&plan)[0], ("update", "Books/a.xtc".into()));
+    }
+
+    #[test]
+    fn recopies_books_missing_from_the_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let a = item(dir.path(), "a", "aaaa");
+        let options = PlanOptions::default();
+        execute_plan(
+            &target,
+            &SyncPlan::build(&target, std::slice::from_ref(&a), &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+        target.delete(Path::new("Books/a.xtc")).unwrap();
+        let plan = SyncPlan::build(&target, &[a], &options).unwrap();
+        assert_eq!(kinds(&plan), [("update", "Books/a.xtc".into())]);
+    }
+
+    #[test]
+    fn books_changed_on_the_device_follow_the_conflict_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let a = item(dir.path(), "a", "aaaa");
+        let items = std::slice::from_ref(&a);
+        let options = PlanOptions::default();
+        execute_plan(
+            &target,
+            &SyncPlan::build(&target, items, &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+        let edited = b"annotated by hand".to_vec();
+        let dest = Path::new("Books/a.xtc");
+        target
+            .write(dest, &mut std::io::Cursor::new(edited.clone()), &|_| Ok(()))
+            .unwrap();
+
+        let with = |conflicts| PlanOptions {
+            conflicts,
+            ..PlanOptions::default()
+        };
+        let plan = SyncPlan::build(&target, items, &with(ConflictPolicy::PreferDevice)).unwrap();
+        assert_eq!(kinds(&plan), [("keep", "Books/a.xtc".into())]);
+        let plan = SyncPlan::build(&target, items, &with(ConflictPolicy::PreferLibrary)).unwrap();
+        assert_eq!(kinds(&plan), [("update", "Books/a.xtc".into())]);
+
+        let mut plan = SyncPlan::build(&target, items, &options).unwrap();
+        assert_eq!(kinds(&plan), [("conflict", "Books/a.xtc".into())]);
+        assert_eq!(plan.preview().conflicts, 1);
+        assert_eq!(plan.total_bytes(), 0);
+        let report = execute_plan(&target, &plan, &unverified()).unwrap();
+        assert_eq!(report.conflicts, 1);
+        assert_eq!(target.get(dest).unwrap(), edited);
+
+        assert!(plan.resolve(dest, Resolution::UseLibrary));
+        assert!(!plan.resolve(dest, Resolution::KeepDevice));
+        execute_plan(&target, &plan, &unverified()).unwrap();
+        assert_eq!(target.get(dest).unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn same_size_device_edits_are_caught_before_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let items = [item(dir.path(), "a", "aaaa"), item(dir.path(), "b", "bbbb")];
+        let options = PlanOptions::default();
+        execute_plan(
+            &target,
+            &SyncPlan::build(&target, &items, &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+        let mut edited = std::io::Cursor::new(b"Aaaa".to_vec());
+        target
+            .write(Path::new("Books/a.xtc"), &mut edited, &|_| Ok(()))
+            .unwrap();
+
+        let plan = SyncPlan::build(&target, &items, &options).unwrap();
+        assert_eq!(
+            kinds(&plan),
+            [
+                ("skip", "Books/a.xtc".into()),
+                ("skip", "Books/b.xtc".into())
+            ]
+        );
+
+        let items = [item(dir.path(), "a", "cccc"), item(dir.path(), "b", "dddd")];
+        let plan = SyncPlan::build(&target, &items, &options).unwrap();
+        assert_eq!(
+            kinds(&plan),
+            [
+                ("conflict", "Books/a.xtc".into()),
+                ("update", "Books/b.xtc".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn untracked_files_in_the_way_follow_the_conflict_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let a = item(dir.path(), "a", "aaaa");
+        let items = std::slice::from_ref(&a);
+        let dest = Path::new("Books/a.xtc");
+        let mut by_hand = std::io::Cursor::new(b"copied by hand".to_vec());
+        target.write(dest, &mut by_hand, &|_| Ok(())).unwrap();
+
+        let with = |conflicts| PlanOptions {
+            conflicts,
+            ..PlanOptions::default()
+        };
+        let plan = SyncPlan::build(&target, items, &with(ConflictPolicy::PreferDevice)).unwrap();
+        assert_eq!(kinds(&plan), [("keep", "Books/a.xtc".into())]);
+        let plan = SyncPlan::build(&target, items, &with(ConflictPolicy::PreferLibrary)).unwrap();
+        assert_eq!(kinds(&plan), [("update", "Books/a.xtc".into())]);
+
+        let plan = SyncPlan::build(&target, items, &PlanOptions::default()).unwrap();
+        assert_eq!(kinds(&plan), [("conflict", "Books/a.xtc".into())]);
+        assert!(plan.orphans.is_empty());
+        execute_plan(&target, &plan, &unverified()).unwrap();
+        assert_eq!(target.get(dest).unwrap(), b"copied by hand");
+    }
+
+    #[test]
+    fn finds_and_optionally_removes_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let a = item(dir.path(), "a", "aaaa");
+        for path in ["Manual/dropped.xtch", "Manual/notes.txt"] {
+            let mut data = std::io::Cursor::new("x");
+            target
+                .write(Path::new(path), &mut data, &|_| Ok(()))
+                .unwrap();
+        }
+
+        let plan =
+            SyncPlan::build(&target, std::slice::from_ref(&a), &PlanOptions::default()).unwrap();
+        assert_eq!(plan.orphans, [PathBuf::from("Manual/dropped.xtch")]);
+        assert_eq!(kinds(&plan), [("copy", "Books/a.xtc".into())]);
+
+        let options = PlanOptions {
+            delete_mode: DeleteMode::Remove,
+            remove_orphans: true,
+            ..PlanOptions::default()
+        };
+        let plan = SyncPlan::build(&target, &[a], &options).unwrap();
+        assert_eq!(kinds(&plan)[1], ("delete", "Manual/dropped.xtch".into()));
+        execute_plan(&target, &plan, &unverified()).unwrap();
+        assert!(target.get(Path::new("Manual/dropped.xtch")).is_none());
+        assert!(target.get(Path::new("Manual/notes.txt")).is_some());
+    }
+
+    #[test]
+    fn trashed_books_stay_recoverable() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let a = item(dir.path(), "a", "aaaa");
+        let options = PlanOptions::default();
+        execute_plan(
+            &target,
+            &SyncPlan::build(&target, &[a], &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+        let report = execute_plan(
+            &target,
+            &SyncPlan::build(&target, &[], &options).unwrap(),
+            &unverified(),
+        )
+        .unwrap();
+        assert_eq!(report.trashed, 1);
+        assert!(target.get(Path::new("Books/a.xtc")).is_none());
+        assert_eq!(
+            target
+                .get(&Path::new(crate::TRASH_DIR).join("Books/a.xtc"))
+                .unwrap(),
+            b"aaaa"
+        );
+        let plan = SyncPlan::build(&target, &[], &options).unwrap();
+        assert!(plan.actions.is_empty() && plan.orphans.is_empty());
+    }
+
+    #[test]
+    fn checks_space_and_suggests_low_priority_drops() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = MemoryTarget::new();
+        let mut items = vec![
+            item(dir.path(), "keep", &"k".repeat(500)),
+            item(dir.path(), "big", &"b".repeat(400)),
+            item(dir.path(), "small", &"s".repeat(100)),
+        ];
+        items[0].priority = 10;
+        let plan = SyncPlan::build(&target, &items, &PlanOptions::default()).unwrap();
+        assert!(plan.check_space(1000).is_ok());
+        assert!(matches!(
+            plan.check_space(999),
+            Err(SyncError::InsufficientSpace {
+                needed: 1000,
+                available: 999
+            })
+        ));
+        assert!(plan.suggest_drops(1000).is_empty());
+        assert_eq!(plan.suggest_drops(700), [PathBuf::from("Books/big.xtc")]);
+        assert_eq!(
+            plan.suggest_drops(550),
+            [
+                PathBuf::from("Books/big.xtc"),
+                PathBuf::from("Books/small.xtc")
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_destinations_outside_the_device() {
+        for dest in ["../escape.xtc", "/abs.xtc", "Books/..", ""] {
+            assert!(matches!(
+                check_dest(Path::new(dest)),
+                Err(SyncError::InvalidDestination(_))
+            ));
+        }
+    }
+}
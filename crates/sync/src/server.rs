@@ -0,0 +1,246 @@
+//! Serving the library over the local network, for syncing without a
+//! cable.
+//!
+//! A device, or a companion script on the reader, fetches `/manifest.json`,
+//! compares it with what it holds and downloads new and changed books from
+//! `/books/<path>`. The manifest has the same format as the one
+//! [`execute_plan`](crate::execute_plan) writes to the card, so the two can
+//! be diffed directly. Only books listed in the manifest are served, and
+//! `Range: bytes=N-` requests let an interrupted download resume.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::thread;
+
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tracing::{debug, info};
+
+use crate::manifest::key;
+use crate::{Manifest, SyncError, SyncItem};
+
+/// Where the manifest is served.
+pub const MANIFEST_URL: &str = "/manifest.json";
+
+/// Prefix of book URLs; the rest is the book's `/`-separated device path.
+pub const BOOKS_URL: &str = "/books/";
+
+/// An HTTP server offering a fixed selection of books.
+pub struct SyncServer {
+    server: Server,
+    manifest: Vec<u8>,
+    /// Library file for each manifest key.
+    books: HashMap<String, PathBuf>,
+}
+
+impl std::fmt::Debug for SyncServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncServer")
+            .field("addr", &self.local_addr())
+            .field("books", &self.books.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyncServer {
+    /// Hashes `items` into a manifest and listens on `addr`, e.g.
+    /// `0.0.0.0:8080` to be reachable from the reader. Nothing is served
+    /// until [`SyncServer::serve`].
+    pub fn bind(addr: impl ToSocketAddrs, items: &[SyncItem]) -> Result<Self, SyncError> {
+        let mut manifest = Manifest::default();
+        let mut books = HashMap::with_capacity(items.len());
+        for item in items {
+            manifest.insert(&item.dest, item.entry()?);
+            books.insert(key(&item.dest), item.source.clone());
+        }
+        let mut json = serde_json::to_vec_pretty(&manifest)?;
+        json.push(b'\n');
+        let server = Server::http(addr).map_err(|err| SyncError::Io(io::Error::other(err)))?;
+        Ok(Self {
+            server,
+            manifest: json,
+            books,
+        })
+    }
+
+    /// The address actually bound, useful after binding port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Answers requests, each on its own thread, until
+    /// [`SyncServer::stop`] is called. Returns once running downloads
+    /// finish.
+    pub fn serve(&self) {
+        info!(addr = ?self.local_addr(), books = self.books.len(), "sync server listening");
+        thread::scope(|scope| {
+            for request in self.server.incoming_requests() {
+                scope.spawn(|| {
+                    let url = request.url().to_owned();
+                    if let Err(err) = self.respond(request) {
+                        debug!(%url, %err, "sync server request failed");
+                    }
+                });
+            }
+        });
+    }
+
+    /// Makes [`SyncServer::serve`] stop accepting requests.
+    pub fn stop(&self) {
+        self.server.unblock();
+    }
+
+    fn respond(&self, request: Request) -> io::Result<()> {
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return request.respond(Response::empty(405));
+        }
+        let url = request.url().split('?').next().unwrap_or_default();
+        if url == MANIFEST_URL {
+            let json = Response::from_data(self.manifest.clone()).with_header(
+                Header::from_bytes("Content-Type", "application/json").expect("valid header"),
+            );
+            return request.respond(json);
+        }
+        let source = url
+            .strip_prefix(BOOKS_URL)
+            .and_then(percent_decode)
+            .and_then(|path| self.books.get(&path));
+        let Some(source) = source else {
+            return request.respond(Response::empty(404));
+        };
+
+        let mut file = File::open(source)?;
+        let len = file.metadata()?.len();
+        let from = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Range"))
+            .and_then(|h| range_start(h.value.as_str()));
+        let binary =
+            Header::from_bytes("Content-Type", "application/octet-stream").expect("valid header");
+        let ranges = Header::from_bytes("Accept-Ranges", "bytes").expect("valid header");
+        match from {
+            Some(from) if from >= len => request.respond(Response::empty(416)),
+            Some(from) => {
+                file.seek(SeekFrom::Start(from))?;
+                let range = format!("bytes {from}-{}/{len}", len - 1);
+                let headers = vec![
+                    binary,
+                    ranges,
+                    Header::from_bytes("Content-Range", range).expect("valid header"),
+                ];
+                let rest = (len - from) as usize;
+                request.respond(Response::new(
+                    StatusCode(206),
+                    headers,
+                    file,
+                    Some(rest),
+                    None,
+                ))
+            }
+            None => request.respond(
+                Response::from_file(file)
+                    .with_header(binary)
+                    .with_header(ranges),
+            ),
+        }
+    }
+}
+
+/// The start of an open-ended `bytes=N-` range; other forms are ignored
+/// and get the whole file.
+fn range_start(value: &str) -> Option<u64> {
+    value
+        .strip_prefix("bytes=")?
+        .strip_suffix('-')?
+        .parse()
+        .ok()
+}
+
+/// Decodes `%XX` escapes; `None` for malformed escapes or non-UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str, extra: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: reader\r\n{extra}Connection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        let end = reply.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = std::str::from_utf8(&reply[9..12]).unwrap().parse().unwrap();
+        (status, reply[end + 4..].to_vec())
+    }
+
+    #[test]
+    fn serves_manifest_and_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("dune.xtc");
+        fs::write(&source, "0123456789").unwrap();
+        fs::write(dir.path().join("secret.txt"), "private").unwrap();
+        let item = SyncItem {
+            source,
+            dest: PathBuf::from("Books/Dune Messiah.xtc"),
+            version: 2,
+            priority: 0,
+        };
+        let server = Arc::new(SyncServer::bind("127.0.0.1:0", &[item]).unwrap());
+        let addr = server.local_addr().unwrap();
+        let running = thread::spawn({
+            let server = Arc::clone(&server);
+            move || server.serve()
+        });
+
+        let (status, body) = get(addr, MANIFEST_URL, "");
+        assert_eq!(status, 200);
+        let manifest: Manifest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(manifest.books["Books/Dune Messiah.xtc"].size, 10);
+        assert_eq!(manifest.books["Books/Dune Messiah.xtc"].version, 2);
+
+        let book = "/books/Books/Dune%20Messiah.xtc";
+        assert_eq!(get(addr, book, ""), (200, b"0123456789".to_vec()));
+        assert_eq!(
+            get(addr, book, "Range: bytes=6-\r\n"),
+            (206, b"6789".to_vec())
+        );
+        assert_eq!(get(addr, book, "Range: bytes=10-\r\n").0, 416);
+        assert_eq!(get(addr, "/books/secret.txt", "").0, 404);
+        assert_eq!(get(addr, "/books/../secret.txt", "").0, 404);
+
+        server.stop();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("a%20b%C3%A9").unwrap(), "a bé");
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(range_start("bytes=100-"), Some(100));
+        assert_eq!(range_start("bytes=0-99"), None);
+    }
+}
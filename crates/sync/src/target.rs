@@ -0,0 +1,321 @@
+//! Where a sync writes.
+//!
+//! Planning and execution only see a [`SyncTarget`]: the card mounted as a
+//! volume ([`FsTarget`]), a reader over MTP, or memory in tests
+//! ([`MemoryTarget`]). Paths are relative to the target's root.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::debug;
+
+use crate::detect::free_space;
+use crate::transfer::copy_atomic;
+use crate::{walk, SyncError};
+
+/// A byte source that can be read and rewound.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Inspects written data before it replaces the old file; see
+/// [`SyncTarget::write`].
+pub type WriteCheck<'a> = dyn Fn(&mut dyn ReadSeek) -> Result<(), SyncError> + 'a;
+
+/// How a write got its data onto the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Fresh,
+    /// Continued a partial file left by an interrupted sync.
+    Resumed {
+        from: u64,
+    },
+}
+
+/// Storage a sync can be planned against and carried out on.
+///
+/// Methods take `&self` so several copies can run at once; targets that
+/// cannot serve requests in parallel lock internally.
+pub trait SyncTarget: Sync {
+    /// Every file outside hidden folders (such as our own `.e-inky` and the
+    /// OS's `.Trashes`) with its size, sorted by path.
+    fn files(&self) -> Result<Vec<(PathBuf, u64)>, SyncError>;
+
+    /// Fails with [`io::ErrorKind::NotFound`] when `path` does not exist.
+    fn read(&self, path: &Path) -> Result<Vec<u8>, SyncError>;
+
+    /// Writes all of `data` to `path`, creating folders as needed.
+    ///
+    /// `check` reads back what was written. When it fails the write is
+    /// undone and its error returned; targets that can do so keep the old
+    /// file at `path` until the check has passed.
+    fn write(
+        &self,
+        path: &Path,
+        data: &mut dyn ReadSeek,
+        check: &WriteCheck<'_>,
+    ) -> Result<Transfer, SyncError>;
+
+    /// Fails with [`io::ErrorKind::NotFound`] when `path` does not exist.
+    fn delete(&self, path: &Path) -> Result<(), SyncError>;
+
+    /// Bytes available for new files. Fails with
+    /// [`io::ErrorKind::Unsupported`] when the target cannot tell.
+    fn free_space(&self) -> Result<u64, SyncError>;
+
+    /// Moves a file, replacing whatever is at `to`. By default a copy and a
+    /// delete.
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), SyncError> {
+        let data = self.read(from)?;
+        self.write(to, &mut Cursor::new(data), &|_| Ok(()))?;
+        self.delete(from)
+    }
+
+    /// Flushes and detaches the target so it can be unplugged. Targets with
+    /// nothing to flush succeed without doing anything.
+    fn eject(&self) -> Result<(), SyncError> {
+        Ok(())
+    }
+}
+
+/// A mounted volume, such as the reader's microSD card.
+///
+/// Writes go through a `.partial` file and a rename, so an interrupted
+/// sync never leaves a truncated book visible and the next one resumes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsTarget {
+    root: PathBuf,
+}
+
+impl FsTarget {
+    /// `root` need not exist yet; it is created on the first write.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl SyncTarget for FsTarget {
+    fn files(&self) -> Result<Vec<(PathBuf, u64)>, SyncError> {
+        let mut files = Vec::new();
+        for path in walk::files(&self.root)? {
+            let size = fs::metadata(&path)?.len();
+            if let Ok(relative) = path.strip_prefix(&self.root) {
+                files.push((relative.to_path_buf(), size));
+            }
+        }
+        Ok(files)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, SyncError> {
+        Ok(fs::read(self.root.join(path))?)
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        data: &mut dyn ReadSeek,
+        check: &WriteCheck<'_>,
+    ) -> Result<Transfer, SyncError> {
+        copy_atomic(data, &self.root.join(path), |partial| {
+            drop_cached(partial);
+            check(&mut BufReader::new(File::open(partial)?))
+        })
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), SyncError> {
+        Ok(fs::remove_file(self.root.join(path))?)
+    }
+
+    fn free_space(&self) -> Result<u64, SyncError> {
+        Ok(free_space(&self.root)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), SyncError> {
+        let to = self.root.join(to);
+        fs::create_dir_all(to.parent().expect("target paths name a file"))?;
+        Ok(fs::rename(self.root.join(from), to)?)
+    }
+
+    /// Ejects the volume; `root` must be its mount point.
+    fn eject(&self) -> Result<(), SyncError> {
+        crate::eject(&self.root)
+    }
+}
+
+/// Evicts a flushed file from the page cache so the next read hits the
+/// device. Best effort, and only possible on Linux.
+fn drop_cached(path: &Path) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        let advised = File::open(path).map(|file| {
+            // SAFETY: the descriptor is open for the duration of the call.
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) }
+        });
+        if !matches!(advised, Ok(0)) {
+            debug!(path = %path.display(), "could not drop cached pages before verifying");
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = path;
+}
+
+/// Files held in memory, for tests and previews.
+#[derive(Debug, Default)]
+pub struct MemoryTarget {
+    files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+    capacity: Option<u64>,
+}
+
+impl MemoryTarget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A target that holds at most `bytes`.
+    pub fn with_capacity(bytes: u64) -> Self {
+        Self {
+            capacity: Some(bytes),
+            ..Self::default()
+        }
+    }
+
+    /// The contents of `path`, if present.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        self.lock().get(path).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Vec<u8>>> {
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SyncTarget for MemoryTarget {
+    fn files(&self) -> Result<Vec<(PathBuf, u64)>, SyncError> {
+        Ok(self
+            .lock()
+            .iter()
+            .filter(|(path, _)| !in_hidden_folder(path))
+            .map(|(path, data)| (path.clone(), data.len() as u64))
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, SyncError> {
+        self.get(path).ok_or_else(|| not_found(path))
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        data: &mut dyn ReadSeek,
+        check: &WriteCheck<'_>,
+    ) -> Result<Transfer, SyncError> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes)?;
+        check(&mut Cursor::new(&bytes))?;
+        self.lock().insert(path.to_path_buf(), bytes);
+        Ok(Transfer::Fresh)
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), SyncError> {
+        self.lock()
+            .remove(path)
+            .map(drop)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn free_space(&self) -> Result<u64, SyncError> {
+        let capacity = self.capacity.ok_or_else(|| {
+            SyncError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "memory target has no capacity",
+            ))
+        })?;
+        let used: u64 = self.lock().values().map(|data| data.len() as u64).sum();
+        Ok(capacity.saturating_sub(used))
+    }
+}
+
+/// Whether any folder above `path` is hidden.
+pub(crate) fn in_hidden_folder(path: &Path) -> bool {
+    path.parent().is_some_and(|dir| {
+        dir.components().any(|c| match c {
+            Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+            _ => false,
+        })
+    })
+}
+
+pub(crate) fn not_found(path: &Path) -> SyncError {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} is not on the device", path.display()),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fs_target_lists_relative_paths_and_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = FsTarget::new(dir.path().join("card"));
+        target
+            .write(Path::new("Books/a.xtc"), &mut Cursor::new(b"aaaa"), &|_| {
+                Ok(())
+            })
+            .unwrap();
+        target
+            .rename(Path::new("Books/a.xtc"), Path::new(".e-inky/trash/a.xtc"))
+            .unwrap();
+        target
+            .write(Path::new("b.xtc"), &mut Cursor::new(b"bb"), &|_| Ok(()))
+            .unwrap();
+        assert_eq!(target.files().unwrap(), [(PathBuf::from("b.xtc"), 2)]);
+        assert_eq!(
+            target.read(Path::new(".e-inky/trash/a.xtc")).unwrap(),
+            b"aaaa"
+        );
+    }
+
+    #[test]
+    fn memory_target_hides_hidden_folders_and_tracks_space() {
+        let target = MemoryTarget::with_capacity(10);
+        let write = |path: &str, data: &[u8]| {
+            target.write(
+                Path::new(path),
+                &mut Cursor::new(data.to_vec()),
+                &|_| Ok(()),
+            )
+        };
+        write(".e-inky/manifest.json", b"{}").unwrap();
+        write("Books/a.xtc", b"aaa").unwrap();
+        assert_eq!(target.files().unwrap(), [(PathBuf::from("Books/a.xtc"), 3)]);
+        assert_eq!(target.free_space().unwrap(), 5);
+
+        let rejected = target.write(Path::new("Books/a.xtc"), &mut Cursor::new(b"x"), &|_| {
+            Err(SyncError::InvalidDestination("bad".into()))
+        });
+        assert!(rejected.is_err());
+        assert_eq!(target.get(Path::new("Books/a.xtc")).unwrap(), b"aaa");
+
+        target
+            .rename(Path::new("Books/a.xtc"), Path::new("Books/b.xtc"))
+            .unwrap();
+        assert!(matches!(
+            target.delete(Path::new("Books/a.xtc")),
+            Err(SyncError::Io(err)) if err.kind() == io::ErrorKind::NotFound
+        ));
+    }
+}
@@ -0,0 +1,187 @@
+//! Copying one file onto the device without ever exposing a partial book.
+//!
+//! Data goes to `<name>.partial` first and is renamed into place once it is
+//! flushed, so a pulled cable leaves at most a `.partial` file, which the
+//! device ignores. The next sync resumes it when every byte of it still
+//! matches the source, and otherwise starts over.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::Transfer;
+
+pub(crate) const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Chunk size for comparing an existing partial file against the source.
+const RESUME_CHUNK: usize = 64 * 1024;
+
+/// `Books/a.xtc` -> `Books/a.xtc.partial`.
+pub(crate) fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(dest.as_os_str());
+    name.push(PARTIAL_SUFFIX);
+    PathBuf::from(name)
+}
+
+pub(crate) fn is_partial(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(PARTIAL_SUFFIX))
+}
+
+/// Copies `source` to `dest` through a partial file and an atomic rename.
+///
+/// `check` runs on the flushed partial file before the rename. When it
+/// fails the partial file is deleted, so neither the device nor a later
+/// resume sees the bad copy, and whatever was at `dest` stays.
+pub(crate) fn copy_atomic<E: From<io::Error>>(
+    src: &mut (impl Read + Seek + ?Sized),
+    dest: &Path,
+    check: impl FnOnce(&Path) -> Result<(), E>,
+) -> Result<Transfer, E> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = partial_path(dest);
+    let from = resume_offset(src, &partial)?;
+
+    let mut out = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&partial)?;
+    out.set_len(from)?;
+    out.seek(SeekFrom::Start(from))?;
+    src.seek(SeekFrom::Start(from))?;
+    io::copy(src, &mut out)?;
+    out.sync_all()?;
+    drop(out);
+    if let Err(err) = check(&partial) {
+        fs::remove_file(&partial)?;
+        return Err(err);
+    }
+    fs::rename(&partial, dest)?;
+    sync_dir(dest);
+
+    Ok(if from > 0 {
+        debug!(path = %dest.display(), from, "resumed partial copy");
+        Transfer::Resumed { from }
+    } else {
+        Transfer::Fresh
+    })
+}
+
+/// Length of `partial` if it is a prefix of `src`; 0 to start over.
+///
+/// The whole partial file is compared: one left by an earlier conversion
+/// of the same book can share its pages but not its header or index, and
+/// resuming that would splice two books together. Reading it back is
+/// still much cheaper than writing it again.
+fn resume_offset(src: &mut (impl Read + Seek + ?Sized), partial: &Path) -> io::Result<u64> {
+    let Ok(mut existing) = File::open(partial) else {
+        return Ok(0);
+    };
+    let len = existing.metadata()?.len();
+    if len == 0 || len > src.seek(SeekFrom::End(0))? {
+        return Ok(0);
+    }
+    src.seek(SeekFrom::Start(0))?;
+    let (mut ours, mut theirs) = (vec![0; RESUME_CHUNK], vec![0; RESUME_CHUNK]);
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(RESUME_CHUNK as u64) as usize;
+        existing.read_exact(&mut theirs[..n])?;
+        src.read_exact(&mut ours[..n])?;
+        if ours[..n] != theirs[..n] {
+            return Ok(0);
+        }
+        left -= n as u64;
+    }
+    Ok(len)
+}
+
+/// Makes the rename durable where the platform allows syncing a directory.
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        if let Err(err) = File::open(dir).and_then(|d| d.sync_all()) {
+            debug!(dir = %dir.display(), %err, "directory sync failed");
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source file of `len` bytes and a destination on a fake card.
+    fn setup(len: usize) -> (tempfile::TempDir, PathBuf, PathBuf, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+        let source = dir.path().join("source.xtc");
+        fs::write(&source, &data).unwrap();
+        let dest = dir.path().join("card/Books/book.xtc");
+        (dir, dest, source, data)
+    }
+
+    fn copy(source: &Path, dest: &Path) -> Transfer {
+        let mut src = File::open(source).unwrap();
+        copy_atomic(&mut src, dest, |_| Ok::<_, io::Error>(())).unwrap()
+    }
+
+    #[test]
+    fn fresh_copy_leaves_no_partial() {
+        let (_dir, dest, source, data) = setup(1000);
+        assert_eq!(copy(&source, &dest), Transfer::Fresh);
+        assert_eq!(fs::read(&dest).unwrap(), data);
+        assert!(!partial_path(&dest).exists());
+    }
+
+    #[test]
+    fn resumes_matching_partial() {
+        let (_dir, dest, source, data) = setup(200_000);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(partial_path(&dest), &data[..150_000]).unwrap();
+        assert_eq!(copy(&source, &dest), Transfer::Resumed { from: 150_000 });
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+
+    #[test]
+    fn restarts_when_partial_differs() {
+        let (_dir, dest, source, data) = setup(1000);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(partial_path(&dest), [0xEE; 400]).unwrap();
+        assert_eq!(copy(&source, &dest), Transfer::Fresh);
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+
+    #[test]
+    fn restarts_when_only_the_front_of_the_partial_differs() {
+        let (_dir, dest, source, data) = setup(200_000);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        let mut stale = data[..150_000].to_vec();
+        stale[..16].fill(0xEE);
+        fs::write(partial_path(&dest), stale).unwrap();
+        assert_eq!(copy(&source, &dest), Transfer::Fresh);
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+
+    #[test]
+    fn failed_check_keeps_the_old_file() {
+        let (_dir, dest, source, _) = setup(1000);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&dest, "old").unwrap();
+        let err = copy_atomic(&mut File::open(&source).unwrap(), &dest, |partial| {
+            assert_eq!(fs::metadata(partial)?.len(), 1000);
+            Err(io::Error::other("bad copy"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "bad copy");
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        assert!(!partial_path(&dest).exists());
+    }
+}
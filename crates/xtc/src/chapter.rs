@@ -0,0 +1,80 @@
+//! Chapter table: 96-byte entries of name (80 bytes, UTF-8, NUL-padded),
+//! start page (u16), end page (u16) and 12 reserved bytes.
+
+use crate::metadata::{read_str, write_str};
+use crate::XtcError;
+
+/// Size of one chapter entry.
+pub const CHAPTER_ENTRY_LEN: usize = 96;
+
+const NAME_LEN: usize = 80;
+
+/// A chapter spanning `start_page..=end_page` (0-based page indices).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Truncated to 79 bytes on a character boundary.
+    pub title: String,
+    pub start_page: u16,
+    pub end_page: u16,
+}
+
+impl Chapter {
+    pub(crate) fn to_bytes(&self) -> [u8; CHAPTER_ENTRY_LEN] {
+        let mut b = [0u8; CHAPTER_ENTRY_LEN];
+        write_str(&mut b[..NAME_LEN], &self.title);
+        b[80..82].copy_from_slice(&self.start_page.to_le_bytes());
+        b[82..84].copy_from_slice(&self.end_page.to_le_bytes());
+        b
+    }
+
+    pub(crate) fn parse(b: &[u8; CHAPTER_ENTRY_LEN]) -> Self {
+        Self {
+            title: read_str(&b[..NAME_LEN]),
+            start_page: u16::from_le_bytes([b[80], b[81]]),
+            end_page: u16::from_le_bytes([b[82], b[83]]),
+        }
+    }
+
+    /// Checks the range is ordered and inside a `page_count`-page book.
+    pub(crate) fn validate(&self, page_count: usize) -> Result<(), XtcError> {
+        if self.start_page > self.end_page || usize::from(self.end_page) >= page_count {
+            return Err(XtcError::InvalidChapter {
+                start: self.start_page,
+                end: self.end_page,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_layout_round_trips() {
+        let chapter = Chapter {
+            title: "Chapter 1. Loomings".into(),
+            start_page: 3,
+            end_page: 17,
+        };
+        let bytes = chapter.to_bytes();
+        assert_eq!(&bytes[..19], b"Chapter 1. Loomings");
+        assert!(bytes[19..80].iter().all(|&b| b == 0));
+        assert_eq!(&bytes[80..84], &[3, 0, 17, 0]);
+        assert!(bytes[84..].iter().all(|&b| b == 0));
+        assert_eq!(Chapter::parse(&bytes), chapter);
+    }
+
+    #[test]
+    fn validates_page_range() {
+        let chapter = |start_page, end_page| Chapter {
+            title: String::new(),
+            start_page,
+            end_page,
+        };
+        assert!(chapter(0, 4).validate(5).is_ok());
+        assert!(chapter(0, 5).validate(5).is_err());
+        assert!(chapter(3, 2).validate(5).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+//! Entry points for fuzzers such as `cargo fuzz`.
+//!
+//! Files come back off the device possibly truncated or corrupted, so
+//! parsing any byte string must return an error rather than panic or
+//! allocate without bound.
+
+use std::io::Cursor;
+
+use crate::{Page, XtcReader};
+
+/// Parses `bytes` as a container and reads everything in it: metadata,
+/// chapters, every page and its checksum. Errors are expected and ignored.
+pub fn parse(bytes: &[u8]) {
+    let Ok(mut reader) = XtcReader::new(Cursor::new(bytes)) else {
+        return;
+    };
+    for index in 0..reader.index().len() {
+        if let Ok(page) = reader.page(index) {
+            let _ = page.to_gray();
+        }
+    }
+    let _ = reader.verify();
+}
+
+/// Parses `bytes` as a single XTG/XTH page record.
+pub fn parse_page(bytes: &[u8]) {
+    if let Ok(page) = Page::parse(bytes) {
+        let _ = page.to_gray();
+    }
+    let _ = Page::parse_verified(bytes);
+}
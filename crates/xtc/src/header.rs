@@ -0,0 +1,165 @@
+//! The 56-byte XTC container header.
+//!
+//! | offset | size | field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | mark: `XTC\0` (XTG pages), `XTCH` (XTH) |
+//! | 4      | 2    | version, see [`XtcVersion`]            |
+//! | 6      | 2    | page count                             |
+//! | 8      | 1    | read direction                         |
+//! | 9      | 1    | has metadata                           |
+//! | 10     | 1    | has thumbnails                         |
+//! | 11     | 1    | has chapters                           |
+//! | 12     | 4    | current page                           |
+//! | 16     | 8    | metadata offset                        |
+//! | 24     | 8    | index offset                           |
+//! | 32     | 8    | data offset                            |
+//! | 40     | 8    | thumbnail offset                       |
+//! | 48     | 8    | chapter offset                         |
+
+use crate::{PageFormat, XtcError, XtcVersion};
+
+/// Size of the container header.
+pub const HEADER_LEN: usize = 56;
+
+/// Offset of the current page field, which the device rewrites in place.
+pub(crate) const CURRENT_PAGE_OFFSET: u64 = 12;
+
+const XTC_MARK: [u8; 4] = *b"XTC\0";
+const XTCH_MARK: [u8; 4] = *b"XTCH";
+
+/// Page turn direction stored in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadDirection {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+    TopToBottom,
+}
+
+impl ReadDirection {
+    fn byte(self) -> u8 {
+        match self {
+            ReadDirection::LeftToRight => 0,
+            ReadDirection::RightToLeft => 1,
+            ReadDirection::TopToBottom => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, XtcError> {
+        match byte {
+            0 => Ok(ReadDirection::LeftToRight),
+            1 => Ok(ReadDirection::RightToLeft),
+            2 => Ok(ReadDirection::TopToBottom),
+            _ => Err(XtcError::Malformed("unknown read direction")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub format: PageFormat,
+    pub version: XtcVersion,
+    pub page_count: u16,
+    pub read_direction: ReadDirection,
+    pub has_metadata: bool,
+    pub has_thumbnails: bool,
+    pub has_chapters: bool,
+    pub current_page: u32,
+    pub metadata_offset: u64,
+    pub index_offset: u64,
+    pub data_offset: u64,
+    pub thumb_offset: u64,
+    pub chapter_offset: u64,
+}
+
+impl Header {
+    pub fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut b = [0u8; HEADER_LEN];
+        b[0..4].copy_from_slice(match self.format {
+            PageFormat::Xtg => &XTC_MARK,
+            PageFormat::Xth => &XTCH_MARK,
+        });
+        b[4..6].copy_from_slice(&self.version.raw().to_le_bytes());
+        b[6..8].copy_from_slice(&self.page_count.to_le_bytes());
+        b[8] = self.read_direction.byte();
+        b[9] = u8::from(self.has_metadata);
+        b[10] = u8::from(self.has_thumbnails);
+        b[11] = u8::from(self.has_chapters);
+        b[12..16].copy_from_slice(&self.current_page.to_le_bytes());
+        b[16..24].copy_from_slice(&self.metadata_offset.to_le_bytes());
+        b[24..32].copy_from_slice(&self.index_offset.to_le_bytes());
+        b[32..40].copy_from_slice(&self.data_offset.to_le_bytes());
+        b[40..48].copy_from_slice(&self.thumb_offset.to_le_bytes());
+        b[48..56].copy_from_slice(&self.chapter_offset.to_le_bytes());
+        b
+    }
+
+    pub fn parse(b: &[u8; HEADER_LEN]) -> Result<Self, XtcError> {
+        let format = match [b[0], b[1], b[2], b[3]] {
+            XTC_MARK => PageFormat::Xtg,
+            XTCH_MARK => PageFormat::Xth,
+            _ => return Err(XtcError::Malformed("not an XTC file")),
+        };
+        let u64_at = |at: usize| u64::from_le_bytes(b[at..at + 8].try_into().unwrap());
+        Ok(Self {
+            format,
+            version: XtcVersion::from_raw(u16::from_le_bytes([b[4], b[5]]))?,
+            page_count: u16::from_le_bytes([b[6], b[7]]),
+            read_direction: ReadDirection::from_byte(b[8])?,
+            has_metadata: b[9] != 0,
+            has_thumbnails: b[10] != 0,
+            has_chapters: b[11] != 0,
+            current_page: u32::from_le_bytes(b[12..16].try_into().unwrap()),
+            metadata_offset: u64_at(16),
+            index_offset: u64_at(24),
+            data_offset: u64_at(32),
+            thumb_offset: u64_at(40),
+            chapter_offset: u64_at(48),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let header = Header {
+            format: PageFormat::Xth,
+            version: XtcVersion::V1_0,
+            page_count: 3,
+            read_direction: ReadDirection::RightToLeft,
+            has_metadata: true,
+            has_thumbnails: false,
+            has_chapters: true,
+            current_page: 2,
+            metadata_offset: 56,
+            index_offset: 312,
+            data_offset: 360,
+            thumb_offset: 0,
+            chapter_offset: 900,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[0..12], b"XTCH\x00\x01\x03\x00\x01\x01\x00\x01");
+        assert_eq!(Header::parse(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn rejects_unknown_major_version() {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(b"XTC\0");
+        bytes[4..6].copy_from_slice(&0x0200u16.to_le_bytes());
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(XtcError::UnsupportedVersion(0x0200))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_mark() {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(b"XTG\0");
+        assert!(matches!(Header::parse(&bytes), Err(XtcError::Malformed(_))));
+    }
+}
@@ -0,0 +1,291 @@
+//! Building a container straight from a directory of page images.
+//!
+//! For scans and comics that never go through CREngine: every PNG or JPEG
+//! in the directory becomes one page, in natural file name order
+//! (`page2.png` before `page10.png`).
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use encoder::{Compression, EncoderConfig, EncoderPipeline, Levels, OutputFormat};
+
+use crate::{Page, XtcError, XtcWriter};
+
+/// Encodes every PNG/JPEG page in `dir` with `config` and writes them to an
+/// XTC at `output`. Returns the path written.
+///
+/// Mono configs produce XTG pages, everything else XTH. Level tables with
+/// more than XTH's four levels are refused rather than re-snapped behind
+/// the dither's back. `config.output` and `config.compression` are ignored
+/// since the container fixes the layout.
+/// Images are decoded one at a time, so memory stays at a page or two.
+pub fn from_images(
+    dir: impl AsRef<Path>,
+    config: &EncoderConfig,
+    output: impl AsRef<Path>,
+) -> Result<PathBuf, XtcError> {
+    let dir = dir.as_ref();
+    if config.levels.count() > 4 {
+        return Err(XtcError::TooManyLevels(config.levels.count()));
+    }
+    let images = image_paths(dir)?;
+    if images.is_empty() {
+        return Err(XtcError::NoImages(dir.to_path_buf()));
+    }
+    let mono = matches!(config.levels, Levels::Mono);
+    let pipeline = EncoderPipeline::from_config(&EncoderConfig {
+        output: OutputFormat::Gray8,
+        compression: Compression::None,
+        ..*config
+    })?;
+
+    let mut writer = XtcWriter::create(output)?;
+    for path in images {
+        let (gray, width, height) = decode_gray(&path).map_err(|reason| XtcError::Image {
+            path: path.clone(),
+            reason,
+        })?;
+        let frame = pipeline.run(&gray, width, height)?;
+        let page = if mono {
+            Page::xtg_from_gray(frame.width, frame.height, &frame.data)?
+        } else {
+            Page::xth_from_gray(frame.width, frame.height, &frame.data)?
+        };
+        writer = writer.add_page(&page)?;
+    }
+    writer.finish()
+}
+
+fn image_paths(dir: &Path) -> Result<Vec<PathBuf>, XtcError> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ["png", "jpg", "jpeg"]
+                    .iter()
+                    .any(|known| ext.eq_ignore_ascii_case(known))
+            });
+        if is_image && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(paths)
+}
+
+/// Compares runs of digits by value and everything else bytewise.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (da, ra) = split_digits(a);
+                let (db, rb) = split_digits(b);
+                let (ta, tb) = (trim_zeros(da), trim_zeros(db));
+                let order = ta.len().cmp(&tb.len()).then(ta.cmp(tb));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                (a, b) = (ra, rb);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                (a, b) = (&a[1..], &b[1..]);
+            }
+        }
+    }
+}
+
+fn split_digits(s: &[u8]) -> (&[u8], &[u8]) {
+    s.split_at(
+        s.iter()
+            .position(|c| !c.is_ascii_digit())
+            .unwrap_or(s.len()),
+    )
+}
+
+fn trim_zeros(s: &[u8]) -> &[u8] {
+    &s[s.iter().position(|&c| c != b'0').unwrap_or(s.len())..]
+}
+
+/// Decodes a PNG or JPEG to grayscale8, compositing transparency onto white.
+fn decode_gray(path: &Path) -> Result<(Vec<u8>, u32, u32), String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let is_png = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        decode_png(file).map_err(|e| e.to_string())
+    } else {
+        decode_jpeg(file)
+    }
+}
+
+fn decode_png(file: BufReader<File>) -> Result<(Vec<u8>, u32, u32), png::DecodingError> {
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let pixels = &buf[..info.buffer_size()];
+    let gray = match info.color_type {
+        png::ColorType::Grayscale => pixels.to_vec(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .map(|p| over_white(p[0], p[1]))
+            .collect(),
+        png::ColorType::Rgb => pixels.chunks_exact(3).map(luma).collect(),
+        png::ColorType::Rgba => pixels
+            .chunks_exact(4)
+            .map(|p| over_white(luma(p), p[3]))
+            .collect(),
+        // EXPAND turns palettes into RGB(A).
+        png::ColorType::Indexed => unreachable!("palette left unexpanded"),
+    };
+    Ok((gray, info.width, info.height))
+}
+
+fn decode_jpeg(file: BufReader<File>) -> Result<(Vec<u8>, u32, u32), String> {
+    let mut decoder = jpeg_decoder::Decoder::new(file);
+    let pixels = decoder.decode().map_err(|e| e.to_string())?;
+    let info = decoder.info().ok_or("missing JPEG header")?;
+    let gray = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => pixels,
+        // Big-endian samples; keep the high byte.
+        jpeg_decoder::PixelFormat::L16 => pixels.chunks_exact(2).map(|p| p[0]).collect(),
+        jpeg_decoder::PixelFormat::RGB24 => pixels.chunks_exact(3).map(luma).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => pixels
+            .chunks_exact(4)
+            .map(|p| {
+                let k = 255 - u16::from(p[3]);
+                let rgb = [0, 1, 2].map(|i| ((255 - u16::from(p[i])) * k / 255) as u8);
+                luma(&rgb)
+            })
+            .collect(),
+    };
+    Ok((gray, info.width.into(), info.height.into()))
+}
+
+/// Rec. 601 luma of an RGB pixel.
+fn luma(rgb: &[u8]) -> u8 {
+    let [r, g, b] = [0, 1, 2].map(|i| u32::from(rgb[i]));
+    ((299 * r + 587 * g + 114 * b + 500) / 1000) as u8
+}
+
+fn over_white(gray: u8, alpha: u8) -> u8 {
+    let (gray, alpha) = (u32::from(gray), u32::from(alpha));
+    ((gray * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XtcReader;
+
+    fn write_png(path: &Path, width: u32, height: u32, color: png::ColorType, data: &[u8]) {
+        let mut encoder = png::Encoder::new(File::create(path).unwrap(), width, height);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+    }
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let mut names = vec!["p10.png", "p2.png", "p1.png", "p02b.png", "a.png"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a.png", "p1.png", "p2.png", "p02b.png", "p10.png"]);
+    }
+
+    #[test]
+    fn builds_a_page_per_image_in_name_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = dir.path().join("scans");
+        fs::create_dir(&pages).unwrap();
+        write_png(
+            &pages.join("page10.png"),
+            8,
+            2,
+            png::ColorType::Grayscale,
+            &[0; 16],
+        );
+        write_png(
+            &pages.join("page2.PNG"),
+            8,
+            2,
+            png::ColorType::Rgba,
+            &[255, 255, 255, 255].repeat(16),
+        );
+        fs::write(pages.join("notes.txt"), "not a page").unwrap();
+
+        let config = EncoderConfig {
+            levels: Levels::Mono,
+            ..EncoderConfig::default()
+        };
+        let output = dir.path().join("book.xtc");
+        from_images(&pages, &config, &output).unwrap();
+
+        let mut reader = XtcReader::open(&output).unwrap();
+        assert_eq!(reader.page_count(), 2);
+        assert_eq!(reader.page(0).unwrap().data(), &[0xFF, 0xFF]);
+        assert_eq!(reader.page(1).unwrap().data(), &[0x00, 0x00]);
+    }
+
+    #[test]
+    fn rejects_directory_without_images() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            from_images(
+                dir.path(),
+                &EncoderConfig::default(),
+                dir.path().join("x.xtc")
+            ),
+            Err(XtcError::NoImages(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_more_levels_than_xth_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        write_png(
+            &dir.path().join("page.png"),
+            8,
+            2,
+            png::ColorType::Grayscale,
+            &[128; 16],
+        );
+        let output = dir.path().join("x.xtc");
+        let config = EncoderConfig {
+            levels: Levels::Table(encoder::LevelTable::uniform(16)),
+            ..EncoderConfig::default()
+        };
+        assert!(matches!(
+            from_images(dir.path(), &config, &output),
+            Err(XtcError::TooManyLevels(16))
+        ));
+        assert!(!output.exists());
+
+        let config = EncoderConfig {
+            levels: Levels::Table(encoder::LevelTable::uniform(3)),
+            ..EncoderConfig::default()
+        };
+        from_images(dir.path(), &config, &output).unwrap();
+    }
+
+    #[test]
+    fn transparency_composites_onto_white() {
+        assert_eq!(over_white(0, 0), 255);
+        assert_eq!(over_white(0, 255), 0);
+        assert_eq!(luma(&[255, 255, 255]), 255);
+    }
+}
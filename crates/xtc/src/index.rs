@@ -0,0 +1,42 @@
+//! Page index: one fixed-size entry per page, so any page can be located
+//! without scanning the ones before it.
+
+/// Size of one page index entry.
+pub const INDEX_ENTRY_LEN: usize = 16;
+
+/// Where one page lives in the container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageEntry {
+    /// Absolute file offset of the page's XTG/XTH header.
+    pub offset: u64,
+    /// Header plus payload size in bytes.
+    pub size: u32,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl PageEntry {
+    pub(crate) fn to_bytes(self) -> [u8; INDEX_ENTRY_LEN] {
+        let mut b = [0u8; INDEX_ENTRY_LEN];
+        b[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        b[8..12].copy_from_slice(&self.size.to_le_bytes());
+        b[12..14].copy_from_slice(&self.width.to_le_bytes());
+        b[14..16].copy_from_slice(&self.height.to_le_bytes());
+        b
+    }
+
+    pub(crate) fn parse(b: &[u8; INDEX_ENTRY_LEN]) -> Self {
+        Self {
+            offset: u64::from_le_bytes(b[0..8].try_into().unwrap()),
+            size: u32::from_le_bytes(b[8..12].try_into().unwrap()),
+            width: u16::from_le_bytes([b[12], b[13]]),
+            height: u16::from_le_bytes([b[14], b[15]]),
+        }
+    }
+
+    /// One past the last byte of the page. Saturates so a corrupt offset
+    /// still compares as past the end of the file.
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(u64::from(self.size))
+    }
+}
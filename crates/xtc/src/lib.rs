@@ -0,0 +1,89 @@
+//! XTC containers and XTG/XTH pages for the Xteink readers.
+//!
+//! Layouts follow the XTC/XTG/XTH/XTCH format specification
+//! (<https://gist.github.com/CrazyCoder/b125f26d6987c0620058249f59f1327d>).
+//! All multi-byte values are little-endian. Nothing here guesses at fields
+//! the spec does not define.
+//!
+//! One container holds one page set: the header has a single page count
+//! and index, so font-size variants of a book are separate files.
+
+mod chapter;
+pub mod fuzz_targets;
+mod header;
+#[cfg(feature = "images")]
+mod images;
+mod index;
+mod metadata;
+mod page;
+mod reader;
+mod split;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+mod version;
+mod writer;
+
+use std::io;
+
+use thiserror::Error;
+
+pub use chapter::{Chapter, CHAPTER_ENTRY_LEN};
+pub use header::{ReadDirection, HEADER_LEN};
+#[cfg(feature = "images")]
+pub use images::from_images;
+pub use index::{PageEntry, INDEX_ENTRY_LEN};
+pub use metadata::{XtcMetadata, METADATA_LEN};
+pub use page::{Page, PageFormat, PAGE_HEADER_LEN};
+pub use reader::XtcReader;
+pub use split::{SplitLimit, SplitWriter};
+pub use version::XtcVersion;
+pub use writer::XtcWriter;
+
+#[derive(Debug, Error)]
+pub enum XtcError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid page dimensions {width}x{height}")]
+    InvalidDimensions { width: u32, height: u32 },
+    #[error("page buffer is {actual} bytes, expected {expected}")]
+    BufferSize { expected: usize, actual: usize },
+    #[error("a container holds either XTG or XTH pages, not both")]
+    MixedPageFormats,
+    #[error("a container holds at most {max} pages", max = u16::MAX)]
+    TooManyPages,
+    #[error("page {index} is out of range for a {count}-page container")]
+    PageOutOfRange { index: usize, count: usize },
+    #[error("chapter pages {start}..={end} are out of order or past the last page")]
+    InvalidChapter { start: u16, end: u16 },
+    #[error("page checksum does not match its data")]
+    ChecksumMismatch,
+    #[error("page {index} is corrupt: {source}")]
+    CorruptPage {
+        index: usize,
+        #[source]
+        source: Box<XtcError>,
+    },
+    #[error("png encoding failed: {0}")]
+    Png(#[from] png::EncodingError),
+    #[cfg(feature = "images")]
+    #[error(transparent)]
+    Encode(#[from] encoder::EncodeError),
+    #[cfg(feature = "images")]
+    #[error("cannot read page image {}: {reason}", path.display())]
+    Image {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+    #[cfg(feature = "images")]
+    #[error("no PNG or JPEG pages in {}", .0.display())]
+    NoImages(std::path::PathBuf),
+    #[cfg(feature = "images")]
+    #[error("XTH pages hold at most 4 gray levels, not {0}")]
+    TooManyLevels(u8),
+    #[error("invalid split: {0}")]
+    InvalidSplit(&'static str),
+    #[error("container version {0:#06x} is not supported")]
+    UnsupportedVersion(u16),
+    #[error("malformed container: {0}")]
+    Malformed(&'static str),
+}
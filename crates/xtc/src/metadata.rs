@@ -0,0 +1,130 @@
+//! The 256-byte metadata block.
+
+/// Size of the metadata block.
+pub const METADATA_LEN: usize = 256;
+
+// Fields: (offset, length).
+const TITLE: (usize, usize) = (0, 128);
+/// Longest title kept, in bytes, leaving room for the terminator.
+pub(crate) const TITLE_MAX_LEN: usize = TITLE.1 - 1;
+const AUTHOR: (usize, usize) = (128, 64);
+const PUBLISHER: (usize, usize) = (192, 32);
+const LANGUAGE: (usize, usize) = (224, 16);
+const CREATE_TIME: usize = 240;
+const COVER_PAGE: usize = 244;
+const CHAPTER_COUNT: usize = 246;
+
+/// Book details stored in the metadata block.
+///
+/// These are exactly the fields the spec defines. Strings are UTF-8 and are
+/// truncated on a character boundary to one byte less than their field
+/// (title 128, author 64, publisher 32, language 16) so they stay
+/// NUL-terminated. The page count lives in the container header and the
+/// chapter count is filled in from the chapter table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XtcMetadata {
+    pub title: String,
+    pub author: String,
+    pub publisher: String,
+    /// BCP 47 tag such as `en` or `pt-BR`.
+    pub language: String,
+    /// Creation time in Unix seconds; 0 when unknown.
+    pub created: u32,
+    /// Index of the page shown as the cover.
+    pub cover_page: u16,
+}
+
+impl XtcMetadata {
+    pub(crate) fn to_bytes(&self, chapter_count: u16) -> [u8; METADATA_LEN] {
+        let mut block = [0u8; METADATA_LEN];
+        write_str(field_mut(&mut block, TITLE), &self.title);
+        write_str(field_mut(&mut block, AUTHOR), &self.author);
+        write_str(field_mut(&mut block, PUBLISHER), &self.publisher);
+        write_str(field_mut(&mut block, LANGUAGE), &self.language);
+        block[CREATE_TIME..CREATE_TIME + 4].copy_from_slice(&self.created.to_le_bytes());
+        block[COVER_PAGE..COVER_PAGE + 2].copy_from_slice(&self.cover_page.to_le_bytes());
+        block[CHAPTER_COUNT..CHAPTER_COUNT + 2].copy_from_slice(&chapter_count.to_le_bytes());
+        block
+    }
+
+    /// The chapter count stored alongside the metadata.
+    pub(crate) fn chapter_count(block: &[u8; METADATA_LEN]) -> u16 {
+        u16::from_le_bytes([block[CHAPTER_COUNT], block[CHAPTER_COUNT + 1]])
+    }
+
+    /// Parses a metadata block. Invalid UTF-8 is replaced rather than
+    /// rejected, since the device tolerates it too.
+    pub(crate) fn from_bytes(block: &[u8; METADATA_LEN]) -> Self {
+        Self {
+            title: read_str(field(block, TITLE)),
+            author: read_str(field(block, AUTHOR)),
+            publisher: read_str(field(block, PUBLISHER)),
+            language: read_str(field(block, LANGUAGE)),
+            created: u32::from_le_bytes(block[CREATE_TIME..CREATE_TIME + 4].try_into().unwrap()),
+            cover_page: u16::from_le_bytes(block[COVER_PAGE..COVER_PAGE + 2].try_into().unwrap()),
+        }
+    }
+}
+
+fn field(block: &[u8], (offset, len): (usize, usize)) -> &[u8] {
+    &block[offset..offset + len]
+}
+
+fn field_mut(block: &mut [u8], (offset, len): (usize, usize)) -> &mut [u8] {
+    &mut block[offset..offset + len]
+}
+
+pub(crate) fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Copies `s` into a NUL-padded field, leaving room for the terminator.
+pub(crate) fn write_str(field: &mut [u8], s: &str) {
+    let mut end = s.len().min(field.len() - 1);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    field[..end].copy_from_slice(&s.as_bytes()[..end]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_block_layout_round_trips() {
+        let metadata = XtcMetadata {
+            title: "T".repeat(200),
+            author: "Herman Melville".into(),
+            publisher: "Harper".into(),
+            language: "en".into(),
+            created: 1_700_000_000,
+            cover_page: 2,
+        };
+        let block = metadata.to_bytes(7);
+        assert_eq!(&block[128..143], b"Herman Melville");
+        assert_eq!(&block[192..198], b"Harper");
+        assert_eq!(&block[224..226], b"en");
+        assert_eq!(&block[240..244], &1_700_000_000u32.to_le_bytes());
+        assert_eq!(&block[244..248], &[2, 0, 7, 0]);
+        assert!(block[248..].iter().all(|&b| b == 0));
+
+        let back = XtcMetadata::from_bytes(&block);
+        assert_eq!(back.title.len(), 127);
+        assert_eq!(
+            back,
+            XtcMetadata {
+                title: "T".repeat(127),
+                ..metadata
+            }
+        );
+    }
+
+    #[test]
+    fn truncates_long_titles_on_char_boundary() {
+        let mut field = [0u8; 8];
+        write_str(&mut field, "ab\u{e9}\u{e9}\u{e9}");
+        assert_eq!(&field, b"ab\xc3\xa9\xc3\xa9\0\0");
+    }
+}
@@ -0,0 +1,372 @@
+//! XTG (1-bit) and XTH (2-bit) page payloads.
+//!
+//! Both share a 22-byte header: mark (u32), width (u16), height (u16),
+//! color mode (u8, 0), compression (u8, 0 = raw), data size (u32) and the
+//! first 8 bytes of the MD5 of the data.
+
+use std::io::{self, Write};
+
+use md5::{Digest, Md5};
+
+use crate::XtcError;
+
+/// Size of the XTG/XTH page header.
+pub const PAGE_HEADER_LEN: usize = 22;
+
+const XTG_MARK: [u8; 4] = *b"XTG\0";
+const XTH_MARK: [u8; 4] = *b"XTH\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFormat {
+    /// 1 bit per pixel, rows top to bottom, MSB first, 1 = white.
+    Xtg,
+    /// 2 bits per pixel in two bit planes, stored column-major.
+    Xth,
+}
+
+impl PageFormat {
+    fn mark(self) -> [u8; 4] {
+        match self {
+            PageFormat::Xtg => XTG_MARK,
+            PageFormat::Xth => XTH_MARK,
+        }
+    }
+
+    /// Payload size of a `width` x `height` page.
+    pub fn data_len(self, width: u16, height: u16) -> usize {
+        let (width, height) = (usize::from(width), usize::from(height));
+        match self {
+            PageFormat::Xtg => width.div_ceil(8) * height,
+            PageFormat::Xth => height.div_ceil(8) * width * 2,
+        }
+    }
+}
+
+/// One encoded page, ready to be written into a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    format: PageFormat,
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+}
+
+impl Page {
+    /// Wraps already-packed XTG bits (`ceil(width / 8)` bytes per row).
+    pub fn xtg(width: u32, height: u32, bits: Vec<u8>) -> Result<Self, XtcError> {
+        let (w, h) = dimensions(width, height)?;
+        check_len(PageFormat::Xtg.data_len(w, h), bits.len())?;
+        Ok(Self {
+            format: PageFormat::Xtg,
+            width: w,
+            height: h,
+            data: bits,
+        })
+    }
+
+    /// Packs a grayscale8 frame as XTG; values of 128 and up are white.
+    pub fn xtg_from_gray(width: u32, height: u32, gray: &[u8]) -> Result<Self, XtcError> {
+        dimensions(width, height)?;
+        check_len(width as usize * height as usize, gray.len())?;
+        let stride = (width as usize).div_ceil(8);
+        let mut bits = vec![0u8; stride * height as usize];
+        for (src, dst) in gray
+            .chunks_exact(width as usize)
+            .zip(bits.chunks_exact_mut(stride))
+        {
+            for (x, &px) in src.iter().enumerate() {
+                if px >= 128 {
+                    dst[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        Self::xtg(width, height, bits)
+    }
+
+    /// Packs a grayscale8 frame as XTH, snapping each pixel to the nearest
+    /// of black, dark gray, light gray and white (0, 85, 170, 255).
+    ///
+    /// The device LUT is non-linear: value 0 is white, 1 dark gray, 2 light
+    /// gray and 3 black. Bit 1 of every pixel goes to the first plane and
+    /// bit 0 to the second. Each plane is scanned column by column from the
+    /// rightmost column, 8 vertical pixels per byte with the topmost in the
+    /// MSB.
+    pub fn xth_from_gray(width: u32, height: u32, gray: &[u8]) -> Result<Self, XtcError> {
+        let (w, h) = dimensions(width, height)?;
+        check_len(width as usize * height as usize, gray.len())?;
+        let (width, height) = (width as usize, height as usize);
+        let column = height.div_ceil(8);
+        let plane = column * width;
+        let mut data = vec![0u8; plane * 2];
+        for (y, row) in gray.chunks_exact(width).enumerate() {
+            for (x, &px) in row.iter().enumerate() {
+                let value = xth_value(px);
+                let byte = (width - 1 - x) * column + y / 8;
+                let bit = 0x80 >> (y % 8);
+                if value & 0b10 != 0 {
+                    data[byte] |= bit;
+                }
+                if value & 0b01 != 0 {
+                    data[plane + byte] |= bit;
+                }
+            }
+        }
+        Ok(Self {
+            format: PageFormat::Xth,
+            width: w,
+            height: h,
+            data,
+        })
+    }
+
+    /// Expands the page to grayscale8, one byte per pixel in row order.
+    ///
+    /// XTG pixels become 0 or 255; XTH pixels become 0, 85, 170 or 255.
+    pub fn to_gray(&self) -> Vec<u8> {
+        let (width, height) = (usize::from(self.width), usize::from(self.height));
+        let mut gray = Vec::with_capacity(width * height);
+        match self.format {
+            PageFormat::Xtg => {
+                let stride = width.div_ceil(8);
+                for row in self.data.chunks_exact(stride) {
+                    gray.extend((0..width).map(|x| {
+                        if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                            255
+                        } else {
+                            0
+                        }
+                    }));
+                }
+            }
+            PageFormat::Xth => {
+                let column = height.div_ceil(8);
+                let (high, low) = self.data.split_at(column * width);
+                for y in 0..height {
+                    gray.extend((0..width).map(|x| {
+                        let byte = (width - 1 - x) * column + y / 8;
+                        let bit = 0x80 >> (y % 8);
+                        let value =
+                            u8::from(high[byte] & bit != 0) << 1 | u8::from(low[byte] & bit != 0);
+                        XTH_GRAY[usize::from(value)]
+                    }));
+                }
+            }
+        }
+        gray
+    }
+
+    /// Parses one page as stored in a container: header then payload.
+    pub fn parse(bytes: &[u8]) -> Result<Self, XtcError> {
+        let Some((header, data)) = bytes.split_first_chunk::<PAGE_HEADER_LEN>() else {
+            return Err(XtcError::Malformed("page shorter than its header"));
+        };
+        let format = match [header[0], header[1], header[2], header[3]] {
+            XTG_MARK => PageFormat::Xtg,
+            XTH_MARK => PageFormat::Xth,
+            _ => return Err(XtcError::Malformed("unknown page mark")),
+        };
+        let width = u16::from_le_bytes([header[4], header[5]]);
+        let height = u16::from_le_bytes([header[6], header[7]]);
+        if header[8] != 0 || header[9] != 0 {
+            return Err(XtcError::Malformed(
+                "unsupported page color mode or compression",
+            ));
+        }
+        let data_size = u32::from_le_bytes(header[10..14].try_into().unwrap()) as usize;
+        check_len(data_size, data.len())?;
+        dimensions(u32::from(width), u32::from(height))?;
+        check_len(format.data_len(width, height), data.len())?;
+        Ok(Self {
+            format,
+            width,
+            height,
+            data: data.to_vec(),
+        })
+    }
+
+    /// [`Page::parse`], then checks the payload against the header's MD5
+    /// prefix.
+    pub fn parse_verified(bytes: &[u8]) -> Result<Self, XtcError> {
+        let page = Self::parse(bytes)?;
+        if bytes[14..PAGE_HEADER_LEN] != page.checksum() {
+            return Err(XtcError::ChecksumMismatch);
+        }
+        Ok(page)
+    }
+
+    /// First 8 bytes of the MD5 of the payload, as stored in the header.
+    pub fn checksum(&self) -> [u8; 8] {
+        let digest = Md5::digest(&self.data);
+        digest[..8].try_into().unwrap()
+    }
+
+    pub fn format(&self) -> PageFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// The raw bitmap payload, without the header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Header plus payload size in bytes.
+    pub fn encoded_len(&self) -> usize {
+        PAGE_HEADER_LEN + self.data.len()
+    }
+
+    /// Writes the header and payload.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut header = [0u8; PAGE_HEADER_LEN];
+        header[0..4].copy_from_slice(&self.format.mark());
+        header[4..6].copy_from_slice(&self.width.to_le_bytes());
+        header[6..8].copy_from_slice(&self.height.to_le_bytes());
+        // header[8] color mode and header[9] compression stay 0.
+        header[10..14].copy_from_slice(&(self.data.len() as u32).to_le_bytes());
+        header[14..22].copy_from_slice(&self.checksum());
+        out.write_all(&header)?;
+        out.write_all(&self.data)
+    }
+}
+
+/// Grayscale8 shade for each XTH device value.
+const XTH_GRAY: [u8; 4] = [255, 85, 170, 0];
+
+/// Device value for a grayscale8 pixel (see [`Page::xth_from_gray`]).
+fn xth_value(px: u8) -> u8 {
+    match px {
+        0..=42 => 3,
+        43..=127 => 1,
+        128..=212 => 2,
+        213..=255 => 0,
+    }
+}
+
+fn dimensions(width: u32, height: u32) -> Result<(u16, u16), XtcError> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(XtcError::InvalidDimensions { width, height }),
+    }
+}
+
+fn check_len(expected: usize, actual: usize) -> Result<(), XtcError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(XtcError::BufferSize { expected, actual })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xtg_packs_rows_msb_first_with_white_set() {
+        let gray = [
+            0, 255, 255, 0, 0, 0, 0, 0, 255, 0, 255, 255, 255, 255, 255, 255, 255, 255,
+        ];
+        let page = Page::xtg_from_gray(9, 2, &gray).unwrap();
+        assert_eq!(
+            page.data(),
+            &[0b0110_0000, 0b1000_0000, 0b0111_1111, 0b1000_0000]
+        );
+    }
+
+    #[test]
+    fn xtg_header_layout() {
+        let page = Page::xtg(16, 1, vec![0xAA, 0x55]).unwrap();
+        let mut out = Vec::new();
+        page.write_to(&mut out).unwrap();
+        assert_eq!(out.len(), page.encoded_len());
+        assert_eq!(&out[0..4], b"XTG\0");
+        assert_eq!(&out[4..8], &[16, 0, 1, 0]);
+        assert_eq!(&out[8..10], &[0, 0]);
+        assert_eq!(&out[10..14], &2u32.to_le_bytes());
+        assert_eq!(&out[14..22], &Md5::digest([0xAA, 0x55])[..8]);
+        assert_eq!(&out[22..], &[0xAA, 0x55]);
+    }
+
+    #[test]
+    fn xth_stores_columns_right_to_left() {
+        // 2x2: top row black, white; bottom row dark gray, light gray.
+        let page = Page::xth_from_gray(2, 2, &[0, 255, 85, 170]).unwrap();
+        // One byte per column per plane; rightmost column first.
+        // Values: black 3, white 0, dark 1, light 2.
+        // Right column (white, light) -> plane1 bits 0,1; plane0 bits 0,0.
+        // Left column (black, dark)   -> plane1 bits 1,0; plane0 bits 1,1.
+        assert_eq!(
+            page.data(),
+            &[0b0100_0000, 0b1000_0000, 0b0000_0000, 0b1100_0000]
+        );
+    }
+
+    #[test]
+    fn parse_reverses_write() {
+        let gray: Vec<u8> = (0..5 * 11).map(|i| (i * 37 % 256) as u8).collect();
+        for page in [
+            Page::xtg_from_gray(5, 11, &gray).unwrap(),
+            Page::xth_from_gray(5, 11, &gray).unwrap(),
+        ] {
+            let mut bytes = Vec::new();
+            page.write_to(&mut bytes).unwrap();
+            assert_eq!(Page::parse(&bytes).unwrap(), page);
+            assert!(matches!(
+                Page::parse(&bytes[..bytes.len() - 1]),
+                Err(XtcError::BufferSize { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn to_gray_reverses_packing() {
+        let gray: Vec<u8> = (0..7 * 10).map(|i| [0, 85, 170, 255][i % 4]).collect();
+        let xth = Page::xth_from_gray(7, 10, &gray).unwrap();
+        assert_eq!(xth.to_gray(), gray);
+        let mono: Vec<u8> = gray
+            .iter()
+            .map(|&v| if v >= 128 { 255 } else { 0 })
+            .collect();
+        assert_eq!(Page::xtg_from_gray(7, 10, &gray).unwrap().to_gray(), mono);
+    }
+
+    #[test]
+    fn parse_verified_detects_flipped_bits() {
+        let page = Page::xtg_from_gray(16, 2, &[0; 32]).unwrap();
+        let mut bytes = Vec::new();
+        page.write_to(&mut bytes).unwrap();
+        assert_eq!(Page::parse_verified(&bytes).unwrap(), page);
+        bytes[PAGE_HEADER_LEN + 1] ^= 0x10;
+        assert!(Page::parse(&bytes).is_ok());
+        assert!(matches!(
+            Page::parse_verified(&bytes),
+            Err(XtcError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(
+            Page::xtg(0, 4, vec![]),
+            Err(XtcError::InvalidDimensions { .. })
+        ));
+        assert!(matches!(
+            Page::xth_from_gray(70_000, 1, &[]),
+            Err(XtcError::InvalidDimensions { .. })
+        ));
+        assert!(matches!(
+            Page::xtg(9, 1, vec![0]),
+            Err(XtcError::BufferSize {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,415 @@
+//! Reading XTC containers.
+//!
+//! Opening a file reads the header, metadata, chapters and page index; pages
+//! are then read one at a time by seeking straight to their index entry.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::header::Header;
+use crate::{
+    Chapter, Page, PageEntry, PageFormat, ReadDirection, XtcError, XtcMetadata, XtcVersion,
+    CHAPTER_ENTRY_LEN, HEADER_LEN, INDEX_ENTRY_LEN, METADATA_LEN,
+};
+
+/// Random-access reader for an XTC file.
+#[derive(Debug)]
+pub struct XtcReader<R = BufReader<File>> {
+    inner: R,
+    header: Header,
+    metadata: Option<XtcMetadata>,
+    index: Vec<PageEntry>,
+    chapters: Vec<Chapter>,
+}
+
+impl XtcReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, XtcError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> XtcReader<R> {
+    /// Parses the header, the metadata block when present, and the page
+    /// index. Index entries pointing past the end of the file are rejected
+    /// here so later page reads cannot run off the end.
+    pub fn new(mut inner: R) -> Result<Self, XtcError> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        let mut bytes = [0u8; HEADER_LEN];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut bytes)?;
+        let header = Header::parse(&bytes)?;
+
+        // The chapter count lives in the metadata block; without metadata
+        // there is no way to size the chapter table.
+        let (metadata, chapter_count) = if header.has_metadata {
+            let mut block = [0u8; METADATA_LEN];
+            inner.seek(SeekFrom::Start(header.metadata_offset))?;
+            inner.read_exact(&mut block)?;
+            let chapter_count = if header.has_chapters {
+                XtcMetadata::chapter_count(&block)
+            } else {
+                0
+            };
+            (Some(XtcMetadata::from_bytes(&block)), chapter_count)
+        } else {
+            (None, 0)
+        };
+
+        let mut raw = vec![0u8; usize::from(header.page_count) * INDEX_ENTRY_LEN];
+        inner.seek(SeekFrom::Start(header.index_offset))?;
+        inner.read_exact(&mut raw)?;
+        let index: Vec<PageEntry> = raw
+            .chunks_exact(INDEX_ENTRY_LEN)
+            .map(|b| PageEntry::parse(b.try_into().unwrap()))
+            .collect();
+        if index.iter().any(|entry| entry.end() > file_len) {
+            return Err(XtcError::Malformed("page index points past end of file"));
+        }
+
+        let mut raw = vec![0u8; usize::from(chapter_count) * CHAPTER_ENTRY_LEN];
+        inner.seek(SeekFrom::Start(header.chapter_offset))?;
+        inner.read_exact(&mut raw)?;
+        let chapters = raw
+            .chunks_exact(CHAPTER_ENTRY_LEN)
+            .map(|b| Chapter::parse(b.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            inner,
+            header,
+            metadata,
+            index,
+            chapters,
+        })
+    }
+
+    pub fn format(&self) -> PageFormat {
+        self.header.format
+    }
+
+    /// The format version the file is read as; see [`XtcVersion::from_raw`].
+    pub fn version(&self) -> XtcVersion {
+        self.header.version
+    }
+
+    pub fn page_count(&self) -> u16 {
+        self.header.page_count
+    }
+
+    /// The last-read page (0-based) as recorded by the device.
+    pub fn current_page(&self) -> u32 {
+        self.header.current_page
+    }
+
+    pub fn read_direction(&self) -> ReadDirection {
+        self.header.read_direction
+    }
+
+    /// The metadata block, if the file has one.
+    pub fn metadata(&self) -> Option<&XtcMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Reads the embedded cover thumbnail, if the file has one.
+    ///
+    /// Hypothesis (`thumbnails` feature): see [`crate::XtcWriter::thumbnail`].
+    #[cfg(feature = "thumbnails")]
+    pub fn thumbnail(&mut self) -> Result<Option<Page>, XtcError> {
+        if !self.header.has_thumbnails {
+            return Ok(None);
+        }
+        let mut header = [0u8; crate::PAGE_HEADER_LEN];
+        self.inner.seek(SeekFrom::Start(self.header.thumb_offset))?;
+        self.inner.read_exact(&mut header)?;
+        let data_size = u32::from_le_bytes(header[10..14].try_into().unwrap());
+        let mut bytes = header.to_vec();
+        // Read through `take` so a corrupt size cannot force a huge
+        // allocation; a short read then fails the length check in `parse`.
+        (&mut self.inner)
+            .take(data_size.into())
+            .read_to_end(&mut bytes)?;
+        Page::parse(&bytes).map(Some)
+    }
+
+    /// The table of contents; empty when the file has none.
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// Index entries for every page, in reading order.
+    pub fn index(&self) -> &[PageEntry] {
+        &self.index
+    }
+
+    /// Reads and parses page `index` (0-based), touching only its bytes.
+    pub fn page(&mut self, index: usize) -> Result<Page, XtcError> {
+        let bytes = self.page_bytes(index)?;
+        Page::parse(&bytes)
+    }
+
+    /// Writes page `index` as an 8-bit grayscale PNG showing the shades the
+    /// device would display, for comparing against what a reader renders.
+    pub fn export_page_png(
+        &mut self,
+        index: usize,
+        path: impl AsRef<Path>,
+    ) -> Result<(), XtcError> {
+        let page = self.page(index)?;
+        let out = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(out, page.width().into(), page.height().into());
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&page.to_gray())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// The raw stored bytes (header and payload) of page `index`.
+    pub fn page_bytes(&mut self, index: usize) -> Result<Vec<u8>, XtcError> {
+        let entry = *self.index.get(index).ok_or(XtcError::PageOutOfRange {
+            index,
+            count: self.index.len(),
+        })?;
+        let mut bytes = vec![0u8; entry.size as usize];
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Checks the whole file: every page parses, matches its index entry
+    /// and its MD5 prefix, pages lie inside the data region without
+    /// overlapping, and chapters point at real pages.
+    ///
+    /// The spec has no checksum over the header or the container as a
+    /// whole, so truncation and corruption outside page payloads are caught
+    /// only through these structural checks.
+    pub fn verify(&mut self) -> Result<(), XtcError> {
+        let mut next = self.header.data_offset;
+        for index in 0..self.index.len() {
+            let entry = self.index[index];
+            let corrupt = |source| XtcError::CorruptPage {
+                index,
+                source: Box::new(source),
+            };
+            if entry.offset < next {
+                return Err(corrupt(XtcError::Malformed(
+                    "page overlaps the previous block",
+                )));
+            }
+            next = entry.end();
+            let page = Page::parse_verified(&self.page_bytes(index)?).map_err(corrupt)?;
+            if (page.width(), page.height()) != (entry.width, entry.height) {
+                return Err(corrupt(XtcError::Malformed(
+                    "page size differs from its index entry",
+                )));
+            }
+            if page.format() != self.header.format {
+                return Err(corrupt(XtcError::MixedPageFormats));
+            }
+        }
+        for chapter in &self.chapters {
+            chapter.validate(self.index.len())?;
+        }
+        #[cfg(feature = "thumbnails")]
+        self.thumbnail()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Page, XtcWriter, PAGE_HEADER_LEN};
+
+    #[test]
+    fn reads_back_written_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let metadata = XtcMetadata {
+            title: "Persuasion".into(),
+            author: "Jane Austen".into(),
+            language: "en".into(),
+            created: 1_234_567,
+            ..XtcMetadata::default()
+        };
+        XtcWriter::create(&path)
+            .unwrap()
+            .metadata(metadata.clone())
+            .add_page(&Page::xtg_from_gray(8, 2, &[255; 16]).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        let reader = XtcReader::open(&path).unwrap();
+        assert_eq!(reader.format(), PageFormat::Xtg);
+        assert_eq!(reader.page_count(), 1);
+        assert_eq!(reader.read_direction(), ReadDirection::LeftToRight);
+        assert_eq!(reader.metadata(), Some(&metadata));
+    }
+
+    #[test]
+    fn reads_any_page_directly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let pages: Vec<Page> = (0..5u8)
+            .map(|i| Page::xtg_from_gray(8, 1, &[i * 50; 8]).unwrap())
+            .collect();
+        let mut writer = XtcWriter::create(&path).unwrap();
+        for page in &pages {
+            writer = writer.add_page(page).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = XtcReader::open(&path).unwrap();
+        assert_eq!(reader.index().len(), 5);
+        for i in [4, 0, 3] {
+            assert_eq!(reader.page(i).unwrap(), pages[i]);
+        }
+        assert!(matches!(
+            reader.page(5),
+            Err(XtcError::PageOutOfRange { index: 5, count: 5 })
+        ));
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn round_trips_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let cover = Page::xtg_from_gray(16, 4, &[0; 64]).unwrap();
+        let page = Page::xtg_from_gray(8, 1, &[255; 8]).unwrap();
+        XtcWriter::create(&path)
+            .unwrap()
+            .thumbnail(cover.clone())
+            .add_page(&page)
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        let mut reader = XtcReader::open(&path).unwrap();
+        assert_eq!(reader.thumbnail().unwrap(), Some(cover));
+        assert_eq!(reader.page(0).unwrap(), page);
+    }
+
+    #[test]
+    fn round_trips_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let chapters = vec![
+            Chapter {
+                title: "One".into(),
+                start_page: 0,
+                end_page: 1,
+            },
+            Chapter {
+                title: "Two".into(),
+                start_page: 2,
+                end_page: 2,
+            },
+        ];
+        let mut writer = XtcWriter::create(&path).unwrap().chapters(chapters.clone());
+        for _ in 0..3 {
+            writer = writer
+                .add_page(&Page::xtg_from_gray(8, 1, &[0; 8]).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = XtcReader::open(&path).unwrap();
+        assert_eq!(reader.chapters(), &chapters[..]);
+        assert_eq!(reader.page(2).unwrap().width(), 8);
+    }
+
+    #[test]
+    fn rejects_chapter_past_last_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = XtcWriter::create(dir.path().join("book.xtc"))
+            .unwrap()
+            .chapters(vec![Chapter {
+                title: "Two".into(),
+                start_page: 1,
+                end_page: 1,
+            }])
+            .add_page(&Page::xtg_from_gray(8, 1, &[0; 8]).unwrap())
+            .unwrap()
+            .finish();
+        assert!(matches!(
+            result,
+            Err(XtcError::InvalidChapter { start: 1, end: 1 })
+        ));
+    }
+
+    #[test]
+    fn verify_accepts_written_file_and_flags_corrupt_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let mut writer = XtcWriter::create(&path).unwrap();
+        for shade in [0u8, 120, 255] {
+            writer = writer
+                .add_page(&Page::xth_from_gray(8, 8, &[shade; 64]).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        XtcReader::open(&path).unwrap().verify().unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let entry = XtcReader::open(&path).unwrap().index()[1];
+        bytes[entry.offset as usize + PAGE_HEADER_LEN] ^= 0xFF;
+        let mut reader = XtcReader::new(std::io::Cursor::new(bytes)).unwrap();
+        match reader.verify() {
+            Err(XtcError::CorruptPage { index: 1, source }) => {
+                assert!(matches!(*source, XtcError::ChecksumMismatch));
+            }
+            other => panic!("expected corrupt page 1, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn exports_page_as_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtch");
+        let gray: Vec<u8> = (0..6 * 9).map(|i| [0, 85, 170, 255][i % 4]).collect();
+        XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&Page::xth_from_gray(6, 9, &gray).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let png_path = dir.path().join("page.png");
+        XtcReader::open(&path)
+            .unwrap()
+            .export_page_png(0, &png_path)
+            .unwrap();
+
+        let decoder = png::Decoder::new(File::open(&png_path).unwrap());
+        let mut png = decoder.read_info().unwrap();
+        let mut pixels = vec![0; png.output_buffer_size()];
+        let info = png.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (6, 9));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(pixels, gray);
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&Page::xtg_from_gray(8, 4, &[0; 32]).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let truncated = std::io::Cursor::new(bytes[..bytes.len() - 1].to_vec());
+        assert!(matches!(
+            XtcReader::new(truncated),
+            Err(XtcError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,325 @@
+//! Splitting one long book across several containers.
+//!
+//! The spec has no field linking containers, so each part is a complete,
+//! standalone XTC. Parts are named `book.part1.xtc`, `book.part2.xtc`, ...
+//! and titled `Title (Part 1 of 3)`, which is what the device's library
+//! shows. A book that fits in one part keeps its plain name and title.
+
+use std::path::{Path, PathBuf};
+
+use crate::metadata::TITLE_MAX_LEN;
+use crate::{
+    Chapter, Page, ReadDirection, XtcError, XtcMetadata, XtcWriter, CHAPTER_ENTRY_LEN, HEADER_LEN,
+    INDEX_ENTRY_LEN, METADATA_LEN,
+};
+
+/// When to start a new part. A part is closed before the page that would
+/// push it past either limit; a single page larger than `max_bytes` still
+/// gets a part of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitLimit {
+    pub max_pages: u16,
+    /// Upper bound on the size of each part file.
+    pub max_bytes: u64,
+}
+
+impl Default for SplitLimit {
+    fn default() -> Self {
+        Self {
+            max_pages: u16::MAX,
+            max_bytes: u64::MAX,
+        }
+    }
+}
+
+/// An [`XtcWriter`] that rolls over to a new part file at a [`SplitLimit`].
+///
+/// Chapters and the cover page use page numbers of the whole book; each
+/// part gets the chapters overlapping it, clipped and renumbered.
+#[derive(Debug)]
+pub struct SplitWriter {
+    path: PathBuf,
+    limit: SplitLimit,
+    metadata: XtcMetadata,
+    chapters: Vec<Chapter>,
+    read_direction: ReadDirection,
+    /// Unfinished parts; they are finished together once the part count is
+    /// known for the titles.
+    parts: Vec<XtcWriter>,
+}
+
+impl SplitWriter {
+    /// Parts are written next to `path`.
+    pub fn create(path: impl AsRef<Path>, limit: SplitLimit) -> Result<Self, XtcError> {
+        if limit.max_pages == 0 || limit.max_bytes == 0 {
+            return Err(XtcError::InvalidSplit("limits must be non-zero"));
+        }
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            limit,
+            metadata: XtcMetadata::default(),
+            chapters: Vec::new(),
+            read_direction: ReadDirection::default(),
+            parts: Vec::new(),
+        })
+    }
+
+    pub fn metadata(mut self, metadata: XtcMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the table of contents for the whole book.
+    pub fn chapters(mut self, chapters: Vec<Chapter>) -> Self {
+        self.chapters = chapters;
+        self
+    }
+
+    pub fn read_direction(mut self, direction: ReadDirection) -> Self {
+        self.read_direction = direction;
+        self
+    }
+
+    pub fn add_page(mut self, page: &Page) -> Result<Self, XtcError> {
+        let fits = self.parts.last().is_some_and(|part| {
+            part.page_count() < usize::from(self.limit.max_pages)
+                && self.part_len(part) + (INDEX_ENTRY_LEN + page.encoded_len()) as u64
+                    <= self.limit.max_bytes
+        });
+        if !fits {
+            let name = part_path(&self.path, self.parts.len() + 1);
+            self.parts.push(XtcWriter::create(name)?);
+        }
+        let part = self.parts.pop().expect("a part was just ensured");
+        self.parts.push(part.add_page(page)?);
+        Ok(self)
+    }
+
+    /// Upper bound on the finished size of `part`, counting every chapter
+    /// since it is not yet known which ones it will get.
+    fn part_len(&self, part: &XtcWriter) -> u64 {
+        (HEADER_LEN + METADATA_LEN + self.chapters.len() * CHAPTER_ENTRY_LEN) as u64
+            + (part.page_count() * INDEX_ENTRY_LEN) as u64
+            + part.data_len()
+    }
+
+    /// Finishes every part and returns their paths in reading order.
+    pub fn finish(self) -> Result<Vec<PathBuf>, XtcError> {
+        let count = self.parts.len();
+        let mut first = 0usize;
+        let mut paths = Vec::with_capacity(count);
+        for (i, part) in self.parts.into_iter().enumerate() {
+            let pages = first..first + part.page_count();
+            let chapters = self
+                .chapters
+                .iter()
+                .filter(|c| {
+                    usize::from(c.start_page) < pages.end && usize::from(c.end_page) >= first
+                })
+                .map(|c| Chapter {
+                    title: c.title.clone(),
+                    start_page: (usize::from(c.start_page).max(first) - first) as u16,
+                    end_page: (usize::from(c.end_page).min(pages.end - 1) - first) as u16,
+                })
+                .collect();
+            let cover = usize::from(self.metadata.cover_page);
+            let metadata = XtcMetadata {
+                title: if count > 1 {
+                    part_title(&self.metadata.title, i + 1, count)
+                } else {
+                    self.metadata.title.clone()
+                },
+                cover_page: if pages.contains(&cover) {
+                    (cover - first) as u16
+                } else {
+                    0
+                },
+                ..self.metadata.clone()
+            };
+            first = pages.end;
+            paths.push(
+                part.metadata(metadata)
+                    .chapters(chapters)
+                    .read_direction(self.read_direction)
+                    .finish()?,
+            );
+        }
+        if let [only] = &mut paths[..] {
+            std::fs::rename(&*only, &self.path)?;
+            *only = self.path;
+        }
+        Ok(paths)
+    }
+}
+
+/// `dir/book.xtc` -> `dir/book.part{n}.xtc`.
+fn part_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.part{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.part{n}"),
+    };
+    path.with_file_name(name)
+}
+
+/// `title` marked as part `part` of `count`. The title is shortened
+/// rather than the suffix, so parts of a long book stay distinguishable.
+fn part_title(title: &str, part: usize, count: usize) -> String {
+    let suffix = format!(" (Part {part} of {count})");
+    let mut end = title.len().min(TITLE_MAX_LEN.saturating_sub(suffix.len()));
+    while !title.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{suffix}", title[..end].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XtcReader;
+
+    fn page() -> Page {
+        Page::xtg_from_gray(8, 1, &[0; 8]).unwrap()
+    }
+
+    #[test]
+    fn splits_by_page_count_and_renumbers_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = SplitWriter::create(
+            dir.path().join("omnibus.xtc"),
+            SplitLimit {
+                max_pages: 2,
+                ..SplitLimit::default()
+            },
+        )
+        .unwrap()
+        .metadata(XtcMetadata {
+            title: "Omnibus".into(),
+            ..XtcMetadata::default()
+        })
+        .chapters(vec![
+            Chapter {
+                title: "One".into(),
+                start_page: 0,
+                end_page: 2,
+            },
+            Chapter {
+                title: "Two".into(),
+                start_page: 3,
+                end_page: 4,
+            },
+        ]);
+        for _ in 0..5 {
+            writer = writer.add_page(&page()).unwrap();
+        }
+        let paths = writer.finish().unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "omnibus.part1.xtc",
+                "omnibus.part2.xtc",
+                "omnibus.part3.xtc"
+            ]
+        );
+        assert!(!dir.path().join("omnibus.xtc").exists());
+
+        let second = XtcReader::open(&paths[1]).unwrap();
+        assert_eq!(second.page_count(), 2);
+        assert_eq!(second.metadata().unwrap().title, "Omnibus (Part 2 of 3)");
+        let ranges: Vec<_> = second
+            .chapters()
+            .iter()
+            .map(|c| (c.title.as_str(), c.start_page, c.end_page))
+            .collect();
+        assert_eq!(ranges, [("One", 0, 0), ("Two", 1, 1)]);
+    }
+
+    #[test]
+    fn long_titles_keep_their_part_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let title = format!("{}a", "\u{e9}".repeat(63));
+        assert_eq!(title.len(), 127);
+        let mut writer = SplitWriter::create(
+            dir.path().join("long.xtc"),
+            SplitLimit {
+                max_pages: 1,
+                ..SplitLimit::default()
+            },
+        )
+        .unwrap()
+        .metadata(XtcMetadata {
+            title: title.clone(),
+            ..XtcMetadata::default()
+        });
+        for _ in 0..2 {
+            writer = writer.add_page(&page()).unwrap();
+        }
+        let titles: Vec<_> = writer
+            .finish()
+            .unwrap()
+            .iter()
+            .map(|path| {
+                XtcReader::open(path)
+                    .unwrap()
+                    .metadata()
+                    .unwrap()
+                    .title
+                    .clone()
+            })
+            .collect();
+        for (i, stored) in titles.iter().enumerate() {
+            assert!(stored.len() <= TITLE_MAX_LEN);
+            assert!(
+                stored.ends_with(&format!(" (Part {} of 2)", i + 1)),
+                "{stored}"
+            );
+            assert!(title.starts_with(stored.split(" (Part").next().unwrap()));
+        }
+    }
+
+    #[test]
+    fn splits_by_size_without_exceeding_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let per_page = (INDEX_ENTRY_LEN + page().encoded_len()) as u64;
+        let max_bytes = (HEADER_LEN + METADATA_LEN) as u64 + 3 * per_page;
+        let mut writer = SplitWriter::create(
+            dir.path().join("book.xtc"),
+            SplitLimit {
+                max_bytes,
+                ..SplitLimit::default()
+            },
+        )
+        .unwrap();
+        for _ in 0..7 {
+            writer = writer.add_page(&page()).unwrap();
+        }
+        let paths = writer.finish().unwrap();
+        assert_eq!(paths.len(), 3);
+        for path in &paths {
+            assert!(std::fs::metadata(path).unwrap().len() <= max_bytes);
+        }
+    }
+
+    #[test]
+    fn single_part_keeps_the_title() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = SplitWriter::create(dir.path().join("book.xtc"), SplitLimit::default())
+            .unwrap()
+            .metadata(XtcMetadata {
+                title: "Short".into(),
+                ..XtcMetadata::default()
+            })
+            .add_page(&page())
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(paths, [dir.path().join("book.xtc")]);
+        assert!(!dir.path().join("book.part1.xtc").exists());
+        let reader = XtcReader::open(&paths[0]).unwrap();
+        assert_eq!(reader.metadata().unwrap().title, "Short");
+    }
+}
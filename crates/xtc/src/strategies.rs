@@ -0,0 +1,186 @@
+//! proptest strategies for arbitrary valid books (`proptest` feature).
+//!
+//! ```no_run
+//! use proptest::prelude::*;
+//! use xtc::strategies::{book, ArbitraryBook};
+//!
+//! proptest! {
+//!     #[test]
+//!     fn survives_a_round_trip(book in book()) {
+//!         let dir = tempfile::tempdir().unwrap();
+//!         let path = book.write(dir.path().join("book.xtc")).unwrap();
+//!         prop_assert_eq!(ArbitraryBook::read(&path).unwrap(), book);
+//!     }
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use proptest::prelude::*;
+
+use crate::{
+    Chapter, Page, PageFormat, ReadDirection, XtcError, XtcMetadata, XtcReader, XtcWriter,
+};
+
+/// Everything a container stores, in a form that compares whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArbitraryBook {
+    pub metadata: XtcMetadata,
+    pub read_direction: ReadDirection,
+    pub current_page: u32,
+    pub chapters: Vec<Chapter>,
+    pub pages: Vec<Page>,
+}
+
+impl ArbitraryBook {
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<PathBuf, XtcError> {
+        let mut writer = XtcWriter::create(path)?
+            .metadata(self.metadata.clone())
+            .read_direction(self.read_direction)
+            .current_page(self.current_page)
+            .chapters(self.chapters.clone());
+        for page in &self.pages {
+            writer = writer.add_page(page)?;
+        }
+        writer.finish()
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, XtcError> {
+        let mut reader = XtcReader::open(path)?;
+        let pages = (0..reader.index().len())
+            .map(|i| reader.page(i))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            metadata: reader.metadata().cloned().unwrap_or_default(),
+            read_direction: reader.read_direction(),
+            current_page: reader.current_page(),
+            chapters: reader.chapters().to_vec(),
+            pages,
+        })
+    }
+}
+
+pub fn page_format() -> impl Strategy<Value = PageFormat> {
+    prop_oneof![Just(PageFormat::Xtg), Just(PageFormat::Xth)]
+}
+
+/// A page of up to 64x64 pixels in `format`.
+pub fn page(format: PageFormat) -> impl Strategy<Value = Page> {
+    (1..=64u32, 1..=64u32).prop_flat_map(move |(width, height)| {
+        let shade = prop_oneof![Just(0u8), Just(85), Just(170), Just(255)];
+        prop::collection::vec(shade, (width * height) as usize).prop_map(move |gray| {
+            match format {
+                PageFormat::Xtg => Page::xtg_from_gray(width, height, &gray),
+                PageFormat::Xth => Page::xth_from_gray(width, height, &gray),
+            }
+            .expect("dimensions and buffer are in range")
+        })
+    })
+}
+
+/// Metadata whose strings fit their fields, so it survives a round trip.
+pub fn metadata() -> impl Strategy<Value = XtcMetadata> {
+    (
+        "\\PC{0,31}",
+        "\\PC{0,15}",
+        "[ -~]{0,31}",
+        "[a-zA-Z-]{0,15}",
+        any::<u32>(),
+        any::<u16>(),
+    )
+        .prop_map(
+            |(title, author, publisher, language, created, cover_page)| XtcMetadata {
+                title,
+                author,
+                publisher,
+                language,
+                created,
+                cover_page,
+            },
+        )
+}
+
+pub fn read_direction() -> impl Strategy<Value = ReadDirection> {
+    prop_oneof![
+        Just(ReadDirection::LeftToRight),
+        Just(ReadDirection::RightToLeft),
+        Just(ReadDirection::TopToBottom),
+    ]
+}
+
+/// Up to 8 chapters with valid ranges for a `page_count`-page book.
+pub fn chapters(page_count: usize) -> impl Strategy<Value = Vec<Chapter>> {
+    let last = page_count.saturating_sub(1) as u16;
+    let chapter = ("\\PC{0,19}", 0..=last, 0..=last).prop_map(|(title, a, b)| Chapter {
+        title,
+        start_page: a.min(b),
+        end_page: a.max(b),
+    });
+    let max = if page_count == 0 { 0 } else { 8 };
+    prop::collection::vec(chapter, 0..=max)
+}
+
+/// A book of up to 8 pages of one format, with metadata, chapters and a
+/// reading position on one of its pages.
+pub fn book() -> impl Strategy<Value = ArbitraryBook> {
+    (page_format(), 0..=8usize)
+        .prop_flat_map(|(format, count)| {
+            (
+                metadata(),
+                read_direction(),
+                0..count.max(1) as u32,
+                chapters(count),
+                prop::collection::vec(page(format), count),
+            )
+        })
+        .prop_map(
+            |(metadata, read_direction, current_page, chapters, pages)| ArbitraryBook {
+                metadata,
+                read_direction,
+                current_page,
+                chapters,
+                pages,
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz_targets;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn books_round_trip(book in book()) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = book.write(dir.path().join("book.xtc")).unwrap();
+            prop_assert_eq!(ArbitraryBook::read(&path).unwrap(), book);
+            XtcReader::open(&path).unwrap().verify().unwrap();
+        }
+
+        #[test]
+        fn corrupted_books_never_panic(
+            book in book(),
+            flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = book.write(dir.path().join("book.xtc")).unwrap();
+            let mut bytes = std::fs::read(&path).unwrap();
+            for (at, value) in flips {
+                let at = at.index(bytes.len());
+                bytes[at] ^= value;
+            }
+            fuzz_targets::parse(&bytes);
+            fuzz_targets::parse(&bytes[..cut.index(bytes.len())]);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            fuzz_targets::parse(&bytes);
+            fuzz_targets::parse_page(&bytes);
+        }
+    }
+}
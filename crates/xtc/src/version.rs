@@ -0,0 +1,73 @@
+//! Container format versions and the compatibility policy.
+//!
+//! The header stores the version as `major << 8 | minor`. Policy:
+//!
+//! - A minor bump only gives meaning to bytes that older versions write as
+//!   zero, so an older reader can still open the file and ignore them.
+//! - A major bump may change the layout; files with an unknown major version
+//!   are rejected instead of misread.
+//! - The writer only produces versions listed in [`XtcVersion`], and keeps
+//!   writing every listed version for as long as it is listed.
+
+use crate::XtcError;
+
+/// A container version this crate can read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[non_exhaustive]
+pub enum XtcVersion {
+    /// Version 1.0 (`0x0100`), the only version published so far.
+    #[default]
+    V1_0,
+}
+
+impl XtcVersion {
+    /// The newest version, which the writer targets by default.
+    pub const LATEST: XtcVersion = XtcVersion::V1_0;
+
+    /// The raw header value.
+    pub fn raw(self) -> u16 {
+        match self {
+            XtcVersion::V1_0 => 0x0100,
+        }
+    }
+
+    /// Maps a raw header value to the version to read it as.
+    ///
+    /// A newer minor version of a known major reads as the newest known
+    /// version of that major; an unknown major is an error.
+    pub fn from_raw(raw: u16) -> Result<Self, XtcError> {
+        match raw >> 8 {
+            1 => Ok(XtcVersion::V1_0),
+            _ => Err(XtcError::UnsupportedVersion(raw)),
+        }
+    }
+
+    pub fn major(self) -> u8 {
+        (self.raw() >> 8) as u8
+    }
+
+    pub fn minor(self) -> u8 {
+        self.raw() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_known_major_and_rejects_others() {
+        assert_eq!(XtcVersion::from_raw(0x0100).unwrap(), XtcVersion::V1_0);
+        assert_eq!(XtcVersion::from_raw(0x0103).unwrap(), XtcVersion::V1_0);
+        for raw in [0x0000, 0x0200, 0xFF01] {
+            assert!(matches!(
+                XtcVersion::from_raw(raw),
+                Err(XtcError::UnsupportedVersion(r)) if r == raw
+            ));
+        }
+        assert_eq!(
+            (XtcVersion::LATEST.major(), XtcVersion::LATEST.minor()),
+            (1, 0)
+        );
+    }
+}
@@ -0,0 +1,552 @@
+//! Streaming XTC container writer.
+//!
+//! Layout: 56-byte header, 256-byte metadata block, chapter table (96 bytes
+//! per chapter, if any), page index (16 bytes per page), then the XTG/XTH
+//! pages back to back. The index precedes the page
+//! data but its size is only known once every page is in, so pages are
+//! spilled to a temporary file next to the output and copied in by
+//! [`XtcWriter::finish`]. At most one page is held in memory at a time;
+//! beyond that the writer keeps only its 16-byte index entry per page, so a
+//! 1,500-page book costs about 24 KiB regardless of page size.
+//!
+//! The output itself is also written beside its destination and renamed
+//! over it once complete, so an existing book at that path survives a
+//! writer that fails or is dropped. [`XtcWriter::open_for_append`] relies
+//! on this to update a file in place: because the index sits in front of
+//! the data, growing a book means moving its pages.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::header::{Header, CURRENT_PAGE_OFFSET};
+use crate::{
+    Chapter, Page, PageEntry, PageFormat, ReadDirection, XtcError, XtcMetadata, XtcReader,
+    XtcVersion, CHAPTER_ENTRY_LEN, HEADER_LEN, INDEX_ENTRY_LEN, METADATA_LEN,
+};
+
+/// Writes an XTC file page by page.
+///
+/// ```no_run
+/// # fn demo(pages: &[xtc::Page]) -> Result<(), xtc::XtcError> {
+/// let mut writer = xtc::XtcWriter::create("book.xtc")?.metadata(xtc::XtcMetadata {
+///     title: "Book".into(),
+///     ..Default::default()
+/// });
+/// for page in pages {
+///     writer = writer.add_page(page)?;
+/// }
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct XtcWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+    /// Where `out` writes; renamed over `path` by `finish`.
+    staged: TempPath,
+    spill: BufWriter<File>,
+    metadata: XtcMetadata,
+    chapters: Vec<Chapter>,
+    read_direction: ReadDirection,
+    version: XtcVersion,
+    current_page: u32,
+    format: Option<PageFormat>,
+    /// Offsets are relative to the page data region until `finish`.
+    entries: Vec<PageEntry>,
+    data_len: u64,
+    #[cfg(feature = "thumbnails")]
+    thumbnail: Option<Page>,
+}
+
+impl XtcWriter {
+    /// Writes a new file at `path`. An existing file there is replaced by
+    /// [`XtcWriter::finish`] and left as it was until then.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, XtcError> {
+        Self::with_output(path.as_ref())
+    }
+
+    /// Opens an existing file to add, drop or re-encode pages and to update
+    /// its metadata and chapters.
+    ///
+    /// Stored pages are copied over byte for byte, never re-encoded. The
+    /// original stays untouched until [`XtcWriter::finish`] replaces it; a
+    /// writer dropped before then leaves it as it was.
+    pub fn open_for_append(path: impl AsRef<Path>) -> Result<Self, XtcError> {
+        let path = path.as_ref();
+        let mut reader = XtcReader::open(path)?;
+        let mut writer = Self::with_output(path)?
+            .metadata(reader.metadata().cloned().unwrap_or_default())
+            .chapters(reader.chapters().to_vec())
+            .read_direction(reader.read_direction())
+            .version(reader.version())
+            .current_page(reader.current_page());
+        if reader.page_count() > 0 {
+            writer.format = Some(reader.format());
+        }
+        #[cfg(feature = "thumbnails")]
+        {
+            writer.thumbnail = reader.thumbnail()?;
+        }
+        for index in 0..reader.index().len() {
+            let entry = reader.index()[index];
+            let bytes = reader.page_bytes(index)?;
+            writer.spill.write_all(&bytes)?;
+            writer.entries.push(PageEntry {
+                offset: writer.data_len,
+                ..entry
+            });
+            writer.data_len += u64::from(entry.size);
+        }
+        Ok(writer)
+    }
+
+    fn with_output(path: &Path) -> Result<Self, XtcError> {
+        let (out, staged) = NamedTempFile::new_in(spill_dir(path))?.into_parts();
+        let spill = tempfile::tempfile_in(spill_dir(path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            out: BufWriter::new(out),
+            staged,
+            spill: BufWriter::new(spill),
+            metadata: XtcMetadata::default(),
+            chapters: Vec::new(),
+            read_direction: ReadDirection::default(),
+            version: XtcVersion::LATEST,
+            current_page: 0,
+            format: None,
+            entries: Vec::new(),
+            data_len: 0,
+            #[cfg(feature = "thumbnails")]
+            thumbnail: None,
+        })
+    }
+
+    pub fn metadata(mut self, metadata: XtcMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the table of contents. Page ranges are checked against the
+    /// final page count in [`XtcWriter::finish`].
+    pub fn chapters(mut self, chapters: Vec<Chapter>) -> Self {
+        self.chapters = chapters;
+        self
+    }
+
+    pub fn read_direction(mut self, direction: ReadDirection) -> Self {
+        self.read_direction = direction;
+        self
+    }
+
+    /// Targets an older format version; defaults to [`XtcVersion::LATEST`].
+    pub fn version(mut self, version: XtcVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the last-read page (0-based) the device resumes from.
+    /// [`XtcWriter::finish`] pulls it back to the last page if the book
+    /// ends up shorter.
+    pub fn current_page(mut self, page: u32) -> Self {
+        self.current_page = page;
+        self
+    }
+
+    /// Updates the last-read page of an existing file in place, touching
+    /// only the header field, e.g. to push reading progress to a device.
+    pub fn set_current_page(path: impl AsRef<Path>, page: u32) -> Result<(), XtcError> {
+        let mut file = File::options().read(true).write(true).open(path)?;
+        let reader = XtcReader::new(&mut file)?;
+        if page >= u32::from(reader.page_count()) {
+            return Err(XtcError::PageOutOfRange {
+                index: page as usize,
+                count: reader.page_count().into(),
+            });
+        }
+        let file = reader.into_inner();
+        file.seek(SeekFrom::Start(CURRENT_PAGE_OFFSET))?;
+        file.write_all(&page.to_le_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Embeds a cover thumbnail for the device's library view, e.g. the
+    /// encoder's output for the cover image at thumbnail size.
+    ///
+    /// Hypothesis (`thumbnails` feature): the thumbnail is stored as one
+    /// XTG/XTH page record at `thumbOffset`, after the page data.
+    #[cfg(feature = "thumbnails")]
+    pub fn thumbnail(mut self, thumbnail: Page) -> Self {
+        self.thumbnail = Some(thumbnail);
+        self
+    }
+
+    /// Appends a page. All pages of a container must share one format.
+    pub fn add_page(mut self, page: &Page) -> Result<Self, XtcError> {
+        if *self.format.get_or_insert(page.format()) != page.format() {
+            return Err(XtcError::MixedPageFormats);
+        }
+        if self.entries.len() >= usize::from(u16::MAX) {
+            return Err(XtcError::TooManyPages);
+        }
+        page.write_to(&mut self.spill)?;
+        let size = page.encoded_len() as u64;
+        self.entries.push(PageEntry {
+            offset: self.data_len,
+            size: size as u32,
+            width: page.width(),
+            height: page.height(),
+        });
+        self.data_len += size;
+        Ok(self)
+    }
+
+    /// Drops every page from `page_count` on, e.g. to re-encode the tail of
+    /// a book opened with [`XtcWriter::open_for_append`]. Chapters are not
+    /// adjusted; update them with [`XtcWriter::chapters`] if needed.
+    pub fn truncate(mut self, page_count: usize) -> Result<Self, XtcError> {
+        let Some(first_dropped) = self.entries.get(page_count) else {
+            return Ok(self);
+        };
+        self.data_len = first_dropped.offset;
+        self.entries.truncate(page_count);
+        self.spill.flush()?;
+        let spill = self.spill.get_mut();
+        spill.set_len(self.data_len)?;
+        spill.seek(SeekFrom::Start(self.data_len))?;
+        Ok(self)
+    }
+
+    /// Number of pages added so far.
+    pub fn page_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Bytes of page data added so far.
+    pub(crate) fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Writes the header, metadata and index, copies the page data in and
+    /// flushes. Returns the path written.
+    pub fn finish(mut self) -> Result<PathBuf, XtcError> {
+        for chapter in &self.chapters {
+            chapter.validate(self.entries.len())?;
+        }
+        let chapter_count = u16::try_from(self.chapters.len())
+            .map_err(|_| XtcError::Malformed("too many chapters"))?;
+        let has_chapters = chapter_count > 0;
+
+        let metadata_offset = HEADER_LEN as u64;
+        let chapter_offset = metadata_offset + METADATA_LEN as u64;
+        let index_offset = chapter_offset + (self.chapters.len() * CHAPTER_ENTRY_LEN) as u64;
+        let data_offset = index_offset + (self.entries.len() * INDEX_ENTRY_LEN) as u64;
+        #[cfg(feature = "thumbnails")]
+        let (has_thumbnails, thumb_offset) = match self.thumbnail {
+            Some(_) => (true, data_offset + self.data_len),
+            None => (false, 0),
+        };
+        #[cfg(not(feature = "thumbnails"))]
+        let (has_thumbnails, thumb_offset) = (false, 0);
+
+        let header = Header {
+            format: self.format.unwrap_or(PageFormat::Xtg),
+            version: self.version,
+            page_count: self.entries.len() as u16,
+            read_direction: self.read_direction,
+            has_metadata: true,
+            has_thumbnails,
+            has_chapters,
+            current_page: self
+                .current_page
+                .min((self.entries.len() as u32).saturating_sub(1)),
+            metadata_offset,
+            index_offset,
+            data_offset,
+            thumb_offset,
+            chapter_offset: if has_chapters { chapter_offset } else { 0 },
+        };
+
+        self.out.write_all(&header.to_bytes())?;
+        self.out.write_all(&self.metadata.to_bytes(chapter_count))?;
+        for chapter in &self.chapters {
+            self.out.write_all(&chapter.to_bytes())?;
+        }
+        for entry in &self.entries {
+            let entry = PageEntry {
+                offset: data_offset + entry.offset,
+                ..*entry
+            };
+            self.out.write_all(&entry.to_bytes())?;
+        }
+
+        let mut spill = self.spill.into_inner().map_err(|e| e.into_error())?;
+        spill.seek(SeekFrom::Start(0))?;
+        io::copy(&mut spill, &mut self.out)?;
+        #[cfg(feature = "thumbnails")]
+        if let Some(thumbnail) = &self.thumbnail {
+            thumbnail.write_to(&mut self.out)?;
+        }
+        self.out
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        self.staged.persist(&self.path).map_err(|e| e.error)?;
+        Ok(self.path)
+    }
+}
+
+/// Temporary files go next to the output so the final rename stays on one
+/// filesystem.
+fn spill_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(b: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_header_metadata_index_and_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let pages = [
+            Page::xtg_from_gray(16, 2, &[255; 32]).unwrap(),
+            Page::xtg_from_gray(8, 3, &[0; 24]).unwrap(),
+        ];
+        let mut writer = XtcWriter::create(&path).unwrap().metadata(XtcMetadata {
+            title: "Moby Dick".into(),
+            ..XtcMetadata::default()
+        });
+        for page in &pages {
+            writer = writer.add_page(page).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[0..4], b"XTC\0");
+        assert_eq!(u16_at(&bytes, 4), 0x0100);
+        assert_eq!(u16_at(&bytes, 6), 2);
+        assert_eq!(&bytes[8..16], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        let (meta, index, data) = (u64_at(&bytes, 16), u64_at(&bytes, 24), u64_at(&bytes, 32));
+        assert_eq!((meta, index, data), (56, 312, 312 + 32));
+        assert_eq!((u64_at(&bytes, 40), u64_at(&bytes, 48)), (0, 0));
+
+        assert_eq!(&bytes[56..65], b"Moby Dick");
+        assert!(bytes[65..312].iter().all(|&b| b == 0));
+
+        let mut expected_offset = data;
+        for (i, page) in pages.iter().enumerate() {
+            let entry = index as usize + i * INDEX_ENTRY_LEN;
+            let offset = u64_at(&bytes, entry);
+            let size = u32_at(&bytes, entry + 8) as usize;
+            assert_eq!(offset, expected_offset);
+            assert_eq!(size, page.encoded_len());
+            assert_eq!(u16_at(&bytes, entry + 12), page.width());
+            assert_eq!(u16_at(&bytes, entry + 14), page.height());
+            let mut encoded = Vec::new();
+            page.write_to(&mut encoded).unwrap();
+            assert_eq!(&bytes[offset as usize..][..size], &encoded[..]);
+            expected_offset += size as u64;
+        }
+        assert_eq!(expected_offset as usize, bytes.len());
+    }
+
+    #[test]
+    fn xth_pages_use_xtch_mark() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtch");
+        XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&Page::xth_from_gray(4, 4, &[170; 16]).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(&std::fs::read(&path).unwrap()[0..4], b"XTCH");
+    }
+
+    #[test]
+    fn long_books_stream_through_the_spill_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("long.xtc");
+        let mut writer = XtcWriter::create(&path).unwrap();
+        for i in 0..1500u16 {
+            let shade = (i % 2) as u8 * 255;
+            writer = writer
+                .add_page(&Page::xtg_from_gray(8, 2, &[shade; 16]).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = crate::XtcReader::open(&path).unwrap();
+        assert_eq!(reader.page_count(), 1500);
+        reader.verify().unwrap();
+        assert_eq!(reader.page(1499).unwrap().data(), &[0xFF; 2]);
+    }
+
+    #[test]
+    fn append_keeps_pages_and_replaces_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let page = |shade| Page::xtg_from_gray(8, 1, &[shade; 8]).unwrap();
+        XtcWriter::create(&path)
+            .unwrap()
+            .metadata(XtcMetadata {
+                title: "Draft".into(),
+                ..XtcMetadata::default()
+            })
+            .current_page(2)
+            .add_page(&page(0))
+            .unwrap()
+            .add_page(&page(0))
+            .unwrap()
+            .add_page(&page(0))
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        let writer = XtcWriter::open_for_append(&path).unwrap();
+        assert_eq!(writer.page_count(), 3);
+        writer
+            .truncate(2)
+            .unwrap()
+            .add_page(&page(255))
+            .unwrap()
+            .add_page(&page(255))
+            .unwrap()
+            .chapters(vec![Chapter {
+                title: "Two".into(),
+                start_page: 2,
+                end_page: 3,
+            }])
+            .finish()
+            .unwrap();
+
+        let mut reader = crate::XtcReader::open(&path).unwrap();
+        reader.verify().unwrap();
+        assert_eq!(reader.metadata().unwrap().title, "Draft");
+        assert_eq!(reader.chapters()[0].title, "Two");
+        let shades: Vec<u8> = (0..4).map(|i| reader.page(i).unwrap().data()[0]).collect();
+        assert_eq!(shades, [0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(reader.current_page(), 2);
+
+        // The reading position can't outlive the pages it pointed at.
+        XtcWriter::open_for_append(&path)
+            .unwrap()
+            .truncate(1)
+            .unwrap()
+            .chapters(Vec::new())
+            .finish()
+            .unwrap();
+        let mut reader = crate::XtcReader::open(&path).unwrap();
+        reader.verify().unwrap();
+        assert_eq!(reader.current_page(), 0);
+    }
+
+    #[test]
+    fn current_page_survives_append_and_updates_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let page = Page::xtg_from_gray(8, 1, &[0; 8]).unwrap();
+        let mut writer = XtcWriter::create(&path).unwrap().current_page(1);
+        for _ in 0..3 {
+            writer = writer.add_page(&page).unwrap();
+        }
+        writer.finish().unwrap();
+        XtcWriter::open_for_append(&path).unwrap().finish().unwrap();
+        assert_eq!(XtcReader::open(&path).unwrap().current_page(), 1);
+
+        let before = std::fs::read(&path).unwrap();
+        XtcWriter::set_current_page(&path, 2).unwrap();
+        let after = std::fs::read(&path).unwrap();
+        assert_eq!(XtcReader::open(&path).unwrap().current_page(), 2);
+        assert_eq!(before[..12], after[..12]);
+        assert_eq!(before[16..], after[16..]);
+        assert!(matches!(
+            XtcWriter::set_current_page(&path, 3),
+            Err(XtcError::PageOutOfRange { index: 3, count: 3 })
+        ));
+    }
+
+    #[test]
+    fn dropped_append_writer_leaves_file_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&Page::xtg_from_gray(8, 1, &[0; 8]).unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let before = std::fs::read(&path).unwrap();
+        drop(
+            XtcWriter::open_for_append(&path)
+                .unwrap()
+                .truncate(0)
+                .unwrap(),
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_create_leaves_previous_file_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xtc");
+        let page = Page::xtg_from_gray(8, 1, &[0; 8]).unwrap();
+        XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&page)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let before = std::fs::read(&path).unwrap();
+
+        drop(XtcWriter::create(&path).unwrap());
+        let failed = XtcWriter::create(&path)
+            .unwrap()
+            .add_page(&page)
+            .unwrap()
+            .chapters(vec![Chapter {
+                title: "Missing".into(),
+                start_page: 4,
+                end_page: 5,
+            }])
+            .finish();
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rejects_mixed_page_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = XtcWriter::create(dir.path().join("mixed.xtc"))
+            .unwrap()
+            .add_page(&Page::xtg_from_gray(8, 1, &[0; 8]).unwrap())
+            .unwrap();
+        assert!(matches!(
+            writer.add_page(&Page::xth_from_gray(8, 1, &[0; 8]).unwrap()),
+            Err(XtcError::MixedPageFormats)
+        ));
+    }
+}